@@ -1,27 +1,85 @@
-use std::{fs, path::Path};
+use std::{
+    env,
+    fs::{self, DirBuilder},
+    os::{
+        fd::{AsFd, OwnedFd},
+        unix::fs::DirBuilderExt,
+    },
+    path::{Path, PathBuf},
+    process::exit,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 use anyhow::{bail, Context, Result};
-use clap::{Parser, Subcommand};
+use clap::{ArgAction, Parser, Subcommand};
+use log::warn;
 use nix::{
     sys::signal::{self, Signal},
-    unistd::Pid,
+    unistd::{self, ForkResult, Pid},
 };
-use oci_spec::runtime::Spec;
+use oci_spec::runtime::{
+    LinuxCpuBuilder, LinuxMemoryBuilder, LinuxPidsBuilder, LinuxResources, LinuxResourcesBuilder,
+    Spec,
+};
+use serde_json::json;
 
 use crate::{
-    container::fork,
+    container::{
+        self, fork,
+        guard::{self, ContainerRootGuard, InterruptGuard},
+        ContainerOptions,
+    },
+    error::ContainerError,
+    events::EventLog,
+    features::Features,
+    health::{self, HealthCheck, HEALTH_CHECK_COMMAND_ANNOTATION},
     hook,
-    socket::{SocketClient, SocketServer},
-    state::{State, Status},
+    linux::{
+        cgroup, hostname, mount, namespace,
+        process::{self, NamespaceProcess},
+        seccomp,
+    },
+    socket::{SocketClient, SocketMessage, SocketServer},
+    state::{State, Status, OCI_VERSION},
+    validation,
 };
 
 const RENO_ROOT: &str = "/tmp/reno";
 
+/// `EXEC_GRACE_PERIOD` is how long `start` waits for the container process to report
+/// an error from `execvp` in the detached mode, such as a nonexistent program.
+const EXEC_GRACE_PERIOD: Duration = Duration::from_millis(500);
+
 #[derive(Parser, Debug)]
-#[clap(version, about)]
+#[clap(about, disable_version_flag = true)]
 pub struct Cli {
+    #[arg(
+        short = 'V',
+        long,
+        help = "print the version, the commit, the build date, and the compiled features"
+    )]
+    pub version: bool,
+
+    #[arg(long, requires = "version", help = "print only the version")]
+    pub short: bool,
+
+    #[arg(
+        long,
+        global = true,
+        help = "manage the container cgroup with transient systemd units, \
+        which is recorded in the state by create and used by the other commands"
+    )]
+    pub systemd_cgroup: bool,
+
+    #[arg(
+        long,
+        global = true,
+        help = "print errors to stderr as JSON objects with the error and its context"
+    )]
+    pub json_errors: bool,
+
     #[command(subcommand)]
-    pub command: CliSubcommand,
+    pub command: Option<CliSubcommand>,
 }
 
 #[derive(Subcommand, Debug)]
@@ -38,13 +96,94 @@ pub enum CliSubcommand {
 
         #[arg(long)]
         pid_file: Option<String>,
+
+        #[arg(
+            long,
+            help = "lower the hard resource limits that exceed the limits of the runtime"
+        )]
+        clamp_rlimits: bool,
+
+        #[arg(
+            long,
+            help = "redirect the stdout and stderr of the container to a named pipe, which is created if it doesn't exist"
+        )]
+        log_fifo: Option<String>,
+
+        #[arg(
+            long,
+            help = "change the root mount with chroot instead of pivot_root, which is less secure"
+        )]
+        no_pivot: bool,
+
+        #[arg(
+            long,
+            default_value_t = 0,
+            help = "pass N additional file descriptors after the stderr to the container process"
+        )]
+        preserve_fds: u32,
+
+        #[arg(
+            long,
+            help = "set the hostname of the container, which overrides the bundle configuration"
+        )]
+        hostname: Option<String>,
+
+        #[arg(
+            long,
+            help = "mount the default file systems, such as /proc and /dev, that the bundle configuration omits, which is implied if it has no mounts"
+        )]
+        add_default_mounts: bool,
+
+        #[arg(
+            long,
+            help = "receive the master of the pseudoterminal of the container on a unix socket, which is required if process.terminal is true"
+        )]
+        console_socket: Option<String>,
+
+        #[arg(
+            long,
+            help = "allow the kernel parameters that are not isolated by the namespaces of the container"
+        )]
+        unsafe_sysctl: bool,
     },
 
     #[command(about = "start a container")]
-    Start { id: String },
+    Start {
+        id: String,
+
+        #[arg(
+            long,
+            default_value_t = true,
+            action = ArgAction::Set,
+            help = "return once the container process is about to execute the user-specified program, \
+            or wait until the program is executed if false"
+        )]
+        detach: bool,
+
+        #[arg(
+            long,
+            help = "monitor the container process and update the state once it exits"
+        )]
+        sync: bool,
+
+        #[arg(
+            long,
+            help = "run the health check defined in the reno.healthcheck.* annotations periodically"
+        )]
+        health_check: bool,
+    },
 
     #[command(about = "kill a container")]
-    Kill { id: String, signal: String },
+    Kill {
+        id: String,
+        signal: String,
+
+        #[arg(
+            long,
+            help = "send the signal to the process group of the container process"
+        )]
+        group: bool,
+    },
 
     #[command(about = "delete a container")]
     Delete {
@@ -52,7 +191,72 @@ pub enum CliSubcommand {
 
         #[arg(long)]
         force: bool,
+
+        #[arg(
+            long,
+            help = "keep the container root at <root>/<id>.deleted-<timestamp> with the state \
+            renamed to state.json.deleted, which should be removed by the user"
+        )]
+        keep_state: bool,
     },
+
+    #[command(about = "update the resource limits of a running container")]
+    Update {
+        id: String,
+
+        #[arg(long, help = "the memory limit in bytes, where -1 is unlimited")]
+        memory: Option<i64>,
+
+        #[arg(
+            long,
+            help = "the CPU time in microseconds that the container could use in each period, where -1 is unlimited"
+        )]
+        cpu_quota: Option<i64>,
+
+        #[arg(long, help = "the relative weight of the CPU time of the container")]
+        cpu_shares: Option<u64>,
+
+        #[arg(long, help = "the maximum number of processes, where -1 is unlimited")]
+        pids_limit: Option<i64>,
+
+        #[arg(
+            long,
+            conflicts_with_all = ["memory", "cpu_quota", "cpu_shares", "pids_limit"],
+            help = "read the resource limits from a JSON file that contains a partial linux.resources object"
+        )]
+        resources: Option<String>,
+    },
+
+    #[command(about = "print the features supported by the runtime")]
+    Features,
+
+    #[command(about = "print the processes in the PID namespace of a container")]
+    Ps {
+        id: String,
+
+        #[arg(long, value_parser = ["table", "json"], help = "the output format, which is table by default")]
+        format: Option<String>,
+    },
+
+    #[command(about = "print the lifecycle events of a container")]
+    Events {
+        id: String,
+
+        #[arg(
+            long,
+            help = "print new events as they arrive until the container is stopped"
+        )]
+        follow: bool,
+    },
+
+    #[command(about = "validate the bundle configuration without creating a container")]
+    Lint {
+        #[arg(long)]
+        bundle: String,
+    },
+
+    #[command(about = "print the version of the runtime")]
+    Version,
 }
 
 pub fn state(id: String) -> Result<()> {
@@ -61,38 +265,68 @@ pub fn state(id: String) -> Result<()> {
     if state.status != Status::Created {
         state.refresh();
     }
+    state.persist(&container_root)?;
 
+    // The health status is kept out of `state.json`, since it's written by the health check process
+    health::annotate_health_status(&container_root, &mut state)?;
     let serialized_state =
         serde_json::to_string(&state).context("failed to serialize the state")?;
     println!("{}", serialized_state);
-
-    state.persist(&container_root)?;
     Ok(())
 }
 
-pub fn create(id: String, bundle: String, pid_file: Option<String>) -> Result<()> {
-    let bundle = Path::new(&bundle);
-    let bundle_exists = bundle
-        .try_exists()
-        .context("failed to check if the bundle exists")?;
-    if !bundle_exists {
-        bail!("the bundle doesn't exist");
-    }
+pub fn create(
+    id: String,
+    bundle: String,
+    pid_file: Option<String>,
+    mut options: ContainerOptions,
+    unsafe_sysctl: bool,
+    systemd_cgroup: bool,
+) -> Result<()> {
+    let bundle = resolve_bundle(&bundle)?;
 
     let bundle_spec = bundle.join("config.json");
+    validation::validate_config_json(&bundle_spec)?;
     let spec = Spec::load(bundle_spec).context("failed to load the bundle configuration")?;
+    hook::run_pre_create_hook(&spec)?;
+    let issue_list = validation::validate_spec(&bundle, &spec, unsafe_sysctl);
+    if !issue_list.is_empty() {
+        bail!(
+            "the bundle at {} is invalid:\n{}",
+            bundle.display(),
+            issue_list.join("\n")
+        );
+    }
+    if let Some(hostname) = &options.hostname {
+        hostname::validate_hostname(hostname)?;
+    }
+    container::validate_console_socket(&spec, options.console_socket.as_deref())?;
 
     let container_root = Path::new(RENO_ROOT).join(&id);
+    let mut container_root_guard = ContainerRootGuard::lock(&container_root, &id)?;
+
     let container_root_exists = container_root
         .try_exists()
         .context("failed to check if the container exists")?;
     if container_root_exists {
-        bail!("the container exists");
+        // The container root of the existing container must not be removed by the guard
+        container_root_guard.commit();
+        return Err(ContainerError::AlreadyExists(id).into());
     }
 
-    fs::create_dir_all(&container_root).context("failed to create the container root path")?;
+    // The container root is only accessible to the owner, since the sockets in it
+    // control the creation of the container
+    DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(&container_root)
+        .context("failed to create the container root path")?;
+
+    // Interrupting the `reno` CLI from now on kills the container process and removes the container root
+    let interrupt_guard = InterruptGuard::install(&container_root)?;
 
-    let mut state = State::new(id, bundle.to_path_buf());
+    let mut state = State::new(id, bundle);
+    state.systemd_cgroup = systemd_cgroup;
     state.persist(&container_root)?;
 
     let namespaces = match &spec.linux() {
@@ -100,6 +334,16 @@ pub fn create(id: String, bundle: String, pid_file: Option<String>) -> Result<()
         None => Vec::new(),
     };
 
+    options.log_fifo = options
+        .log_fifo
+        .map(|log_fifo| env::current_dir().unwrap_or_default().join(log_fifo));
+    if let Some(log_fifo) = &options.log_fifo {
+        process::create_fifo(log_fifo)?;
+    }
+    options.console_socket = options
+        .console_socket
+        .map(|console_socket| env::current_dir().unwrap_or_default().join(console_socket));
+
     let init_socket_path = container_root.join("init.sock");
     let mut init_socket_server = SocketServer::bind(&init_socket_path)?;
 
@@ -110,15 +354,47 @@ pub fn create(id: String, bundle: String, pid_file: Option<String>) -> Result<()
         &namespaces,
         &init_socket_path,
         &container_socket_path,
+        &options,
     )?;
+    interrupt_guard.set_child(pid);
+    container_root_guard.commit();
+
+    // The container stays in the cgroup of the runtime if the bundle configuration
+    // doesn't ask for a container cgroup
+    if cgroup::cgroup_requested(&spec) {
+        let resources = spec
+            .linux()
+            .as_ref()
+            .and_then(|linux| linux.resources().as_ref());
+        cgroup::cgroup_manager(&spec, &state.id, systemd_cgroup)?.apply(resources, pid)?;
+    }
 
     init_socket_server.listen()?;
+    if namespace::new_user_namespace(&namespaces) {
+        if let Some(linux) = spec.linux() {
+            namespace::write_id_mappings(
+                pid,
+                linux.uid_mappings().as_deref().unwrap_or_default(),
+                linux.gid_mappings().as_deref().unwrap_or_default(),
+            )?;
+        }
+    }
+    init_socket_server.write(SocketMessage::new(Status::Creating, None))?;
+    init_socket_server.wait_for_close()?;
 
     let mut container_socket_client = SocketClient::connect(&container_socket_path)?;
     let container_message = container_socket_client.read()?;
     container_socket_client.shutdown()?;
 
     if container_message.status == Status::Creating {
+        if let Some(time_offsets) = spec
+            .linux()
+            .as_ref()
+            .and_then(|linux| linux.time_offsets().as_ref())
+        {
+            namespace::write_time_offsets(pid, &namespaces, time_offsets)?;
+        }
+
         if let Some(hooks) = spec.hooks() {
             if let Some(create_runtime_hooks) = hooks.create_runtime() {
                 for create_runtime_hook in create_runtime_hooks {
@@ -152,13 +428,16 @@ pub fn create(id: String, bundle: String, pid_file: Option<String>) -> Result<()
     }
 }
 
-pub fn start(id: String) -> Result<()> {
+/// `start` starts the created container. The container process reports the `Running` status
+/// right before it executes the user-specified program. `start` keeps the connection to the
+/// container process until the connection is closed by `execvp`, which means the program is
+/// executed, or until the container process reports an error, which fails `start`.
+/// If `detach` is `true`, it only waits for [EXEC_GRACE_PERIOD], after which the program is
+/// assumed to be executed. The `poststart` hooks are invoked after that.
+pub fn start(id: String, detach: bool, sync: bool, health_check: bool) -> Result<()> {
     let container_root = Path::new(RENO_ROOT).join(id);
-    container_root
-        .try_exists()
-        .context("the container doesn't exist")?;
-
     let mut state = State::load(&container_root)?;
+    state.check_owner()?;
     if state.status != Status::Created {
         bail!("the container is not in the 'Created' state");
     }
@@ -177,13 +456,28 @@ pub fn start(id: String) -> Result<()> {
 
     let container_socket_path = container_root.join("container.sock");
     let mut container_socket_client = SocketClient::connect(&container_socket_path)?;
-    let container_message = container_socket_client.read()?;
-    container_socket_client.shutdown()?;
+    let (container_message, seccomp_fd) = wait_for_exec(&mut container_socket_client, detach)?;
 
     if container_message.status == Status::Running {
         state.refresh();
         state.persist(&container_root)?;
 
+        if let Some(seccomp_fd) = seccomp_fd {
+            let seccomp = spec
+                .linux()
+                .as_ref()
+                .and_then(|linux| linux.seccomp().as_ref());
+            match seccomp.and_then(|seccomp| seccomp.listener_path().as_ref()) {
+                Some(listener_path) => seccomp::send_seccomp_listener(
+                    listener_path,
+                    seccomp.and_then(|seccomp| seccomp.listener_metadata().as_deref()),
+                    seccomp_fd.as_fd(),
+                    &state,
+                )?,
+                None => bail!("linux.seccomp.listenerPath is not defined"),
+            }
+        }
+
         if let Some(hooks) = spec.hooks() {
             if let Some(post_start_hooks) = hooks.poststart() {
                 for post_start_hook in post_start_hooks {
@@ -192,6 +486,25 @@ pub fn start(id: String) -> Result<()> {
                 }
             }
         }
+
+        if sync {
+            monitor_container(&container_root, Pid::from_raw(state.pid))?;
+        }
+        if health_check {
+            let annotations = spec.annotations().clone().unwrap_or_default();
+            match HealthCheck::from_annotations(&annotations)? {
+                Some(health_check) => health::monitor_health(
+                    &container_root,
+                    &spec,
+                    health_check,
+                    Pid::from_raw(state.pid),
+                )?,
+                None => bail!(
+                    "the {} annotation is not defined",
+                    HEALTH_CHECK_COMMAND_ANNOTATION
+                ),
+            }
+        }
         Ok(())
     } else if let Some(error) = container_message.error {
         bail!("failed to start the container: {}", error);
@@ -200,13 +513,71 @@ pub fn start(id: String) -> Result<()> {
     }
 }
 
-pub fn kill(id: String, signal: String) -> Result<()> {
-    let container_root = Path::new(RENO_ROOT).join(id);
-    container_root
-        .try_exists()
-        .context("the container doesn't exist")?;
+/// `wait_for_exec` reads the status of the container process from `container_socket_client`
+/// along with the file descriptor of the seccomp notification listener. If the status is
+/// `Running`, it waits for the connection to be closed without a message, which means `execvp`
+/// succeeds, since the socket is closed on `execvp`, or for the error that the container process
+/// sends if it fails. If `detach` is `true`, it only waits for [EXEC_GRACE_PERIOD].
+fn wait_for_exec(
+    container_socket_client: &mut SocketClient,
+    detach: bool,
+) -> Result<(SocketMessage, Option<OwnedFd>)> {
+    let (mut container_message, seccomp_fd) = container_socket_client.read_with_fd()?;
+    if container_message.status == Status::Running {
+        let timeout = detach.then_some(EXEC_GRACE_PERIOD);
+        if let Some(message) = container_socket_client.read_until_close(timeout)? {
+            container_message = message;
+        }
+    }
+    container_socket_client.shutdown()?;
+    Ok((container_message, seccomp_fd))
+}
 
+/// `monitor_container` forks a monitor process that waits the container process `pid` to exit,
+/// and then marks the container as stopped and removes the socket files in `container_root`.
+/// The monitor process is detached with [process::daemonize], so that the `reno` CLI could return
+/// while the container is running.
+fn monitor_container(container_root: &Path, pid: Pid) -> Result<()> {
+    match unsafe { unistd::fork() }.context("failed to fork the monitor process")? {
+        ForkResult::Parent { .. } => Ok(()),
+        ForkResult::Child => {
+            let result = process::daemonize()
+                .and_then(|_| process::wait_process_exit(pid))
+                .and_then(|_| {
+                    let mut state = State::load(container_root)?;
+                    state.status = Status::Stopped;
+                    state.persist(container_root)?;
+
+                    remove_socket_files(container_root)
+                });
+            exit(if result.is_ok() { 0 } else { 1 });
+        }
+    }
+}
+
+/// `resolve_bundle` resolves `bundle` to an absolute path, since the relative path would be
+/// resolved against a different working directory when the container is started or deleted.
+fn resolve_bundle(bundle: &str) -> Result<PathBuf> {
+    fs::canonicalize(bundle).context(format!("failed to resolve the bundle {}", bundle))
+}
+
+/// `remove_socket_files` removes the socket files that the runtime uses to communicate
+/// with the container process in `container_root`.
+fn remove_socket_files(container_root: &Path) -> Result<()> {
+    for socket in ["init.sock", "container.sock"] {
+        let socket_path = container_root.join(socket);
+        if socket_path.exists() {
+            fs::remove_file(&socket_path)
+                .context(format!("failed to remove {}", socket_path.display()))?;
+        }
+    }
+    Ok(())
+}
+
+pub fn kill(id: String, signal: String, group: bool) -> Result<()> {
+    let container_root = Path::new(RENO_ROOT).join(id);
     let mut state = State::load(&container_root)?;
+    state.check_owner()?;
     if state.status != Status::Created && state.status != Status::Running {
         bail!("the container is not in the 'Created' or 'Running' state");
     }
@@ -223,35 +594,95 @@ pub fn kill(id: String, signal: String) -> Result<()> {
     };
 
     let pid = Pid::from_raw(state.pid);
-    signal::kill(pid, signal).context("failed to kill the container")?;
+    if group {
+        // The process group is only signalled if the container process leads it,
+        // otherwise the signal would reach processes outside the container
+        let pgid = unistd::getpgid(Some(pid)).context(format!(
+            "failed to get the process group of the container process {}",
+            pid
+        ))?;
+        if pgid != pid {
+            bail!(
+                "the container process {} is not the leader of its process group {}",
+                pid,
+                pgid
+            );
+        }
+        signal::killpg(pgid, signal)
+            .context("failed to kill the process group of the container")?;
+    } else {
+        signal::kill(pid, signal).context("failed to kill the container")?;
+    }
 
     state.refresh();
     state.persist(&container_root)?;
     Ok(())
 }
 
-pub fn delete(id: String, force: bool) -> Result<()> {
+/// `delete` deletes the stopped container. If `keep_state` is `true`, the container root is kept
+/// for postmortem analysis with [keep_container_root], and the container `id` could be created
+/// again. The user is responsible for removing the kept container root.
+/// If `force` is `true`, deleting a container that doesn't exist succeeds, and a container
+/// with a corrupt state is removed without stopping the container process.
+pub fn delete(id: String, force: bool, keep_state: bool) -> Result<()> {
     let container_root = Path::new(RENO_ROOT).join(id);
-    container_root
-        .try_exists()
-        .context("the container doesn't exist")?;
-
-    let mut state = State::load(&container_root)?;
+    let mut state = match State::load(&container_root) {
+        Ok(state) => state,
+        Err(error) if force => {
+            match error.downcast_ref::<ContainerError>() {
+                // The container is already deleted
+                Some(ContainerError::NotFound(_)) => return Ok(()),
+                // The container root is removed, since the container process and the cgroup
+                // can't be located without the state
+                Some(ContainerError::CorruptState(..)) => {
+                    warn!(
+                        "{:#}, which is removed without stopping the container",
+                        error
+                    );
+                    fs::remove_dir_all(&container_root)
+                        .context("failed to remove the container")?;
+                    return remove_lock_file(&container_root);
+                }
+                _ => return Err(error),
+            }
+        }
+        Err(error) => return Err(error),
+    };
+    state.check_owner()?;
     state.refresh();
 
     if state.status != Status::Stopped {
         if force {
             let pid = Pid::from_raw(state.pid);
             signal::kill(pid, Signal::SIGKILL).context("failed to kill the container")?;
+            // The cgroup can't be removed while the container process is still in it
+            process::wait_process_exit(pid)?;
         } else {
             bail!("the container is not in the 'Stopped' state");
         }
     }
 
-    fs::remove_dir_all(container_root).context("failed to remove the container")?;
-
     let bundle_spec = state.bundle.join("config.json");
     let spec = Spec::load(bundle_spec).context("failed to load the bundle configuration")?;
+
+    // Neither a cgroup nor a scope unit of `systemd` is created by `create` if the bundle
+    // configuration doesn't ask for a container cgroup, so `systemd` isn't contacted
+    if cgroup::cgroup_requested(&spec) {
+        cgroup::cgroup_manager(&spec, &state.id, state.systemd_cgroup)?.remove()?;
+    }
+
+    // The mounts might be left under the root file system if they're propagated to the host
+    if let Ok(rootfs) = container::resolve_rootfs(&state.bundle, &spec) {
+        mount::umount_recursive(&rootfs)?;
+    }
+
+    if keep_state {
+        keep_container_root(&container_root)?;
+    } else {
+        fs::remove_dir_all(&container_root).context("failed to remove the container")?;
+    }
+    remove_lock_file(&container_root)?;
+
     if let Some(hooks) = spec.hooks() {
         if let Some(post_stop_hooks) = hooks.poststop() {
             for post_stop_hook in post_stop_hooks {
@@ -262,3 +693,371 @@ pub fn delete(id: String, force: bool) -> Result<()> {
     }
     Ok(())
 }
+
+/// `keep_container_root` moves `container_root` aside to `<container_root>.deleted-<timestamp>`,
+/// where `<timestamp>` is the number of milliseconds since the Unix epoch, and returns the new path.
+/// The socket files are removed, and `state.json` is renamed to `state.json.deleted`. The container
+/// root is moved, so that a container created later with the same ID starts with an empty container
+/// root, whose removal doesn't remove the kept files, and whose events don't append to them.
+fn keep_container_root(container_root: &Path) -> Result<PathBuf> {
+    remove_socket_files(container_root)?;
+    fs::rename(
+        container_root.join("state.json"),
+        container_root.join("state.json.deleted"),
+    )
+    .context("failed to rename the state of the container")?;
+
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let mut kept_container_root = container_root.as_os_str().to_owned();
+    kept_container_root.push(format!(".deleted-{}", timestamp));
+    let kept_container_root = PathBuf::from(kept_container_root);
+    fs::rename(container_root, &kept_container_root).context(format!(
+        "failed to move the container root to {}",
+        kept_container_root.display()
+    ))?;
+    Ok(kept_container_root)
+}
+
+/// `remove_lock_file` removes the lock file of `container_root` created by [ContainerRootGuard].
+fn remove_lock_file(container_root: &Path) -> Result<()> {
+    let lock_path = guard::lock_path(container_root);
+    if lock_path.exists() {
+        fs::remove_file(&lock_path).context(format!("failed to remove {}", lock_path.display()))?;
+    }
+    Ok(())
+}
+
+/// `update` applies the resource limits to the cgroup of the running container, where each limit
+/// is independent, and the limits that are not specified are unchanged. The limits are read from
+/// the `resources` JSON file if it's specified, which contains a partial `linux.resources` object.
+/// The bundle configuration is not changed, so the limits are not kept if the container is recreated.
+/// It fails if the container stays in the cgroup of the runtime, which has no cgroup to update.
+pub fn update(
+    id: String,
+    memory: Option<i64>,
+    cpu_quota: Option<i64>,
+    cpu_shares: Option<u64>,
+    pids_limit: Option<i64>,
+    resources: Option<String>,
+) -> Result<()> {
+    let container_root = Path::new(RENO_ROOT).join(id);
+    let mut state = State::load(&container_root)?;
+    state.check_owner()?;
+    state.refresh();
+    if state.status != Status::Running {
+        bail!("the container is not in the 'Running' state");
+    }
+
+    let resources = match resources {
+        Some(resources_path) => {
+            let resources_json = fs::read_to_string(&resources_path)
+                .context(format!("failed to read {}", resources_path))?;
+            serde_json::from_str::<LinuxResources>(&resources_json).context(format!(
+                "failed to parse the resource limits in {}",
+                resources_path
+            ))?
+        }
+        None => build_resources(memory, cpu_quota, cpu_shares, pids_limit)?,
+    };
+
+    let bundle_spec = state.bundle.join("config.json");
+    let spec = Spec::load(bundle_spec).context("failed to load the bundle configuration")?;
+    if !cgroup::cgroup_requested(&spec) {
+        bail!("the container has no cgroup, since the bundle configuration defines neither linux.cgroupsPath nor linux.resources");
+    }
+    let cgroup_manager = cgroup::cgroup_manager(&spec, &state.id, state.systemd_cgroup)?;
+    cgroup::update_resources(cgroup_manager.as_ref(), &resources)
+}
+
+/// `build_resources` builds the [LinuxResources] from the options of `update`.
+fn build_resources(
+    memory: Option<i64>,
+    cpu_quota: Option<i64>,
+    cpu_shares: Option<u64>,
+    pids_limit: Option<i64>,
+) -> Result<LinuxResources> {
+    let mut resources = LinuxResourcesBuilder::default();
+    if let Some(memory) = memory {
+        resources = resources.memory(LinuxMemoryBuilder::default().limit(memory).build()?);
+    }
+    if cpu_quota.is_some() || cpu_shares.is_some() {
+        let mut cpu = LinuxCpuBuilder::default();
+        if let Some(cpu_quota) = cpu_quota {
+            cpu = cpu.quota(cpu_quota);
+        }
+        if let Some(cpu_shares) = cpu_shares {
+            cpu = cpu.shares(cpu_shares);
+        }
+        resources = resources.cpu(cpu.build()?);
+    }
+    if let Some(pids_limit) = pids_limit {
+        resources = resources.pids(LinuxPidsBuilder::default().limit(pids_limit).build()?);
+    }
+    Ok(resources.build()?)
+}
+
+/// `ps` prints the processes of the container with [process::list_container_processes],
+/// as a table or as a JSON array if `format` is `json`.
+pub fn ps(id: String, format: Option<String>) -> Result<()> {
+    let container_root = Path::new(RENO_ROOT).join(id);
+    let mut state = State::load(&container_root)?;
+    state.refresh();
+    if state.status != Status::Created && state.status != Status::Running {
+        bail!("the container is not in the 'Created' or 'Running' state");
+    }
+
+    let bundle_spec = state.bundle.join("config.json");
+    let spec = Spec::load(bundle_spec).context("failed to load the bundle configuration")?;
+    let process_list =
+        process::list_container_processes(state.pid, cgroup::cgroup_requested(&spec))?;
+    print!("{}", format_processes(&process_list, format.as_deref())?);
+    Ok(())
+}
+
+/// `format_processes` formats `process_list` as a JSON array if `format` is `json`,
+/// or as a table with a header otherwise.
+fn format_processes(process_list: &[NamespaceProcess], format: Option<&str>) -> Result<String> {
+    if format == Some("json") {
+        let serialized_process_list =
+            serde_json::to_string(process_list).context("failed to serialize the processes")?;
+        return Ok(format!("{}\n", serialized_process_list));
+    }
+
+    let mut table = format!("{:<10} {:<10} {:<16} CMD\n", "PID", "NSPID", "COMM");
+    for process in process_list {
+        table.push_str(&format!(
+            "{:<10} {:<10} {:<16} {}\n",
+            process.pid,
+            process.namespace_pid,
+            process.comm,
+            process.cmdline.join(" ")
+        ));
+    }
+    Ok(table)
+}
+
+pub fn events(id: String, follow: bool) -> Result<()> {
+    let container_root = Path::new(RENO_ROOT).join(id);
+    EventLog::new(&container_root).tail(&container_root, follow)
+}
+
+/// `lint` prints every issue of the bundle at `bundle` that would fail `create`,
+/// and exits with status `1` if there's any issue.
+pub fn lint(bundle: String) -> Result<()> {
+    let issue_list = validation::lint_bundle(Path::new(&bundle))?;
+    if issue_list.is_empty() {
+        return Ok(());
+    }
+
+    for issue in issue_list {
+        println!("{}", issue);
+    }
+    exit(1);
+}
+
+pub fn features() -> Result<()> {
+    let features = Features::detect();
+    let serialized_features =
+        serde_json::to_string(&features).context("failed to serialize the features")?;
+    println!("{}", serialized_features);
+    Ok(())
+}
+
+/// `print_json_error` prints `error` to stderr as a JSON object, where `error` is the message
+/// of the error, and `context` is the chain of errors that cause it, from the outermost to the
+/// innermost, such as `{"error": "failed to mount /proc", "context": ["ENOENT: No such file or directory"]}`.
+pub fn print_json_error(error: &anyhow::Error) {
+    let context: Vec<String> = error
+        .chain()
+        .skip(1)
+        .map(|cause| cause.to_string())
+        .collect();
+    let error = json!({
+        "error": error.to_string(),
+        "context": context,
+    });
+    eprintln!("{}", error);
+}
+
+/// `build_version` returns the version of the runtime, together with the commit and the date
+/// of the build and whether each optional Cargo feature is compiled in. If `short` is `true`,
+/// only the version is returned, which is stable for scripts.
+pub fn build_version(short: bool) -> String {
+    let version = env!("CARGO_PKG_VERSION");
+    if short {
+        return version.to_string();
+    }
+
+    // Only the Cargo features are listed, since the other capabilities are always compiled in
+    let feature_list = [("seccomp", cfg!(feature = "seccomp"))]
+        .iter()
+        .map(|(feature, enabled)| format!("{}{}", if *enabled { '+' } else { '-' }, feature))
+        .collect::<Vec<String>>()
+        .join(" ");
+
+    format!(
+        "reno {}\ncommit: {}\nbuild date: {}\nspec: {}\nfeatures: {}",
+        version,
+        env!("RENO_BUILD_COMMIT"),
+        env!("RENO_BUILD_DATE"),
+        OCI_VERSION,
+        feature_list
+    )
+}
+
+pub fn version() -> Result<()> {
+    let version = json!({
+        "runtimeVersion": env!("CARGO_PKG_VERSION"),
+        "spec": OCI_VERSION,
+        "platform": {
+            "os": env::consts::OS,
+            "arch": env::consts::ARCH,
+        },
+    });
+    println!("{}", version);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use oci_spec::runtime::ProcessBuilder;
+
+    use super::*;
+    use crate::test_util::{self, UMASK_LOCK};
+
+    #[test]
+    fn build_version_features() {
+        assert_eq!(build_version(true), env!("CARGO_PKG_VERSION"));
+
+        let version = build_version(false);
+        let features = version
+            .lines()
+            .find_map(|line| line.strip_prefix("features: "))
+            .unwrap();
+        let expected = if cfg!(feature = "seccomp") {
+            "+seccomp"
+        } else {
+            "-seccomp"
+        };
+        assert_eq!(features, expected);
+    }
+
+    #[test]
+    fn format_processes_as_table_and_json() {
+        let process_list = [NamespaceProcess {
+            pid: 4242,
+            namespace_pid: 1,
+            comm: String::from("sh"),
+            cmdline: vec![
+                String::from("sh"),
+                String::from("-c"),
+                String::from("sleep 1"),
+            ],
+        }];
+        assert_eq!(
+            format_processes(&process_list, None).unwrap(),
+            "PID        NSPID      COMM             CMD\n\
+            4242       1          sh               sh -c sleep 1\n"
+        );
+        assert_eq!(
+            format_processes(&process_list, Some("json")).unwrap(),
+            "[{\"pid\":4242,\"namespacePid\":1,\"comm\":\"sh\",\"cmdline\":[\"sh\",\"-c\",\"sleep 1\"]}]\n"
+        );
+        assert_eq!(format_processes(&[], Some("json")).unwrap(), "[]\n");
+    }
+
+    #[test]
+    fn wait_for_exec_reports_exec_error() {
+        let container_socket_path =
+            env::temp_dir().join(format!("reno-cli-exec-{}.sock", std::process::id()));
+        let mut container_socket_server = {
+            let _lock = UMASK_LOCK.lock().unwrap();
+            SocketServer::bind(&container_socket_path).unwrap()
+        };
+
+        // The container process reports `Running` before `execvp`, which fails
+        let container = thread::spawn(move || {
+            container_socket_server.listen().unwrap();
+            container_socket_server
+                .write(SocketMessage::new(Status::Running, None))
+                .unwrap();
+            let process = ProcessBuilder::default()
+                .args(vec![String::from("/nonexistent/program")])
+                .build()
+                .unwrap();
+            let error = fork::execute_process(&process).unwrap_err();
+            container_socket_server
+                .write(SocketMessage::new(
+                    Status::Stopped,
+                    Some(format!("{:#}", error)),
+                ))
+                .unwrap();
+        });
+
+        let mut container_socket_client = SocketClient::connect(&container_socket_path).unwrap();
+        let (container_message, seccomp_fd) =
+            wait_for_exec(&mut container_socket_client, false).unwrap();
+        container.join().unwrap();
+        assert_eq!(container_message.status, Status::Stopped);
+        assert!(seccomp_fd.is_none());
+        assert_eq!(
+            container_message.error.as_deref(),
+            Some("failed to execute /nonexistent/program: ENOENT: No such file or directory")
+        );
+    }
+
+    #[test]
+    fn resolve_bundle_from_relative_path() {
+        let directory = test_util::test_directory("cli", "bundle");
+        let container_root = directory.join("container");
+        fs::create_dir_all(directory.join("bundle")).unwrap();
+        fs::create_dir_all(&container_root).unwrap();
+        fs::write(directory.join("bundle/config.json"), "{}").unwrap();
+
+        // The working directory is changed in a child process, since it's shared by the tests
+        test_util::run_in_child(|| {
+            env::set_current_dir(&directory).unwrap();
+            let bundle = resolve_bundle("./bundle/../bundle").unwrap();
+            State::new(String::from("container"), bundle)
+                .persist(&container_root)
+                .unwrap();
+
+            env::set_current_dir("/").unwrap();
+            let state = State::load(&container_root).unwrap();
+            assert_eq!(state.bundle, directory.join("bundle"));
+            assert!(state.bundle.join("config.json").exists());
+        });
+        assert!(resolve_bundle("/nonexistent/bundle").is_err());
+    }
+
+    #[test]
+    fn keep_container_root_moves_it_aside() {
+        let container_root = test_util::test_directory("cli", "keep-state");
+        for file in ["state.json", "events.log", "container.sock"] {
+            fs::write(container_root.join(file), file).unwrap();
+        }
+
+        let kept_container_root = keep_container_root(&container_root).unwrap();
+        assert!(!container_root.exists());
+        let file_name = kept_container_root.file_name().unwrap().to_string_lossy();
+        let prefix = format!(
+            "{}.deleted-",
+            container_root.file_name().unwrap().to_string_lossy()
+        );
+        assert!(file_name.starts_with(&prefix));
+        assert_eq!(
+            fs::read_to_string(kept_container_root.join("state.json.deleted")).unwrap(),
+            "state.json"
+        );
+        assert!(kept_container_root.join("events.log").exists());
+        assert!(!kept_container_root.join("state.json").exists());
+        assert!(!kept_container_root.join("container.sock").exists());
+
+        fs::remove_dir_all(kept_container_root).unwrap();
+    }
+}
@@ -1,28 +1,69 @@
-use anyhow::Result;
-use oci_spec::runtime::{LinuxNamespace, Spec};
+use std::{os::fd::AsFd, path::Path};
+
+use anyhow::{Context, Result};
+use log::debug;
+use nix::{
+    mount::MsFlags,
+    unistd::{Gid, Uid},
+};
+use oci_spec::runtime::{LinuxNamespace, LinuxNamespaceType, Spec};
 
 use crate::{
+    container::{self, ContainerOptions},
     hook,
-    linux::{device, hostname, mount, namespace, sysctl},
+    linux::{device, hostname, keyring, mount, namespace, sysctl},
     state::State,
 };
 
+/// The annotation that opts the container out of a new session keyring,
+/// which matches the `--no-new-keyring` flag of `runc`.
+const NO_NEW_KEYRING_ANNOTATION: &str = "org.reno.noNewKeyring";
+
 pub fn init_environment(
     spec: &Spec,
     state: &State,
     namespace_list: &[LinuxNamespace],
+    options: &ContainerOptions,
 ) -> Result<()> {
     namespace::set_namespace(namespace_list)?;
+    namespace::set_time_namespace(namespace_list)?;
+    namespace::set_cgroup_namespace(namespace_list)?;
 
-    let rootfs = &state.bundle.join(spec.root().as_ref().unwrap().path());
-    mount::mount_rootfs(rootfs)?;
-
-    if let Some(mounts) = &spec.mounts() {
-        for mount in mounts {
-            mount::custom_mount(rootfs, mount)?;
-        }
+    let no_new_keyring = spec
+        .annotations()
+        .as_ref()
+        .and_then(|annotations| annotations.get(NO_NEW_KEYRING_ANNOTATION))
+        .is_some_and(|value| value == "true");
+    if !no_new_keyring {
+        keyring::join_session_keyring()?;
     }
 
+    let rootfs = &container::resolve_rootfs(&state.bundle, spec)?;
+    mount::mount_rootfs(rootfs, rootfs_propagation(spec)?)?;
+
+    // The `tmpfs` mounts are owned by the container user in a new user namespace,
+    // instead of the root of the namespace that mounts them
+    let new_user_namespace = namespace_list
+        .iter()
+        .any(|namespace| namespace.typ() == LinuxNamespaceType::User && namespace.path().is_none());
+    let tmpfs_owner = spec
+        .process()
+        .as_ref()
+        .filter(|_| new_user_namespace)
+        .map(|process| {
+            (
+                Uid::from_raw(process.user().uid()),
+                Gid::from_raw(process.user().gid()),
+            )
+        });
+    mount::mount_all(
+        rootfs,
+        &state.bundle,
+        spec.mounts().as_deref().unwrap_or_default(),
+        options.add_default_mounts,
+        tmpfs_owner,
+    )?;
+
     if let Some(linux) = spec.linux() {
         if let Some(devices) = linux.devices() {
             for device in devices {
@@ -31,17 +72,42 @@ pub fn init_environment(
         }
     }
 
-    device::create_default_device(rootfs)?;
-    device::create_default_symlink(rootfs)?;
+    // The default devices are not created in the `/dev` of the host, which already contains them,
+    // since the nodes would be created on the host
+    if dev_mounted_from_host(spec) {
+        debug!("skipping the default devices, since /dev is mounted from the host");
+    } else {
+        device::create_default_device(rootfs)?;
+        device::create_default_symlink(rootfs)?;
+    }
 
-    if let Some(hostname) = spec.hostname() {
+    if let Some(process) = spec
+        .process()
+        .as_ref()
+        .filter(|process| process.terminal() == Some(true))
+    {
+        let console_socket = options
+            .console_socket
+            .as_deref()
+            .context("process.terminal requires --console-socket")?;
+        let owner = (
+            Uid::from_raw(process.user().uid()),
+            Gid::from_raw(process.user().gid()),
+        );
+        // The `/dev/console` of the host is not replaced if `/dev` is mounted from the host
+        let master = device::create_console(rootfs, owner, !dev_mounted_from_host(spec))?;
+        device::send_console(console_socket, master.as_fd())?;
+    }
+
+    // The hostname of `create --hostname` takes precedence over the bundle configuration
+    if let Some(hostname) = options.hostname.as_deref().or(spec.hostname().as_deref()) {
         hostname::set_hostname(hostname)?;
     }
 
     Ok(())
 }
 
-pub fn create_container(spec: &Spec, state: &State) -> Result<()> {
+pub fn create_container(spec: &Spec, state: &State, no_pivot: bool) -> Result<()> {
     if let Some(hooks) = spec.hooks() {
         if let Some(create_container_hooks) = hooks.create_container() {
             for create_container_hook in create_container_hooks {
@@ -50,14 +116,102 @@ pub fn create_container(spec: &Spec, state: &State) -> Result<()> {
         }
     }
 
-    let rootfs = state.bundle.join(spec.root().as_ref().unwrap().path());
+    let rootfs = container::resolve_rootfs(&state.bundle, spec)?;
     let readonly = spec.root().as_ref().unwrap().readonly().unwrap_or_default();
-    mount::pivot_rootfs(&rootfs, readonly)?;
+    mount::switch_rootfs(&rootfs, readonly, !no_pivot)?;
+    mount::set_rootfs_propagation(rootfs_propagation(spec)?)?;
 
     if let Some(linux) = spec.linux() {
         if let Some(sysctl) = linux.sysctl() {
             sysctl::set_sysctl(sysctl)?;
         }
+
+        // The paths are made read-only or masked after the kernel parameters are changed,
+        // since `/proc/sys` is usually one of the read-only paths
+        for path in linux.readonly_paths().as_deref().unwrap_or_default() {
+            mount::readonly_path(Path::new(path))?;
+        }
+        for path in linux.masked_paths().as_deref().unwrap_or_default() {
+            mount::mask_path(Path::new(path))?;
+        }
     }
     Ok(())
 }
+
+/// `dev_mounted_from_host` checks if `/dev` is provided by a mount in the bundle configuration
+/// that is not a `tmpfs`, such as a bind mount of the `/dev` of the host in a privileged container,
+/// where the default devices and `/dev/console` are not created.
+/// Otherwise, `/dev` is the `tmpfs` of `create --add-default-mounts`, or the directory in the image.
+fn dev_mounted_from_host(spec: &Spec) -> bool {
+    spec.mounts()
+        .as_deref()
+        .unwrap_or_default()
+        .iter()
+        .filter(|mount| mount.destination() == Path::new("/dev"))
+        .any(|mount| {
+            let bind = mount
+                .options()
+                .as_deref()
+                .unwrap_or_default()
+                .iter()
+                .any(|option| option == "bind" || option == "rbind");
+            bind || mount.typ().as_deref() != Some("tmpfs")
+        })
+}
+
+/// `rootfs_propagation` returns the propagation type of the root mount
+/// defined in `linux.rootfsPropagation`.
+fn rootfs_propagation(spec: &Spec) -> Result<MsFlags> {
+    let propagation = spec
+        .linux()
+        .as_ref()
+        .and_then(|linux| linux.rootfs_propagation().as_deref());
+    mount::rootfs_propagation_to_msflags(propagation)
+}
+
+#[cfg(test)]
+mod tests {
+    use oci_spec::runtime::{Mount, MountBuilder};
+
+    use super::*;
+
+    fn dev_mount(typ: &str, options: &[&str]) -> Mount {
+        MountBuilder::default()
+            .destination("/dev")
+            .typ(typ)
+            .source("/dev")
+            .options(
+                options
+                    .iter()
+                    .map(|option| option.to_string())
+                    .collect::<Vec<_>>(),
+            )
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn dev_mounted_from_host_tmpfs() {
+        let mut spec = Spec::default();
+        assert!(!dev_mounted_from_host(&spec));
+
+        spec.set_mounts(Some(vec![dev_mount("tmpfs", &["nosuid", "mode=755"])]));
+        assert!(!dev_mounted_from_host(&spec));
+
+        spec.set_mounts(None);
+        assert!(!dev_mounted_from_host(&spec));
+    }
+
+    #[test]
+    fn dev_mounted_from_host_bind() {
+        let mut spec = Spec::default();
+        spec.set_mounts(Some(vec![dev_mount("bind", &["rbind"])]));
+        assert!(dev_mounted_from_host(&spec));
+
+        spec.set_mounts(Some(vec![dev_mount("tmpfs", &["bind"])]));
+        assert!(dev_mounted_from_host(&spec));
+
+        spec.set_mounts(Some(vec![dev_mount("devtmpfs", &[])]));
+        assert!(dev_mounted_from_host(&spec));
+    }
+}
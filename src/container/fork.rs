@@ -1,11 +1,14 @@
-use std::{ffi::CString, path::Path, process::exit};
+use std::{ffi::CString, os::fd::AsFd, path::Path, process::exit};
 
-use anyhow::{bail, Result};
-use nix::unistd::{self, Pid};
-use oci_spec::runtime::{LinuxNamespace, Spec};
+use anyhow::{bail, Context, Result};
+use nix::{
+    sys::prctl,
+    unistd::{self, Pid},
+};
+use oci_spec::runtime::{LinuxNamespace, LinuxNamespaceType, Process, Spec};
 
 use crate::{
-    container::{create, start},
+    container::{create, guard, start, ContainerOptions},
     linux::process,
     socket::{SocketClient, SocketMessage, SocketServer},
     state::{State, Status},
@@ -13,74 +16,142 @@ use crate::{
 
 /// `pipeline` initializes the container environment, run hooks, and start the container process.
 /// The pipeline contains these phases:
+/// - [detach_terminal](process::detach_terminal): Detach from the controlling terminal if `process.terminal` is `false`
 /// - [init_environment](create::init_environment): Mount the root file system, create devices and symbolic links, and change the hostname
+/// - [redirect_output_to_fifo](process::redirect_output_to_fifo): Redirect the stdout and stderr to `options.log_fifo` if it's specified
 /// - Listen on the `container_socket_server` to wait the runtime to invoke the `create_runtime` hook
-/// - [create_container](create::create_container): Run the `create_container` hook, change the root mount, and change kernel parameters
+/// - [create_container](create::create_container): Run the `create_container` hook, change the root mount, change kernel parameters, and apply the read-only and masked paths
 /// - Listen on the `container_socket_server` to wait the runtime to invoke the `prestart` hook
-/// - [start_container](start::start_container): Run the `start_container` hook, set resource limits, capabilities, and ownership of the container process
+/// - [start_container](start::start_container): Run the `start_container` hook, set resource limits, capabilities, and ownership of the container process, and load the seccomp filter
+/// - [preserve_fds](process::preserve_fds): Pass `options.preserve_fds` file descriptors after the stderr to the container process
 /// - [execvp](unistd::execvp): Start the container process
 pub fn pipeline(
     spec: &Spec,
     state: &State,
     namespace_list: &[LinuxNamespace],
+    options: &ContainerOptions,
     container_socket_server: &mut SocketServer,
 ) -> Result<()> {
-    create::init_environment(spec, state, namespace_list)?;
+    let terminal = spec
+        .process()
+        .as_ref()
+        .and_then(|process| process.terminal())
+        .unwrap_or_default();
+    if !terminal {
+        process::detach_terminal()?;
+    }
+
+    // Prevent processes in the container from attaching to the container process with `ptrace`
+    // before it drops the privileges in `start_container`
+    prctl::set_dumpable(false).context("failed to set PR_SET_DUMPABLE to false")?;
+
+    // In a new PID namespace, the container process is the init process of the namespace,
+    // which reaps the orphaned processes in it. Without a new PID namespace, the orphaned
+    // processes are reaped by the container process instead of the init process of the host.
+    let new_pid_namespace = namespace_list
+        .iter()
+        .any(|namespace| namespace.typ() == LinuxNamespaceType::Pid && namespace.path().is_none());
+    if !new_pid_namespace {
+        process::setup_subreaper()?;
+    }
+
+    create::init_environment(spec, state, namespace_list, options)?;
+
+    // The named pipe is opened before `create_container` changes the root mount,
+    // where its path on the host is no longer accessible
+    if let Some(log_fifo) = &options.log_fifo {
+        process::redirect_output_to_fifo(log_fifo)?;
+    }
     container_socket_server.write(SocketMessage::new(Status::Creating, None))?;
 
     // Listen on the `container_socket_server` to wait the runtime to invoke the `create_runtime` hook
     container_socket_server.listen()?;
-    create::create_container(spec, state)?;
+    create::create_container(spec, state, options.no_pivot)?;
     container_socket_server.write(SocketMessage::new(Status::Created, None))?;
 
     // Listen on the `container_socket_server` to wait the runtime to invoke the `prestart` hook
     container_socket_server.listen().unwrap();
-    start::start_container(spec, state)?;
-    container_socket_server.write(SocketMessage::new(Status::Running, None))?;
-
-    if let Some(process) = spec.process() {
-        let command = CString::new(process.args().as_ref().unwrap()[0].as_bytes())?;
-        let argument_list: Vec<CString> = process
-            .args()
-            .as_ref()
-            .unwrap()
-            .iter()
-            .map(|a| CString::new(a.to_string()).unwrap_or_default())
-            .collect();
-
-        unistd::execvp(&command, &argument_list)?;
-    } else {
-        bail!("the 'process' field doesn't exist");
+    let seccomp_fd = start::start_container(spec, state, options.clamp_rlimits)?;
+    match seccomp_fd {
+        // Send the file descriptor of the seccomp notification listener to the runtime,
+        // which forwards it to `linux.seccomp.listenerPath`
+        Some(seccomp_fd) => container_socket_server.write_with_fd(
+            SocketMessage::new(Status::Running, None),
+            seccomp_fd.as_fd(),
+        )?,
+        None => container_socket_server.write(SocketMessage::new(Status::Running, None))?,
     }
 
+    process::preserve_fds(options.preserve_fds)?;
+    match spec.process() {
+        Some(process) => execute_process(process),
+        None => bail!("the 'process' field doesn't exist"),
+    }
+}
+
+/// `execute_process` replaces the current process with the program in `process.args`,
+/// which only returns if `execvp` fails, such as a nonexistent program. The error is
+/// reported to the `reno` CLI, which is waiting in `start` until the connection is closed.
+pub fn execute_process(process: &Process) -> Result<()> {
+    let args = process.args().as_deref().unwrap_or_default();
+    let Some(program) = args.first() else {
+        bail!("process.args is empty");
+    };
+    let command = CString::new(program.as_bytes())?;
+    let argument_list: Vec<CString> = args
+        .iter()
+        .map(|a| CString::new(a.to_string()).unwrap_or_default())
+        .collect();
+
+    unistd::execvp(&command, &argument_list).context(format!("failed to execute {}", program))?;
     Ok(())
 }
 
 /// `fork_container` clones a new process that invokes the [pipeline] function,
 /// which initializes the container environment, run hooks, and start the container process.
+/// Every namespace is created by a single `clone`, where the user namespace is created first,
+/// and the other namespaces are owned by it. The container process has no uid or gid in the
+/// user namespace until the runtime writes the mappings, so it connects to `init_socket_path`
+/// and waits for the runtime to write the mappings before it creates the container socket.
 pub fn fork_container(
     spec: &Spec,
     state: &State,
     namespace_list: &[LinuxNamespace],
     init_socket_path: &Path,
     container_socket_path: &Path,
+    options: &ContainerOptions,
 ) -> Result<Pid> {
     process::clone_child(namespace_list, || {
+        // The signal mask is inherited from the `reno` CLI, where the signals are blocked
+        // by the `InterruptGuard`, and is kept by `execvp`
+        guard::interrupt_signals().thread_unblock().unwrap();
+
+        // Connect to the socket server on `init_socket_path` and wait for the `reno` CLI
+        // to write the uid and gid mappings of the user namespace
+        let mut init_socket_client = SocketClient::connect(init_socket_path).unwrap();
+        init_socket_client.read().unwrap();
+
         // Initialize the `container_socket_server` that enables communication between
-        // the container process and the `reno` CLI
+        // the container process and the `reno` CLI, and close the connection to the
+        // `init_socket_path` to let the `reno` CLI know that it's initialized
         let mut container_socket_server = SocketServer::bind(container_socket_path).unwrap();
-
-        // Connect to the socket server on `init_socket_path` to let the `reno` CLI know that
-        // the `container_socket_server` is initialized
-        let init_socket_client = SocketClient::connect(init_socket_path).unwrap();
         init_socket_client.shutdown().unwrap();
 
         // Wait for the `reno` CLI to connect to the `container_socket_server`
         container_socket_server.listen().unwrap();
 
-        if let Err(error) = pipeline(spec, state, namespace_list, &mut container_socket_server) {
+        if let Err(error) = pipeline(
+            spec,
+            state,
+            namespace_list,
+            options,
+            &mut container_socket_server,
+        ) {
             container_socket_server
-                .write(SocketMessage::new(Status::Stopped, Some(error.to_string())))
+                .write(SocketMessage::new(
+                    Status::Stopped,
+                    Some(format!("{:#}", error)),
+                ))
                 .unwrap();
             exit(1);
         }
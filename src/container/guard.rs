@@ -0,0 +1,223 @@
+use std::{
+    fs::{self, File},
+    io::ErrorKind,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    process::exit,
+    sync::{
+        atomic::{AtomicBool, AtomicI32, Ordering},
+        Arc,
+    },
+    thread,
+};
+
+use anyhow::{Context, Result};
+use nix::{
+    errno::Errno,
+    fcntl::{Flock, FlockArg},
+    sys::{
+        signal::{self, SigSet, Signal},
+        wait,
+    },
+    unistd::Pid,
+};
+
+use crate::error::ContainerError;
+
+/// `ContainerRootGuard` holds an exclusive lock on `<container_root>.lock` while the container
+/// is being created, which prevents concurrent invocations of `create` with the same container ID
+/// from passing the existence check together. When the guard is dropped, the container root is removed
+/// if the creation is not [committed](ContainerRootGuard::commit). The lock file is kept,
+/// since another invocation might be waiting on it, and it's removed by `delete`.
+pub struct ContainerRootGuard {
+    container_root: PathBuf,
+    committed: bool,
+    _lock: Flock<File>,
+}
+
+impl ContainerRootGuard {
+    /// `lock` acquires the lock of `container_root`, or returns [ContainerError::AlreadyExists]
+    /// if the lock is held by another invocation. If the lock file is removed by `delete`
+    /// before it's locked, the lock is acquired again on the new lock file.
+    pub fn lock(container_root: &Path, id: &str) -> Result<Self> {
+        let lock_path = lock_path(container_root);
+        if let Some(parent) = lock_path.parent() {
+            fs::create_dir_all(parent).context(format!("failed to create {}", parent.display()))?;
+        }
+
+        loop {
+            let lock_file = File::create(&lock_path)
+                .context(format!("failed to create {}", lock_path.display()))?;
+            let lock = match Flock::lock(lock_file, FlockArg::LockExclusiveNonblock) {
+                Ok(lock) => lock,
+                Err((_, Errno::EWOULDBLOCK)) => {
+                    return Err(ContainerError::AlreadyExists(id.to_string()).into())
+                }
+                Err((_, errno)) => {
+                    return Err(errno).context(format!("failed to lock {}", lock_path.display()))
+                }
+            };
+
+            // The lock is only valid if the locked file is still at the lock path
+            let locked_inode = lock
+                .metadata()
+                .context(format!("failed to stat {}", lock_path.display()))?
+                .ino();
+            match fs::metadata(&lock_path) {
+                Ok(metadata) if metadata.ino() == locked_inode => {
+                    return Ok(ContainerRootGuard {
+                        container_root: container_root.to_path_buf(),
+                        committed: false,
+                        _lock: lock,
+                    })
+                }
+                Ok(_) => (),
+                Err(error) if error.kind() == ErrorKind::NotFound => (),
+                Err(error) => {
+                    return Err(error).context(format!("failed to stat {}", lock_path.display()))
+                }
+            }
+        }
+    }
+
+    /// `commit` keeps the container root when the guard is dropped, which should be invoked
+    /// once the container process is created, since the container should be removed with `delete`.
+    pub fn commit(&mut self) {
+        self.committed = true;
+    }
+}
+
+impl Drop for ContainerRootGuard {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = fs::remove_dir_all(&self.container_root);
+        }
+    }
+}
+
+/// `lock_path` returns the path of the lock file of `container_root`, which is `<container_root>.lock`.
+pub fn lock_path(container_root: &Path) -> PathBuf {
+    let mut lock_path = container_root.as_os_str().to_os_string();
+    lock_path.push(".lock");
+    PathBuf::from(lock_path)
+}
+
+/// `InterruptGuard` cleans up the container if the `reno` CLI receives `SIGINT` or `SIGTERM`
+/// while the container is being created. The signals are blocked in the `reno` CLI and received
+/// with `sigwait` by a dedicated thread, which kills the container process recorded with
+/// [InterruptGuard::set_child], removes the container root, and exits
+/// with a non-zero status. When the guard is dropped, the signals are unblocked,
+/// so that they are handled with the default action once the container is created.
+/// For more information, see the [sigwait(3)](https://man7.org/linux/man-pages/man3/sigwait.3.html)
+/// man page.
+pub struct InterruptGuard {
+    child: Arc<AtomicI32>,
+    disarmed: Arc<AtomicBool>,
+}
+
+impl InterruptGuard {
+    /// `install` blocks the signals and spawns the thread that cleans up `container_root`.
+    pub fn install(container_root: &Path) -> Result<Self> {
+        interrupt_signals()
+            .thread_block()
+            .context("failed to block SIGINT and SIGTERM")?;
+
+        let child = Arc::new(AtomicI32::new(0));
+        let disarmed = Arc::new(AtomicBool::new(false));
+        let container_root = container_root.to_path_buf();
+        let (thread_child, thread_disarmed) = (Arc::clone(&child), Arc::clone(&disarmed));
+        thread::spawn(move || {
+            let Ok(signal) = interrupt_signals().wait() else {
+                return;
+            };
+
+            // The signal might be received after the guard is dropped,
+            // where the container is created and must not be removed
+            if !thread_disarmed.load(Ordering::SeqCst) {
+                let pid = thread_child.load(Ordering::SeqCst);
+                if pid > 0 {
+                    let _ = signal::kill(Pid::from_raw(pid), Signal::SIGKILL);
+                    let _ = wait::waitpid(Pid::from_raw(pid), None);
+                }
+                let _ = fs::remove_dir_all(&container_root);
+                eprintln!("the creation of the container is interrupted by {}", signal);
+            }
+            exit(128 + signal as i32);
+        });
+
+        Ok(InterruptGuard { child, disarmed })
+    }
+
+    /// `set_child` records the container process, which is killed if the creation is interrupted.
+    pub fn set_child(&self, pid: Pid) {
+        self.child.store(pid.as_raw(), Ordering::SeqCst);
+    }
+}
+
+impl Drop for InterruptGuard {
+    fn drop(&mut self) {
+        self.disarmed.store(true, Ordering::SeqCst);
+        let _ = interrupt_signals().thread_unblock();
+    }
+}
+
+/// `interrupt_signals` returns the signals handled by [InterruptGuard].
+pub fn interrupt_signals() -> SigSet {
+    let mut signal_set = SigSet::empty();
+    signal_set.add(Signal::SIGINT);
+    signal_set.add(Signal::SIGTERM);
+    signal_set
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lock_path_appends_extension() {
+        assert_eq!(
+            lock_path(Path::new("/run/reno/container")),
+            Path::new("/run/reno/container.lock")
+        );
+    }
+
+    #[test]
+    fn lock_is_exclusive_and_kept_on_drop() {
+        let container_root =
+            std::env::temp_dir().join(format!("reno-guard-{}", std::process::id()));
+        let guard = ContainerRootGuard::lock(&container_root, "container").unwrap();
+        let Err(error) = ContainerRootGuard::lock(&container_root, "container") else {
+            panic!("the lock is acquired twice");
+        };
+        assert!(matches!(
+            error.downcast_ref::<ContainerError>(),
+            Some(ContainerError::AlreadyExists(_))
+        ));
+
+        drop(guard);
+        assert!(lock_path(&container_root).exists());
+        let guard = ContainerRootGuard::lock(&container_root, "container").unwrap();
+        drop(guard);
+        fs::remove_file(lock_path(&container_root)).unwrap();
+    }
+
+    #[test]
+    fn interrupt_signals_blocked_until_drop() {
+        let signal_set = interrupt_signals();
+        assert!(signal_set.contains(Signal::SIGINT));
+        assert!(signal_set.contains(Signal::SIGTERM));
+        assert!(!signal_set.contains(Signal::SIGKILL));
+
+        let container_root =
+            std::env::temp_dir().join(format!("reno-interrupt-{}", std::process::id()));
+        let guard = InterruptGuard::install(&container_root).unwrap();
+        let signal_mask = SigSet::thread_get_mask().unwrap();
+        assert!(signal_mask.contains(Signal::SIGINT));
+        assert!(signal_mask.contains(Signal::SIGTERM));
+
+        drop(guard);
+        let signal_mask = SigSet::thread_get_mask().unwrap();
+        assert!(!signal_mask.contains(Signal::SIGINT));
+        assert!(!signal_mask.contains(Signal::SIGTERM));
+    }
+}
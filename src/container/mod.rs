@@ -1,3 +1,156 @@
 pub mod create;
 pub mod fork;
+pub mod guard;
 pub mod start;
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use oci_spec::runtime::Spec;
+
+/// `ContainerOptions` contains the options of `create` that are applied in the container process.
+pub struct ContainerOptions {
+    /// `clamp_rlimits` lowers the hard resource limits that exceed the limits of the runtime
+    pub clamp_rlimits: bool,
+    /// `log_fifo` is the named pipe that receives the stdout and stderr of the container
+    pub log_fifo: Option<PathBuf>,
+    /// `no_pivot` changes the root mount with `chroot` instead of `pivot_root`
+    pub no_pivot: bool,
+    /// `preserve_fds` is the number of file descriptors after the stderr that are passed
+    /// to the container process
+    pub preserve_fds: u32,
+    /// `hostname` overrides the `hostname` field of the bundle configuration
+    pub hostname: Option<String>,
+    /// `add_default_mounts` mounts the default file systems that the bundle configuration omits,
+    /// besides the `tmpfs` on `/dev/shm`, which is always mounted if it's omitted. Every default
+    /// file system is mounted regardless of it if the bundle configuration has no mounts
+    pub add_default_mounts: bool,
+    /// `console_socket` is the socket that receives the master of the pseudoterminal
+    /// if `process.terminal` is `true`
+    pub console_socket: Option<PathBuf>,
+}
+
+/// `resolve_rootfs` resolves `root.path` in the bundle configuration to the canonical path
+/// of the root file system, where a relative path is relative to `bundle`, and the symbolic links
+/// are resolved. It returns an error if the root file system doesn't exist, is not a directory,
+/// or is the root directory of the host.
+pub fn resolve_rootfs(bundle: &Path, spec: &Spec) -> Result<PathBuf> {
+    let root = spec
+        .root()
+        .as_ref()
+        .context("the 'root' field doesn't exist")?;
+    let rootfs = if root.path().is_absolute() {
+        root.path().clone()
+    } else {
+        bundle.join(root.path())
+    };
+
+    let rootfs = fs::canonicalize(&rootfs).context(format!(
+        "failed to resolve the root file system {}",
+        rootfs.display()
+    ))?;
+    if !rootfs.is_dir() {
+        bail!(
+            "the root file system {} is not a directory",
+            rootfs.display()
+        );
+    }
+    if rootfs.parent().is_none() {
+        bail!(
+            "the root file system {} is the root directory of the host",
+            rootfs.display()
+        );
+    }
+    Ok(rootfs)
+}
+
+/// `validate_console_socket` checks that `console_socket` is specified if and only if
+/// `process.terminal` is `true`, since the master of the pseudoterminal is only sent
+/// to the console socket, which matches the behavior of `runc`.
+pub fn validate_console_socket(spec: &Spec, console_socket: Option<&Path>) -> Result<()> {
+    let terminal = spec
+        .process()
+        .as_ref()
+        .and_then(|process| process.terminal())
+        .unwrap_or_default();
+    match (terminal, console_socket) {
+        (true, None) => bail!("process.terminal is true, but --console-socket is not specified"),
+        (false, Some(_)) => {
+            bail!("--console-socket is specified, but process.terminal is not true")
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use oci_spec::runtime::{Process, Root};
+
+    use super::*;
+    use crate::test_util;
+
+    fn terminal_spec(terminal: bool) -> Spec {
+        let mut process = Process::default();
+        process.set_terminal(Some(terminal));
+        let mut spec = Spec::default();
+        spec.set_process(Some(process));
+        spec
+    }
+
+    #[test]
+    fn validate_console_socket_with_terminal() {
+        let spec = terminal_spec(true);
+        assert!(validate_console_socket(&spec, Some(Path::new("/tmp/console.sock"))).is_ok());
+        assert!(validate_console_socket(&spec, None).is_err());
+    }
+
+    #[test]
+    fn validate_console_socket_without_terminal() {
+        let spec = terminal_spec(false);
+        assert!(validate_console_socket(&spec, None).is_ok());
+        assert!(validate_console_socket(&spec, Some(Path::new("/tmp/console.sock"))).is_err());
+    }
+
+    fn root_spec(path: &Path) -> Spec {
+        let mut root = Root::default();
+        root.set_path(path.to_path_buf());
+        let mut spec = Spec::default();
+        spec.set_root(Some(root));
+        spec
+    }
+
+    #[test]
+    fn resolve_rootfs_against_bundle() {
+        let bundle = test_util::test_directory("container", "rootfs");
+        fs::create_dir_all(bundle.join("rootfs")).unwrap();
+        std::os::unix::fs::symlink("rootfs", bundle.join("link")).unwrap();
+        fs::write(bundle.join("file"), "").unwrap();
+        let rootfs = fs::canonicalize(bundle.join("rootfs")).unwrap();
+
+        assert_eq!(
+            resolve_rootfs(&bundle, &root_spec(Path::new("rootfs"))).unwrap(),
+            rootfs
+        );
+        assert_eq!(
+            resolve_rootfs(&bundle, &root_spec(Path::new("link"))).unwrap(),
+            rootfs
+        );
+        assert_eq!(
+            resolve_rootfs(Path::new("/"), &root_spec(&bundle.join("rootfs"))).unwrap(),
+            rootfs
+        );
+        assert!(resolve_rootfs(&bundle, &root_spec(Path::new("missing"))).is_err());
+        assert!(resolve_rootfs(&bundle, &root_spec(Path::new("file"))).is_err());
+        assert!(resolve_rootfs(&bundle, &root_spec(Path::new("/"))).is_err());
+    }
+
+    #[test]
+    fn resolve_rootfs_without_root() {
+        let mut spec = Spec::default();
+        spec.set_root(None);
+        assert!(resolve_rootfs(Path::new("/"), &spec).is_err());
+    }
+}
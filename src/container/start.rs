@@ -1,21 +1,25 @@
-use std::env;
+use std::{env, os::fd::OwnedFd};
 
-use anyhow::{bail, Context, Result};
+use anyhow::{Context, Result};
 use caps::CapSet;
 use nix::{
     sys::{prctl, stat, stat::Mode},
     unistd,
     unistd::{Gid, Uid},
 };
-use oci_spec::runtime::Spec;
+use oci_spec::runtime::{LinuxSchedulerPolicy, Process, Scheduler, Spec};
 
 use crate::{
     hook,
-    linux::{cap, rlimit, sysctl},
+    linux::{cap, personality, process, rlimit, seccomp, sysctl},
     state::State,
 };
 
-pub fn start_container(spec: &Spec, state: &State) -> Result<()> {
+/// `start_container` runs the `start_container` hook, sets the resource limits, capabilities,
+/// ownership, and personality of the container process, and loads the seccomp filter.
+/// The file descriptor of the seccomp notification listener is returned if the seccomp filter
+/// intercepts system calls.
+pub fn start_container(spec: &Spec, state: &State, clamp_rlimits: bool) -> Result<Option<OwnedFd>> {
     if let Some(hooks) = spec.hooks() {
         if let Some(start_container_hooks) = hooks.start_container() {
             for start_container_hook in start_container_hooks {
@@ -34,66 +38,184 @@ pub fn start_container(spec: &Spec, state: &State) -> Result<()> {
         }
 
         if let Some(rlimits) = process.rlimits() {
-            for rlimit in rlimits {
-                rlimit::set_rlimit(rlimit)?;
-            }
+            rlimit::set_rlimits(rlimits, clamp_rlimits)?;
         }
 
         if let Some(oom_score_adj) = process.oom_score_adj() {
             sysctl::set_oom_score_adj(oom_score_adj)?;
         }
 
-        if let Some(capabilities) = process.capabilities() {
-            if let Some(capabilities) = capabilities.bounding() {
-                cap::set_cap(CapSet::Bounding, capabilities)?;
-            }
+        if let Some(nice) = process.scheduler().as_ref().and_then(scheduler_nice) {
+            process::set_nice(nice)?;
         }
 
-        prctl::set_keepcaps(true).context("failed to set PR_SET_KEEPCAPS to true")?;
-        unistd::setgid(Gid::from_raw(process.user().gid()))
-            .context(format!("failed to set gid to {}", process.user().gid()))?;
+        drop_privileges(process)?;
 
-        if let Some(mode) = process.user().umask() {
-            if let Some(mode) = Mode::from_bits(mode) {
-                stat::umask(mode);
-            } else {
-                bail!("invalid umask: {}", mode);
-            }
-        }
+        unistd::chdir(process.cwd()).context(format!(
+            "failed to change the working directory to {}",
+            process.cwd().display()
+        ))?;
+    }
+
+    // The personality is set before the seccomp filter, which might deny `personality`
+    if let Some(personality) = spec
+        .linux()
+        .as_ref()
+        .and_then(|linux| linux.personality().as_ref())
+    {
+        personality::set_personality(personality)?;
+    }
+
+    seccomp::apply_seccomp(spec)
+}
 
-        if let Some(additional_gids) = process.user().additional_gids() {
-            let additional_gids: &Vec<Gid> = &additional_gids
-                .iter()
-                .map(|gid| Gid::from_raw(*gid))
-                .collect();
-            unistd::setgroups(additional_gids)
-                .context("failed to set additional gids".to_string())?;
+/// `scheduler_nice` returns the nice value of `scheduler`, which is the only field of
+/// `process.scheduler` that is applied, where the idle policy runs the process with the lowest
+/// priority unless the nice value is specified.
+fn scheduler_nice(scheduler: &Scheduler) -> Option<i32> {
+    match (scheduler.nice(), scheduler.policy()) {
+        (Some(nice), _) => Some(*nice),
+        (None, LinuxSchedulerPolicy::SchedIdle) => Some(19),
+        (None, _) => None,
+    }
+}
+
+/// `umask_mode` converts `process.user.umask` to the [Mode] of `umask`, where only the permission
+/// bits are meaningful, and the higher bits, such as the file type, are discarded.
+fn umask_mode(umask: u32) -> Mode {
+    Mode::from_bits_truncate(umask & 0o7777)
+}
+
+/// `drop_privileges` changes the ownership and the capabilities of the current process to the ones
+/// of `process`, and sets `no_new_privs` if `process.noNewPrivileges` is `true`. It's shared by the
+/// container process and the processes that join the container later, such as the health check.
+pub fn drop_privileges(process: &Process) -> Result<()> {
+    if let Some(capabilities) = process.capabilities() {
+        cap::validate_ambient(capabilities)?;
+        cap::validate_effective(capabilities)?;
+        if let Some(capabilities) = capabilities.bounding() {
+            cap::set_cap(CapSet::Bounding, capabilities)?;
         }
-        unistd::setuid(Uid::from_raw(process.user().uid()))
-            .context(format!("failed to set uid to {}", process.user().gid()))?;
-
-        prctl::set_keepcaps(false).context("failed to set PR_SET_KEEPCAPS to false")?;
-
-        if let Some(capabilities) = process.capabilities() {
-            let capabilities_list = [
-                (capabilities.effective(), CapSet::Effective),
-                (capabilities.permitted(), CapSet::Permitted),
-                (capabilities.inheritable(), CapSet::Inheritable),
-                (capabilities.ambient(), CapSet::Ambient),
-            ];
-            for (capabilities, capabilities_set_flag) in capabilities_list.into_iter() {
-                if let Some(capabilities) = capabilities {
-                    if let Err(err) = cap::set_cap(capabilities_set_flag, capabilities) {
-                        println!("{}", err);
-                    }
-                }
+    }
+
+    prctl::set_keepcaps(true).context("failed to set PR_SET_KEEPCAPS to true")?;
+    unistd::setgid(Gid::from_raw(process.user().gid()))
+        .context(format!("failed to set gid to {}", process.user().gid()))?;
+
+    if let Some(umask) = process.user().umask() {
+        stat::umask(umask_mode(umask));
+    }
+
+    if let Some(additional_gids) = process.user().additional_gids() {
+        let additional_gids: &Vec<Gid> = &additional_gids
+            .iter()
+            .map(|gid| Gid::from_raw(*gid))
+            .collect();
+        unistd::setgroups(additional_gids).context("failed to set additional gids".to_string())?;
+    }
+    unistd::setuid(Uid::from_raw(process.user().uid()))
+        .context(format!("failed to set uid to {}", process.user().uid()))?;
+
+    prctl::set_keepcaps(false).context("failed to set PR_SET_KEEPCAPS to false")?;
+
+    // The sets are applied in order, since the effective set is cleared by `setuid` and
+    // should be raised while the permitted set is still full, the permitted set is lowered
+    // after the inheritable set is raised from it, and an ambient capability is only raised
+    // if it's in both the permitted and the inheritable sets
+    if let Some(capabilities) = process.capabilities() {
+        let capabilities_list = [
+            (capabilities.effective(), CapSet::Effective),
+            (capabilities.inheritable(), CapSet::Inheritable),
+            (capabilities.permitted(), CapSet::Permitted),
+        ];
+        for (capabilities, capabilities_set_flag) in capabilities_list.into_iter() {
+            if let Some(capabilities) = capabilities {
+                cap::set_cap(capabilities_set_flag, capabilities).context(format!(
+                    "failed to set the {:?} capabilities",
+                    capabilities_set_flag
+                ))?;
             }
         }
+        if let Some(ambient) = capabilities.ambient() {
+            cap::set_cap(CapSet::Ambient, ambient)
+                .context("failed to raise the ambient capabilities")?;
+        }
+    }
 
-        unistd::chdir(process.cwd()).context(format!(
-            "failed to change the working directory to {}",
-            process.cwd().display()
-        ))?;
+    // The kernel clears the dumpable flag after the uid changes, which makes `/proc/<pid>`
+    // owned by root. It's set again once the capabilities are reduced to the ones in the spec,
+    // so that `/proc/<pid>` is accessible to the container user, who could otherwise inspect
+    // the process while it still has the privileges of the runtime
+    prctl::set_dumpable(true).context("failed to set PR_SET_DUMPABLE to true")?;
+
+    if process.no_new_privileges() == Some(true) {
+        prctl::set_no_new_privs().context("failed to set PR_SET_NO_NEW_PRIVS")?;
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, os::unix::fs::MetadataExt};
+
+    use oci_spec::runtime::{ProcessBuilder, SchedulerBuilder, UserBuilder};
+
+    use super::*;
+    use crate::test_util;
+
+    #[test]
+    fn scheduler_nice_of_policy() {
+        let scheduler = |policy: LinuxSchedulerPolicy, nice: Option<i32>| {
+            let builder = SchedulerBuilder::default().policy(policy);
+            match nice {
+                Some(nice) => builder.nice(nice).build().unwrap(),
+                None => builder.build().unwrap(),
+            }
+        };
+        assert_eq!(
+            scheduler_nice(&scheduler(LinuxSchedulerPolicy::SchedOther, Some(5))),
+            Some(5)
+        );
+        assert_eq!(
+            scheduler_nice(&scheduler(LinuxSchedulerPolicy::SchedIdle, Some(-5))),
+            Some(-5)
+        );
+        assert_eq!(
+            scheduler_nice(&scheduler(LinuxSchedulerPolicy::SchedIdle, None)),
+            Some(19)
+        );
+        assert_eq!(
+            scheduler_nice(&scheduler(LinuxSchedulerPolicy::SchedOther, None)),
+            None
+        );
+    }
+
+    #[test]
+    fn umask_mode_keeps_permission_bits() {
+        assert_eq!(umask_mode(0o022).bits(), 0o022);
+        assert_eq!(umask_mode(0o100022).bits(), 0o022);
+        assert_eq!(umask_mode(0o7777).bits(), 0o7777);
+    }
+
+    #[test]
+    fn drop_privileges_keeps_proc_owned_by_user() {
+        if !test_util::is_root() {
+            return;
+        }
+
+        for (uid, gid) in [(0, 0), (1000, 1000)] {
+            test_util::run_in_child(|| {
+                prctl::set_dumpable(false).unwrap();
+                let process = ProcessBuilder::default()
+                    .user(UserBuilder::default().uid(uid).gid(gid).build().unwrap())
+                    .build()
+                    .unwrap();
+                drop_privileges(&process).unwrap();
+
+                assert!(prctl::get_dumpable().unwrap());
+                let metadata = fs::metadata(format!("/proc/{}", unistd::getpid())).unwrap();
+                assert_eq!((metadata.uid(), metadata.gid()), (uid, gid));
+            });
+        }
+    }
+}
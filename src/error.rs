@@ -0,0 +1,16 @@
+use thiserror::Error;
+
+/// `ContainerError` is the error of an operation on a container, which could be
+/// distinguished from other errors with [anyhow::Error::downcast_ref].
+#[derive(Debug, Error)]
+pub enum ContainerError {
+    #[error("the container {0} exists")]
+    AlreadyExists(String),
+    #[error("the container {0} doesn't exist")]
+    NotFound(String),
+    /// The error of the deserialization is kept as the source, which is displayed by `{:#}`
+    #[error("the state of the container {0} is corrupt")]
+    CorruptState(String, #[source] serde_json::Error),
+    #[error("the container {0} is owned by the user {1}")]
+    PermissionDenied(String, u32),
+}
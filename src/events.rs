@@ -0,0 +1,167 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    os::fd::AsFd,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use nix::{
+    errno::Errno,
+    poll::{self, PollFd, PollFlags, PollTimeout},
+    sys::inotify::{AddWatchFlags, InitFlags, Inotify},
+    unistd::Pid,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    linux::process,
+    state::{State, Status},
+};
+
+/// `Event` is a lifecycle event of the container, which is emitted
+/// whenever the status of the container changes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Event {
+    pub id: String,
+    pub status: Status,
+    pub pid: i32,
+    pub timestamp: u64,
+}
+
+/// `EventLog` is the log of lifecycle events in `{container_root}/events.log`,
+/// where each line is an [Event] serialized to JSON.
+pub struct EventLog {
+    path: PathBuf,
+}
+
+impl EventLog {
+    pub fn new(container_root: &Path) -> Self {
+        EventLog {
+            path: container_root.join("events.log"),
+        }
+    }
+
+    /// `append` appends an [Event] that records the status of `state` to the log.
+    pub fn append(&self, state: &State) -> Result<()> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default();
+        let event = Event {
+            id: state.id.clone(),
+            status: state.status.clone(),
+            pid: state.pid,
+            timestamp,
+        };
+        let mut event_json =
+            serde_json::to_string(&event).context("failed to serialize the event to JSON")?;
+        event_json.push('\n');
+
+        let mut log_file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .context(format!("failed to open {}", self.path.display()))?;
+        log_file.write_all(event_json.as_bytes()).context(format!(
+            "failed to write the event to {}",
+            self.path.display()
+        ))?;
+        Ok(())
+    }
+
+    /// `tail` prints the events in the log to stdout. If `follow` is `true`, it watches the log
+    /// with `inotify` and prints new events as they arrive, until the container is stopped.
+    /// The container process is watched with [process::open_pidfd], so that the `Stopped` event
+    /// is emitted even if no other command observes the exit of the container.
+    /// For more information, see the [inotify(7)](https://man7.org/linux/man-pages/man7/inotify.7.html)
+    /// man page.
+    pub fn tail(&self, container_root: &Path, follow: bool) -> Result<()> {
+        // The watch is added before reading the log to not miss events appended in between
+        let inotify =
+            Inotify::init(InitFlags::IN_CLOEXEC).context("failed to initialize inotify")?;
+        inotify
+            .add_watch(&self.path, AddWatchFlags::IN_MODIFY)
+            .context(format!("failed to watch {}", self.path.display()))?;
+
+        let log_file =
+            File::open(&self.path).context(format!("failed to open {}", self.path.display()))?;
+        let mut reader = BufReader::new(log_file);
+        let mut pending = String::new();
+        print_events(&mut reader, &mut pending)?;
+        if !follow {
+            return Ok(());
+        }
+
+        let mut state = State::load(container_root)?;
+        let pidfd = match state.pid {
+            -1 => None,
+            pid => process::open_pidfd(Pid::from_raw(pid)).ok(),
+        };
+        if state.pid != -1 && pidfd.is_none() {
+            return emit_stopped(container_root, &mut state, &mut reader, &mut pending);
+        }
+
+        loop {
+            let mut poll_fd_list = vec![PollFd::new(inotify.as_fd(), PollFlags::POLLIN)];
+            if let Some(pidfd) = &pidfd {
+                poll_fd_list.push(PollFd::new(pidfd.as_fd(), PollFlags::POLLIN));
+            }
+            match poll::poll(&mut poll_fd_list, PollTimeout::NONE) {
+                Err(Errno::EINTR) => continue,
+                result => result.context("failed to wait for the events")?,
+            };
+
+            let process_exited = poll_fd_list
+                .get(1)
+                .and_then(|poll_fd| poll_fd.revents())
+                .is_some_and(|revents| revents.contains(PollFlags::POLLIN));
+            if process_exited {
+                return emit_stopped(container_root, &mut state, &mut reader, &mut pending);
+            }
+
+            inotify
+                .read_events()
+                .context("failed to read the inotify events")?;
+            if print_events(&mut reader, &mut pending)? {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// `emit_stopped` persists the `Stopped` status of the container, which appends
+/// the final event to the log, and prints the remaining events.
+fn emit_stopped(
+    container_root: &Path,
+    state: &mut State,
+    reader: &mut BufReader<File>,
+    pending: &mut String,
+) -> Result<()> {
+    state.refresh();
+    state.persist(container_root)?;
+    print_events(reader, pending)?;
+    Ok(())
+}
+
+/// `print_events` prints the complete lines that are appended to the log since the last call,
+/// where an incomplete line is kept in `pending` until the rest of it is written.
+/// It returns `true` if a `Stopped` event is printed.
+fn print_events(reader: &mut BufReader<File>, pending: &mut String) -> Result<bool> {
+    let mut stopped = false;
+    loop {
+        let size = reader
+            .read_line(pending)
+            .context("failed to read the event log")?;
+        if size == 0 || !pending.ends_with('\n') {
+            return Ok(stopped);
+        }
+
+        print!("{}", pending);
+        stopped = serde_json::from_str::<Event>(pending)
+            .is_ok_and(|event| event.status == Status::Stopped);
+        pending.clear();
+    }
+}
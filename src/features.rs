@@ -0,0 +1,107 @@
+use oci_spec::runtime::{LinuxNamespaceBuilder, LinuxNamespaceType};
+use serde::Serialize;
+
+use crate::{
+    linux::{
+        cgroup::{self, CgroupVersion},
+        mount::MOUNT_OPTION_LIST,
+        namespace,
+    },
+    state::OCI_VERSION,
+};
+
+const OCI_VERSION_MIN: &str = "1.0.0";
+
+/// The features supported by the runtime, which is similar to the
+/// [features structure](https://github.com/opencontainers/runtime-spec/blob/main/features.md)
+/// defined in the runtime specification. Higher-level tools could query the features
+/// to decide how to generate the bundle configuration.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Features {
+    pub oci_version_min: String,
+    pub oci_version_max: String,
+    pub hooks: Vec<String>,
+    pub mount_options: Vec<String>,
+    pub linux: LinuxFeatures,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LinuxFeatures {
+    pub namespaces: Vec<String>,
+    pub cgroup: CgroupFeatures,
+    pub seccomp: SeccompFeatures,
+}
+
+/// The cgroup versions that are detected on the host at runtime.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CgroupFeatures {
+    pub v1: bool,
+    pub v2: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SeccompFeatures {
+    pub enabled: bool,
+}
+
+impl Features {
+    /// `detect` collects the features supported by the runtime and the host.
+    pub fn detect() -> Self {
+        let hooks = [
+            "prestart",
+            "createRuntime",
+            "createContainer",
+            "startContainer",
+            "poststart",
+            "poststop",
+        ];
+
+        // The namespaces that can't be converted to clone flags are not supported
+        let namespaces = [
+            ("mount", LinuxNamespaceType::Mount),
+            ("cgroup", LinuxNamespaceType::Cgroup),
+            ("uts", LinuxNamespaceType::Uts),
+            ("ipc", LinuxNamespaceType::Ipc),
+            ("user", LinuxNamespaceType::User),
+            ("pid", LinuxNamespaceType::Pid),
+            ("network", LinuxNamespaceType::Network),
+            ("time", LinuxNamespaceType::Time),
+        ]
+        .into_iter()
+        .filter(|(_, typ)| {
+            LinuxNamespaceBuilder::default()
+                .typ(*typ)
+                .build()
+                .map(|namespace| !namespace::linux_namespace_to_clone_flags(&namespace).is_empty())
+                .unwrap_or_default()
+        })
+        .map(|(name, _)| name.to_string())
+        .collect();
+
+        let cgroup_version = cgroup::cgroup_version().ok();
+
+        Features {
+            oci_version_min: String::from(OCI_VERSION_MIN),
+            oci_version_max: String::from(OCI_VERSION),
+            hooks: hooks.iter().map(|hook| hook.to_string()).collect(),
+            mount_options: MOUNT_OPTION_LIST
+                .iter()
+                .map(|(option, _, _)| option.to_string())
+                .collect(),
+            linux: LinuxFeatures {
+                namespaces,
+                cgroup: CgroupFeatures {
+                    v1: cgroup_version == Some(CgroupVersion::V1),
+                    v2: cgroup_version == Some(CgroupVersion::V2),
+                },
+                seccomp: SeccompFeatures {
+                    enabled: cfg!(feature = "seccomp"),
+                },
+            },
+        }
+    }
+}
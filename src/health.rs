@@ -0,0 +1,424 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{Read, Write},
+    os::{fd::AsFd, unix::fs::MetadataExt},
+    path::Path,
+    process::{exit, Command, ExitStatus, Stdio},
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use log::warn;
+use nix::{
+    errno::Errno,
+    fcntl::OFlag,
+    poll::{self, PollFd, PollFlags, PollTimeout},
+    sched::{self, CloneFlags},
+    sys::wait::{self, WaitStatus},
+    unistd::{self, ForkResult, Pid},
+};
+use oci_spec::runtime::{Process, Spec};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    container::start,
+    linux::{cgroup, process, seccomp},
+    state::State,
+};
+
+/// The annotation that contains the health check command as a JSON array of the program and
+/// its arguments, such as `["curl", "-f", "http://localhost"]`.
+pub const HEALTH_CHECK_COMMAND_ANNOTATION: &str = "reno.healthcheck.command";
+/// The annotation that contains the interval between health checks in milliseconds.
+pub const HEALTH_CHECK_INTERVAL_ANNOTATION: &str = "reno.healthcheck.interval";
+/// The annotation that contains the number of consecutive failures
+/// before the container is considered unhealthy.
+pub const HEALTH_CHECK_RETRIES_ANNOTATION: &str = "reno.healthcheck.retries";
+/// The annotation in the container state that contains the health status,
+/// which is `healthy` or `unhealthy`.
+pub const HEALTH_CHECK_STATUS_ANNOTATION: &str = "reno.healthcheck.status";
+/// The annotation in the container state that contains the error of the last health check,
+/// if the health check command couldn't be run, such as a nonexistent program.
+pub const HEALTH_CHECK_ERROR_ANNOTATION: &str = "reno.healthcheck.error";
+
+/// The file in the container root that contains the [HealthStatus] of the container.
+/// The health check process writes it instead of `state.json`, which is written by the monitor
+/// process and the `reno` CLI without a lock, so that it never overwrites a newer state.
+const HEALTH_STATUS_FILE: &str = "health.json";
+
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(30);
+const DEFAULT_RETRIES: u32 = 3;
+
+/// The namespaces that the health check command joins, where the user namespace is joined first
+/// to gain the privileges over the other namespaces, and the mount namespace is joined last,
+/// since it changes the root directory to the one of the container. The PID namespace only applies
+/// to the children of the process that joins it.
+const NAMESPACE_LIST: [(&str, CloneFlags); 7] = [
+    ("user", CloneFlags::CLONE_NEWUSER),
+    ("ipc", CloneFlags::CLONE_NEWIPC),
+    ("uts", CloneFlags::CLONE_NEWUTS),
+    ("net", CloneFlags::CLONE_NEWNET),
+    ("pid", CloneFlags::CLONE_NEWPID),
+    ("cgroup", CloneFlags::CLONE_NEWCGROUP),
+    ("mnt", CloneFlags::CLONE_NEWNS),
+];
+
+/// `HealthCheck` is the health check of the container defined in the annotations of the bundle
+/// configuration, which runs `command` in the namespaces of the container every `interval`.
+/// The container is unhealthy once the command fails `retries` times in a row.
+pub struct HealthCheck {
+    command: Vec<String>,
+    interval: Duration,
+    retries: u32,
+}
+
+impl HealthCheck {
+    /// `from_annotations` reads the health check from `annotations`,
+    /// or returns `None` if the health check command is not defined.
+    pub fn from_annotations(annotations: &HashMap<String, String>) -> Result<Option<Self>> {
+        let Some(command) = annotations.get(HEALTH_CHECK_COMMAND_ANNOTATION) else {
+            return Ok(None);
+        };
+        let command: Vec<String> = serde_json::from_str(command).context(format!(
+            "the {} annotation {} is not a JSON array of strings",
+            HEALTH_CHECK_COMMAND_ANNOTATION, command
+        ))?;
+        if command.is_empty() {
+            bail!(
+                "the {} annotation is empty",
+                HEALTH_CHECK_COMMAND_ANNOTATION
+            );
+        }
+
+        let interval = match annotations.get(HEALTH_CHECK_INTERVAL_ANNOTATION) {
+            Some(interval) => Duration::from_millis(interval.parse().context(format!(
+                "the {} annotation {} is invalid",
+                HEALTH_CHECK_INTERVAL_ANNOTATION, interval
+            ))?),
+            None => DEFAULT_INTERVAL,
+        };
+        let retries = match annotations.get(HEALTH_CHECK_RETRIES_ANNOTATION) {
+            Some(retries) => retries.parse().context(format!(
+                "the {} annotation {} is invalid",
+                HEALTH_CHECK_RETRIES_ANNOTATION, retries
+            ))?,
+            None => DEFAULT_RETRIES,
+        };
+
+        Ok(Some(HealthCheck {
+            command,
+            interval,
+            retries,
+        }))
+    }
+
+    /// `check` runs the health check command in the container process `pid` and returns `true`
+    /// if the command exits successfully. The command is run by a child process that joins
+    /// the cgroups and the namespaces of the container, drops its privileges to the ones of
+    /// `process` in `spec`, and loads the seccomp filter, so that the command is confined
+    /// as the container process is. The error of the child process is sent through a pipe.
+    fn check(&self, spec: &Spec, pid: Pid) -> Result<bool> {
+        let Some(process) = spec.process() else {
+            bail!("the health check requires process in the bundle configuration");
+        };
+        let namespace_list = container_namespace_list(pid)?;
+
+        let (read_fd, write_fd) =
+            unistd::pipe2(OFlag::O_CLOEXEC).context("failed to create the pipe")?;
+        match unsafe { unistd::fork() }.context("failed to fork the health check process")? {
+            ForkResult::Parent { child } => {
+                drop(write_fd);
+                let mut error = String::new();
+                File::from(read_fd)
+                    .read_to_string(&mut error)
+                    .context("failed to read the result of the health check")?;
+                let wait_status = wait::waitpid(child, None)
+                    .context("failed to wait for the health check process")?;
+                if !error.is_empty() {
+                    bail!(error);
+                }
+                Ok(wait_status == WaitStatus::Exited(child, 0))
+            }
+            ForkResult::Child => {
+                drop(read_fd);
+                let code = match self.run_command(spec, process, pid, &namespace_list) {
+                    Ok(status) if status.success() => 0,
+                    Ok(_) => 1,
+                    Err(error) => {
+                        let _ = File::from(write_fd).write_all(format!("{:#}", error).as_bytes());
+                        1
+                    }
+                };
+                exit(code);
+            }
+        }
+    }
+
+    /// `run_command` joins the container process `pid` and runs the health check command
+    /// with the environment and the working directory of `process`. The command is forked,
+    /// since joining the PID namespace only affects the children of the current process.
+    fn run_command(
+        &self,
+        spec: &Spec,
+        process: &Process,
+        pid: Pid,
+        namespace_list: &[(File, CloneFlags)],
+    ) -> Result<ExitStatus> {
+        cgroup::join_cgroups(pid)?;
+        for (file, flag) in namespace_list {
+            sched::setns(file.as_fd(), *flag)
+                .context(format!("failed to join the namespace {:?}", flag))?;
+        }
+        start::drop_privileges(process)?;
+
+        // The notification listener is closed, since no supervisor receives it,
+        // which makes the intercepted system calls fail with `ENOSYS`
+        drop(seccomp::apply_seccomp(spec)?);
+
+        let env_list = process.env().clone().unwrap_or_default();
+        Command::new(&self.command[0])
+            .args(&self.command[1..])
+            .env_clear()
+            .envs(env_list.iter().filter_map(|env| env.split_once('=')))
+            .current_dir(process.cwd())
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .context(format!(
+                "failed to run the health check {}",
+                self.command[0]
+            ))
+    }
+}
+
+/// `HealthStatus` is the result of the health checks, which is written to [HEALTH_STATUS_FILE].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct HealthStatus {
+    status: String,
+    error: Option<String>,
+}
+
+/// `write_health_status` replaces [HEALTH_STATUS_FILE] in `container_root` with `health_status`,
+/// which is written to a temporary file and renamed, so that a reader never sees a partial file.
+fn write_health_status(container_root: &Path, health_status: &HealthStatus) -> Result<()> {
+    let health_status =
+        serde_json::to_string(health_status).context("failed to serialize the health status")?;
+    let path = container_root.join(HEALTH_STATUS_FILE);
+    let temporary_path = path.with_extension("json.tmp");
+    fs::write(&temporary_path, health_status)
+        .context(format!("failed to write {}", temporary_path.display()))?;
+    fs::rename(&temporary_path, &path).context(format!("failed to write {}", path.display()))?;
+    Ok(())
+}
+
+/// `annotate_health_status` adds the health status in [HEALTH_STATUS_FILE] of `container_root`
+/// to the annotations of `state`, if the health check is running.
+pub fn annotate_health_status(container_root: &Path, state: &mut State) -> Result<()> {
+    let path = container_root.join(HEALTH_STATUS_FILE);
+    let health_status = match fs::read_to_string(&path) {
+        Ok(health_status) => health_status,
+        Err(_) => return Ok(()),
+    };
+    let health_status: HealthStatus = serde_json::from_str(&health_status)
+        .context(format!("failed to parse {}", path.display()))?;
+
+    let annotations = state.annotations.get_or_insert_with(HashMap::new);
+    annotations.insert(
+        String::from(HEALTH_CHECK_STATUS_ANNOTATION),
+        health_status.status,
+    );
+    match health_status.error {
+        Some(error) => annotations.insert(String::from(HEALTH_CHECK_ERROR_ANNOTATION), error),
+        None => annotations.remove(HEALTH_CHECK_ERROR_ANNOTATION),
+    };
+    Ok(())
+}
+
+/// `container_namespace_list` opens the namespaces of the container process `pid`
+/// in [NAMESPACE_LIST] that are different from the ones of the runtime.
+/// For more information, see the [setns(2)](https://man7.org/linux/man-pages/man2/setns.2.html)
+/// man page.
+fn container_namespace_list(pid: Pid) -> Result<Vec<(File, CloneFlags)>> {
+    let mut namespace_list = Vec::new();
+    for (namespace, flag) in NAMESPACE_LIST {
+        let path = format!("/proc/{}/ns/{}", pid, namespace);
+        let runtime_namespace = fs::metadata(format!("/proc/self/ns/{}", namespace))
+            .context(format!("failed to inspect the {} namespace", namespace))?;
+        let container_namespace =
+            fs::metadata(&path).context(format!("failed to inspect {}", path))?;
+        if runtime_namespace.ino() == container_namespace.ino()
+            && runtime_namespace.dev() == container_namespace.dev()
+        {
+            continue;
+        }
+
+        let file = File::open(&path).context(format!("failed to open {}", path))?;
+        namespace_list.push((file, flag));
+    }
+    Ok(namespace_list)
+}
+
+/// `monitor_health` forks a monitor process that runs `health_check` for the container process
+/// `pid` of the bundle configuration `spec` every interval, and writes the health status to [HEALTH_STATUS_FILE] in `container_root`,
+/// which is shown in the annotations of the container state. The monitor process is detached
+/// with [process::daemonize], and exits once the container process exits.
+pub fn monitor_health(
+    container_root: &Path,
+    spec: &Spec,
+    health_check: HealthCheck,
+    pid: Pid,
+) -> Result<()> {
+    match unsafe { unistd::fork() }.context("failed to fork the health check process")? {
+        ForkResult::Parent { .. } => Ok(()),
+        ForkResult::Child => {
+            let result = process::daemonize()
+                .and_then(|_| run_health_check(container_root, spec, &health_check, pid));
+            exit(if result.is_ok() { 0 } else { 1 });
+        }
+    }
+}
+
+fn run_health_check(
+    container_root: &Path,
+    spec: &Spec,
+    health_check: &HealthCheck,
+    pid: Pid,
+) -> Result<()> {
+    let pidfd = process::open_pidfd(pid)?;
+    let timeout = PollTimeout::try_from(health_check.interval).unwrap_or(PollTimeout::MAX);
+
+    let mut failures = 0;
+    loop {
+        // The container process exits if the file descriptor is readable before the timeout
+        let mut poll_fd_list = [PollFd::new(pidfd.as_fd(), PollFlags::POLLIN)];
+        match poll::poll(&mut poll_fd_list, timeout) {
+            Err(Errno::EINTR) => continue,
+            Ok(0) => (),
+            result => {
+                result.context("failed to wait for the container process")?;
+                return Ok(());
+            }
+        }
+
+        // The command that can't be run counts as a failure, whose error is recorded
+        // in the health status, since the monitor process has no standard streams
+        let error = match health_check.check(spec, pid) {
+            Ok(true) => {
+                failures = 0;
+                None
+            }
+            Ok(false) => {
+                failures += 1;
+                None
+            }
+            Err(error) => {
+                warn!("{:#}", error);
+                failures += 1;
+                Some(format!("{:#}", error))
+            }
+        };
+        let status = if failures >= health_check.retries.max(1) {
+            "unhealthy"
+        } else {
+            "healthy"
+        };
+
+        write_health_status(
+            container_root,
+            &HealthStatus {
+                status: String::from(status),
+                error,
+            },
+        )?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+
+    fn test_health_check(command: &str) -> Result<Option<HealthCheck>> {
+        let annotations = HashMap::from([(
+            String::from(HEALTH_CHECK_COMMAND_ANNOTATION),
+            String::from(command),
+        )]);
+        HealthCheck::from_annotations(&annotations)
+    }
+
+    #[test]
+    fn from_annotations_with_quoted_argument() {
+        let health_check = test_health_check(r#"["sh", "-c", "test -f /tmp/ready"]"#)
+            .unwrap()
+            .unwrap();
+        assert_eq!(health_check.command, ["sh", "-c", "test -f /tmp/ready"]);
+        assert_eq!(health_check.interval, DEFAULT_INTERVAL);
+        assert_eq!(health_check.retries, DEFAULT_RETRIES);
+    }
+
+    #[test]
+    fn from_annotations_with_invalid_command() {
+        assert!(test_health_check("curl -f http://localhost").is_err());
+        assert!(test_health_check("[]").is_err());
+        assert!(HealthCheck::from_annotations(&HashMap::new())
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn check_without_process() {
+        let health_check = test_health_check(r#"["true"]"#).unwrap().unwrap();
+        let mut spec = Spec::default();
+        spec.set_process(None);
+        assert!(health_check.check(&spec, unistd::getpid()).is_err());
+    }
+
+    #[test]
+    fn check_in_current_process() {
+        if !test_util::is_root() {
+            return;
+        }
+
+        // The current process is the container process, whose namespaces are the same
+        let spec = Spec::default();
+        let pid = unistd::getpid();
+        let health_check = test_health_check(r#"["true"]"#).unwrap().unwrap();
+        assert!(health_check.check(&spec, pid).unwrap());
+        let health_check = test_health_check(r#"["false"]"#).unwrap().unwrap();
+        assert!(!health_check.check(&spec, pid).unwrap());
+
+        let health_check = test_health_check(r#"["/nonexistent"]"#).unwrap().unwrap();
+        let error = health_check.check(&spec, pid).unwrap_err();
+        assert!(format!("{:#}", error).contains("failed to run the health check /nonexistent"));
+    }
+
+    #[test]
+    fn annotate_health_status_without_health_check() {
+        let container_root = test_util::test_directory("health", "none");
+        let mut state = State::new(String::from("test"), container_root.to_path_buf());
+        annotate_health_status(&container_root, &mut state).unwrap();
+        assert_eq!(state.annotations, Some(HashMap::new()));
+    }
+
+    #[test]
+    fn annotate_health_status_with_error() {
+        let container_root = test_util::test_directory("health", "error");
+        let health_status = HealthStatus {
+            status: String::from("unhealthy"),
+            error: Some(String::from("failed to run the health check")),
+        };
+        write_health_status(&container_root, &health_status).unwrap();
+
+        let mut state = State::new(String::from("test"), container_root.to_path_buf());
+        annotate_health_status(&container_root, &mut state).unwrap();
+        let annotations = state.annotations.unwrap();
+        assert_eq!(annotations[HEALTH_CHECK_STATUS_ANNOTATION], "unhealthy");
+        assert_eq!(
+            annotations[HEALTH_CHECK_ERROR_ANNOTATION],
+            "failed to run the health check"
+        );
+    }
+}
@@ -1,11 +1,12 @@
 use std::{
+    collections::HashMap,
     io::Write,
     os::unix::process::CommandExt,
     process::{Command, Stdio},
 };
 
 use anyhow::{bail, Context, Result};
-use oci_spec::runtime::Hook;
+use oci_spec::runtime::{Hook, Spec};
 
 use crate::state::State;
 
@@ -16,16 +17,21 @@ pub fn run_hook(state: &State, hook: &Hook) -> Result<()> {
     command.env_clear();
 
     if let Some(env_list) = hook.env() {
+        let mut existing_env = HashMap::new();
         for env in env_list {
             if let Some((k, v)) = env.split_once('=') {
-                command.env(k, v);
+                let v = expand_env_vars(v, &existing_env);
+                command.env(k, &v);
+                existing_env.insert(k.to_string(), v);
             }
         }
     }
 
-    if let Some(args) = hook.args() {
-        command.arg0(&args[0]);
-        command.args(&args[1..]);
+    if let Some([arg0, args @ ..]) = hook.args().as_deref() {
+        command.arg0(arg0);
+        command.args(args);
+    } else {
+        command.arg0(hook.path());
     }
 
     let mut hook_process = command
@@ -33,12 +39,15 @@ pub fn run_hook(state: &State, hook: &Hook) -> Result<()> {
         .spawn()
         .context("failed to spawn the hook process")?;
 
-    if let Some(mut stdin) = hook_process.stdin.as_ref() {
+    if let Some(mut stdin) = hook_process.stdin.take() {
         let state_json =
             serde_json::to_string(state).context("failed to serialize the state to JSON")?;
         stdin
             .write_all(state_json.as_bytes())
             .context("failed to write the state to the hook standard input")?;
+
+        // Close the standard input to let the hook read until EOF
+        drop(stdin);
     }
 
     let status = hook_process
@@ -54,3 +63,136 @@ pub fn run_hook(state: &State, hook: &Hook) -> Result<()> {
         bail!("failed to run the hook")
     }
 }
+
+/// The annotation that contains the path of an executable that validates the bundle configuration
+/// before the container is created.
+pub const PRE_CREATE_HOOK_ANNOTATION: &str = "org.reno.preCreateHook";
+
+/// `run_pre_create_hook` invokes the executable in the [PRE_CREATE_HOOK_ANNOTATION] annotation
+/// of `spec` if it's defined, which receives the bundle configuration as JSON on its standard input,
+/// and vetoes the creation of the container if it exits with a non-zero status.
+/// The executable runs in the context of the host, before any namespace or file is created,
+/// with the environment and the privileges of the runtime.
+pub fn run_pre_create_hook(spec: &Spec) -> Result<()> {
+    let Some(path) = spec
+        .annotations()
+        .as_ref()
+        .and_then(|annotations| annotations.get(PRE_CREATE_HOOK_ANNOTATION))
+    else {
+        return Ok(());
+    };
+
+    let mut hook_process = Command::new(path)
+        .stdin(Stdio::piped())
+        .spawn()
+        .context(format!("failed to spawn the pre-create hook {}", path))?;
+
+    if let Some(mut stdin) = hook_process.stdin.take() {
+        let spec_json =
+            serde_json::to_string(spec).context("failed to serialize the bundle configuration")?;
+        stdin
+            .write_all(spec_json.as_bytes())
+            .context("failed to write the bundle configuration to the hook standard input")?;
+
+        // Close the standard input to let the hook read until EOF
+        drop(stdin);
+    }
+
+    let status = hook_process
+        .wait()
+        .context("failed to wait the pre-create hook to exit")?;
+    if !status.success() {
+        bail!(
+            "the container is rejected by the pre-create hook {}: {}",
+            path,
+            status
+        );
+    }
+    Ok(())
+}
+
+/// `expand_env_vars` replaces the `$VAR` and `${VAR}` references in `value` with the variables
+/// in `existing_env`, which are the entries before it in `hook.env`, such as `$PATH`
+/// in `PATH=/usr/local/sbin:$PATH`. An undefined variable is replaced with an empty string,
+/// and a `$` that isn't followed by a variable name is kept as it is.
+fn expand_env_vars(value: &str, existing_env: &HashMap<String, String>) -> String {
+    let is_name_char = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let mut expanded = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(index) = rest.find('$') {
+        expanded.push_str(&rest[..index]);
+        rest = &rest[index + 1..];
+
+        let (name, remaining) = if let Some(braced) = rest.strip_prefix('{') {
+            match braced.find('}') {
+                Some(end) if braced[..end].chars().all(is_name_char) && end > 0 => {
+                    (&braced[..end], &braced[end + 1..])
+                }
+                _ => ("", rest),
+            }
+        } else {
+            let end = rest.find(|c| !is_name_char(c)).unwrap_or(rest.len());
+            (&rest[..end], &rest[end..])
+        };
+
+        if name.is_empty() {
+            expanded.push('$');
+        } else if let Some(value) = existing_env.get(name) {
+            expanded.push_str(value);
+        }
+        rest = remaining;
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, os::unix::fs::PermissionsExt, path::PathBuf};
+
+    use oci_spec::runtime::HookBuilder;
+
+    use super::*;
+
+    #[test]
+    fn run_hook_without_args() {
+        let hook_path = std::env::temp_dir().join(format!("reno-hook-{}", std::process::id()));
+        fs::write(
+            &hook_path,
+            format!(
+                "#!/bin/sh\ncat > /dev/null\ntest \"$0\" = {} || exit 1\n",
+                hook_path.display()
+            ),
+        )
+        .unwrap();
+        fs::set_permissions(&hook_path, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let state = State::new(String::from("test"), PathBuf::from("/bundle"));
+        let hook = HookBuilder::default().path(&hook_path).build().unwrap();
+        assert!(run_hook(&state, &hook).is_ok());
+
+        let hook = HookBuilder::default()
+            .path(&hook_path)
+            .args(Vec::new())
+            .build()
+            .unwrap();
+        assert!(run_hook(&state, &hook).is_ok());
+        fs::remove_file(&hook_path).unwrap();
+    }
+
+    #[test]
+    fn run_hook_with_args() {
+        let state = State::new(String::from("test"), PathBuf::from("/bundle"));
+        let hook = HookBuilder::default()
+            .path("/bin/sh")
+            .args(vec![
+                String::from("sh"),
+                String::from("-c"),
+                String::from("cat > /dev/null; test \"$0\" = sh && exit 3"),
+            ])
+            .build()
+            .unwrap();
+        let error = run_hook(&state, &hook).unwrap_err();
+        assert!(error.to_string().contains("exit status 3"));
+    }
+}
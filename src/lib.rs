@@ -1,6 +1,13 @@
 pub mod cli;
 pub mod container;
+pub mod error;
+pub mod events;
+pub mod features;
+pub mod health;
 pub mod hook;
 pub mod linux;
 pub mod socket;
 pub mod state;
+#[cfg(test)]
+mod test_util;
+pub mod validation;
@@ -1,8 +1,8 @@
 use std::collections::HashSet;
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use caps::{self, CapSet, Capability as CapsCap};
-use oci_spec::runtime::{Capabilities, Capability as OCICap};
+use oci_spec::runtime::{Capabilities, Capability as OCICap, LinuxCapabilities};
 
 /// `set_cap` sets Linux capabilities for the container process.
 /// It drops extra capabilities for the bounding set, and raises capabilities for other sets.
@@ -28,6 +28,57 @@ pub fn set_cap(cap_set: CapSet, capabilities: &Capabilities) -> Result<()> {
     Ok(())
 }
 
+/// `validate_ambient` checks that every ambient capability in `capabilities` is also in
+/// the permitted and the inheritable sets, since the kernel rejects raising an ambient capability
+/// that is not in both of them with `EPERM`.
+pub fn validate_ambient(capabilities: &LinuxCapabilities) -> Result<()> {
+    let Some(ambient) = capabilities.ambient() else {
+        return Ok(());
+    };
+    let empty = Capabilities::new();
+    let permitted = capabilities.permitted().as_ref().unwrap_or(&empty);
+    let inheritable = capabilities.inheritable().as_ref().unwrap_or(&empty);
+
+    let mut invalid_list: Vec<String> = ambient
+        .iter()
+        .filter(|cap| !permitted.contains(cap) || !inheritable.contains(cap))
+        .map(|cap| oci_cap_to_caps_cap(cap).to_string())
+        .collect();
+    if !invalid_list.is_empty() {
+        invalid_list.sort();
+        bail!(
+            "the ambient capabilities {} should be in both the permitted and the inheritable capabilities",
+            invalid_list.join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// `validate_effective` checks that every effective capability in `capabilities` is also in
+/// the permitted set, since the kernel rejects raising an effective capability that is not
+/// permitted with `EPERM`. The check is skipped if the permitted set is not defined, since
+/// the permitted set of the process is left unchanged in that case.
+pub fn validate_effective(capabilities: &LinuxCapabilities) -> Result<()> {
+    let (Some(effective), Some(permitted)) = (capabilities.effective(), capabilities.permitted())
+    else {
+        return Ok(());
+    };
+
+    let mut invalid_list: Vec<String> = effective
+        .iter()
+        .filter(|cap| !permitted.contains(cap))
+        .map(|cap| oci_cap_to_caps_cap(cap).to_string())
+        .collect();
+    if !invalid_list.is_empty() {
+        invalid_list.sort();
+        bail!(
+            "the effective capabilities {} should be in the permitted capabilities",
+            invalid_list.join(", ")
+        );
+    }
+    Ok(())
+}
+
 /// `oci_cap_to_caps_cap` converts [OCICap] to [CapsCap].
 fn oci_cap_to_caps_cap(cap: &OCICap) -> CapsCap {
     match cap {
@@ -74,3 +125,65 @@ fn oci_cap_to_caps_cap(cap: &OCICap) -> CapsCap {
         OCICap::WakeAlarm => CapsCap::CAP_WAKE_ALARM,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn capabilities(
+        permitted: &[OCICap],
+        inheritable: &[OCICap],
+        ambient: &[OCICap],
+    ) -> LinuxCapabilities {
+        let mut capabilities = LinuxCapabilities::default();
+        capabilities.set_permitted(Some(permitted.iter().copied().collect()));
+        capabilities.set_inheritable(Some(inheritable.iter().copied().collect()));
+        capabilities.set_ambient(Some(ambient.iter().copied().collect()));
+        capabilities
+    }
+
+    #[test]
+    fn validate_ambient_in_permitted_and_inheritable() {
+        let capabilities = capabilities(
+            &[OCICap::NetBindService, OCICap::Chown],
+            &[OCICap::NetBindService],
+            &[OCICap::NetBindService],
+        );
+        assert!(validate_ambient(&capabilities).is_ok());
+
+        let mut without_ambient = capabilities.clone();
+        without_ambient.set_ambient(None);
+        assert!(validate_ambient(&without_ambient).is_ok());
+    }
+
+    #[test]
+    fn validate_ambient_reports_every_capability() {
+        let capabilities = capabilities(
+            &[OCICap::NetBindService, OCICap::Chown],
+            &[OCICap::NetBindService, OCICap::Kill],
+            &[OCICap::NetBindService, OCICap::Chown, OCICap::Kill],
+        );
+        let error = validate_ambient(&capabilities).unwrap_err().to_string();
+        assert!(error.contains("CAP_CHOWN, CAP_KILL"), "{}", error);
+        assert!(!error.contains("CAP_NET_BIND_SERVICE"), "{}", error);
+    }
+
+    #[test]
+    fn validate_effective_in_permitted() {
+        let mut capabilities = capabilities(&[OCICap::NetBindService, OCICap::Chown], &[], &[]);
+        capabilities.set_effective(Some([OCICap::Chown].into_iter().collect()));
+        assert!(validate_effective(&capabilities).is_ok());
+
+        capabilities.set_effective(Some(
+            [OCICap::Chown, OCICap::Kill, OCICap::SysAdmin]
+                .into_iter()
+                .collect(),
+        ));
+        let error = validate_effective(&capabilities).unwrap_err().to_string();
+        assert!(error.contains("CAP_KILL, CAP_SYS_ADMIN"), "{}", error);
+        assert!(!error.contains("CAP_CHOWN"), "{}", error);
+
+        capabilities.set_permitted(None);
+        assert!(validate_effective(&capabilities).is_ok());
+    }
+}
@@ -0,0 +1,1099 @@
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    fs,
+    io::ErrorKind,
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use log::{debug, warn};
+use nix::{
+    sys::{
+        stat,
+        statfs::{self, CGROUP2_SUPER_MAGIC},
+    },
+    unistd::{self, Pid},
+};
+use oci_spec::runtime::{
+    LinuxCpu, LinuxDevice, LinuxDeviceCgroup, LinuxDeviceCgroupBuilder, LinuxDeviceType,
+    LinuxMemory, LinuxPids, LinuxRdma, LinuxResources, Spec,
+};
+
+use crate::linux::{cgroup_systemd::SystemdManager, device};
+
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// The annotation that makes the OOM killer kill every process in the container together
+/// on cgroup v2, instead of a single process, if it's `true`.
+pub const OOM_GROUP_ANNOTATION: &str = "org.reno.memory.oomGroup";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CgroupVersion {
+    V1,
+    V2,
+}
+
+/// `cgroup_version` detects the cgroup version of the host based on the file system type of
+/// `/sys/fs/cgroup`, which is `cgroup2` if the host uses the unified hierarchy.
+/// The hybrid hierarchy is treated as cgroup v1.
+/// For more information, see the [cgroups(7)](https://man7.org/linux/man-pages/man7/cgroups.7.html)
+/// man page.
+pub fn cgroup_version() -> Result<CgroupVersion> {
+    let cgroup_root_stat = statfs::statfs(CGROUP_ROOT).context(format!(
+        "failed to inspect the file system of {}",
+        CGROUP_ROOT
+    ))?;
+    if cgroup_root_stat.filesystem_type() == CGROUP2_SUPER_MAGIC {
+        Ok(CgroupVersion::V2)
+    } else {
+        Ok(CgroupVersion::V1)
+    }
+}
+
+/// `container_cgroup_path` returns the path of the container cgroup relative to the cgroup root,
+/// which is `linux.cgroupsPath` in the bundle configuration or `reno/<id>` by default.
+pub fn container_cgroup_path(spec: &Spec, id: &str) -> PathBuf {
+    match spec
+        .linux()
+        .as_ref()
+        .and_then(|linux| linux.cgroups_path().as_ref())
+    {
+        Some(cgroups_path) => {
+            PathBuf::from(cgroups_path.display().to_string().trim_start_matches('/'))
+        }
+        None => Path::new("reno").join(id),
+    }
+}
+
+/// `cgroup_requested` checks if the bundle configuration asks for a container cgroup,
+/// which is the case if `linux.cgroupsPath` or `linux.resources` is defined.
+/// Otherwise, the container stays in the cgroup of the runtime, and no cgroup is created.
+pub fn cgroup_requested(spec: &Spec) -> bool {
+    spec.linux()
+        .as_ref()
+        .is_some_and(|linux| linux.cgroups_path().is_some() || linux.resources().is_some())
+}
+
+/// `process_cgroup_paths` returns the cgroups of the process `pid` in every hierarchy that is
+/// mounted under `/sys/fs/cgroup`, which are read from `/proc/<pid>/cgroup`. The named hierarchies
+/// on cgroup v1, such as `name=systemd`, are mounted at `/sys/fs/cgroup/<name>`, and the unified
+/// hierarchy is mounted at `/sys/fs/cgroup/unified` on the hybrid hierarchy.
+pub fn process_cgroup_paths(pid: Pid) -> Result<Vec<PathBuf>> {
+    let cgroup_version = cgroup_version()?;
+    let process_cgroups = procfs::process::Process::new(pid.as_raw())
+        .and_then(|process| process.cgroups())
+        .context(format!("failed to read the cgroups of the process {}", pid))?;
+
+    let mut cgroup_path_list = Vec::new();
+    for process_cgroup in process_cgroups {
+        let hierarchy_path = if process_cgroup.hierarchy == 0 {
+            match cgroup_version {
+                CgroupVersion::V1 => Path::new(CGROUP_ROOT).join("unified"),
+                CgroupVersion::V2 => PathBuf::from(CGROUP_ROOT),
+            }
+        } else {
+            let controllers: Vec<&str> = process_cgroup
+                .controllers
+                .iter()
+                .map(|controller| controller.trim_start_matches("name="))
+                .collect();
+            Path::new(CGROUP_ROOT).join(controllers.join(","))
+        };
+        if hierarchy_path.is_dir() {
+            cgroup_path_list
+                .push(hierarchy_path.join(process_cgroup.pathname.trim_start_matches('/')));
+        }
+    }
+    Ok(cgroup_path_list)
+}
+
+/// `join_cgroups` moves the current process into the cgroups of the process `pid`
+/// in every hierarchy, such as a process that runs in a container after it's started.
+pub fn join_cgroups(pid: Pid) -> Result<()> {
+    for cgroup_path in process_cgroup_paths(pid)? {
+        write_cgroup_file(&cgroup_path, "cgroup.procs", unistd::getpid())?;
+    }
+    Ok(())
+}
+
+/// `CgroupManager` creates and removes the container cgroup on a specific cgroup version.
+/// For more information, see the [cgroups(7)](https://man7.org/linux/man-pages/man7/cgroups.7.html)
+/// man page.
+pub trait CgroupManager {
+    /// `apply` creates the container cgroup, applies the resource limits in `resources`,
+    /// and moves the process `pid` into the cgroup.
+    fn apply(&self, resources: Option<&LinuxResources>, pid: Pid) -> Result<()>;
+
+    /// `update` applies the resource limits in `resources` to the existing container cgroup,
+    /// where the limits that are not in `resources` are unchanged.
+    fn update(&self, resources: &LinuxResources) -> Result<()>;
+
+    /// `remove` removes the container cgroup, which should not contain any process.
+    fn remove(&self) -> Result<()>;
+}
+
+/// `cgroup_manager` returns the [CgroupManager] of the container `id`, which is the [SystemdManager]
+/// if `systemd_cgroup` is `true`, or the manager of the cgroup version on the host otherwise.
+pub fn cgroup_manager(
+    spec: &Spec,
+    id: &str,
+    systemd_cgroup: bool,
+) -> Result<Box<dyn CgroupManager>> {
+    if systemd_cgroup {
+        return Ok(Box::new(SystemdManager::new(id)));
+    }
+
+    let cgroup_path = container_cgroup_path(spec, id);
+    let cgroup_manager: Box<dyn CgroupManager> = match cgroup_version()? {
+        CgroupVersion::V1 => {
+            let devices = spec
+                .linux()
+                .as_ref()
+                .and_then(|linux| linux.devices().clone())
+                .unwrap_or_default();
+            Box::new(V1Manager::new(&cgroup_path, devices))
+        }
+        CgroupVersion::V2 => {
+            let oom_group = spec
+                .annotations()
+                .as_ref()
+                .and_then(|annotations| annotations.get(OOM_GROUP_ANNOTATION))
+                .is_some_and(|value| value == "true");
+            Box::new(V2Manager::new(&cgroup_path, oom_group))
+        }
+    };
+    Ok(cgroup_manager)
+}
+
+/// `update_resources` applies `resources` to the container cgroup with [CgroupManager::update]
+/// one controller at a time, in the order of memory, cpu, cpuset, and pids, so that a controller
+/// that fails doesn't prevent the others from being updated, and the errors of the controllers
+/// are aggregated. The other resources are skipped, since they can't be updated.
+pub fn update_resources(
+    cgroup_manager: &dyn CgroupManager,
+    resources: &LinuxResources,
+) -> Result<()> {
+    let mut controller_list: Vec<(&str, LinuxResources)> = Vec::new();
+    if let Some(memory) = resources.memory() {
+        let mut memory_resources = LinuxResources::default();
+        memory_resources.set_memory(Some(*memory));
+        controller_list.push(("memory", memory_resources));
+    }
+    if let Some(cpu) = resources.cpu() {
+        let mut cpu_limits = cpu.clone();
+        cpu_limits.set_cpus(None);
+        cpu_limits.set_mems(None);
+        if cpu_limits != LinuxCpu::default() {
+            let mut cpu_resources = LinuxResources::default();
+            cpu_resources.set_cpu(Some(cpu_limits));
+            controller_list.push(("cpu", cpu_resources));
+        }
+
+        if cpu.cpus().is_some() || cpu.mems().is_some() {
+            let mut cpuset = LinuxCpu::default();
+            cpuset.set_cpus(cpu.cpus().clone());
+            cpuset.set_mems(cpu.mems().clone());
+            let mut cpuset_resources = LinuxResources::default();
+            cpuset_resources.set_cpu(Some(cpuset));
+            controller_list.push(("cpuset", cpuset_resources));
+        }
+    }
+    if let Some(pids) = resources.pids() {
+        let mut pids_resources = LinuxResources::default();
+        pids_resources.set_pids(Some(*pids));
+        controller_list.push(("pids", pids_resources));
+    }
+
+    if resources.devices().is_some()
+        || resources.block_io().is_some()
+        || resources.hugepage_limits().is_some()
+        || resources.network().is_some()
+        || resources.rdma().is_some()
+        || resources.unified().is_some()
+    {
+        warn!("skipping the resource limits other than memory, cpu, cpuset, and pids, which can't be updated");
+    }
+
+    let error_list: Vec<String> = controller_list
+        .iter()
+        .filter_map(|(controller, resources)| {
+            cgroup_manager
+                .update(resources)
+                .err()
+                .map(|error| format!("{}: {:#}", controller, error))
+        })
+        .collect();
+    if !error_list.is_empty() {
+        bail!(
+            "failed to update the resource limits of the container: {}",
+            error_list.join("; ")
+        );
+    }
+    Ok(())
+}
+
+/// `V1Manager` manages the container cgroup on the legacy hierarchy,
+/// where each controller is mounted at `/sys/fs/cgroup/<controller>`
+/// and the container has a cgroup in each of them.
+pub struct V1Manager {
+    cgroup_path: PathBuf,
+    /// The devices in `linux.devices`, which are always accessible in the container
+    devices: Vec<LinuxDevice>,
+}
+
+impl V1Manager {
+    const CONTROLLER_LIST: [&'static str; 5] = ["cpu", "devices", "memory", "pids", "rdma"];
+
+    pub fn new(cgroup_path: &Path, devices: Vec<LinuxDevice>) -> Self {
+        V1Manager {
+            cgroup_path: cgroup_path.to_path_buf(),
+            devices,
+        }
+    }
+
+    /// `container_device_rules` converts the devices in `linux.devices` to the rules that allow
+    /// the access to them, where the named pipes are skipped, since they're not controlled
+    /// by the device whitelist controller.
+    fn container_device_rules(&self) -> Result<Vec<LinuxDeviceCgroup>> {
+        let mut rule_list = Vec::new();
+        for device in &self.devices {
+            let typ = match device.typ() {
+                LinuxDeviceType::C | LinuxDeviceType::U => LinuxDeviceType::C,
+                LinuxDeviceType::B => LinuxDeviceType::B,
+                _ => continue,
+            };
+            let dev = device::device_number(device)?;
+            rule_list.push(
+                LinuxDeviceCgroupBuilder::default()
+                    .allow(true)
+                    .typ(typ)
+                    .major(stat::major(dev) as i64)
+                    .minor(stat::minor(dev) as i64)
+                    .access("rwm")
+                    .build()?,
+            );
+        }
+        Ok(rule_list)
+    }
+
+    /// `controller_path` returns the container cgroup in the hierarchy of `controller`.
+    fn controller_path(&self, controller: &str) -> PathBuf {
+        Path::new(CGROUP_ROOT)
+            .join(controller)
+            .join(&self.cgroup_path)
+    }
+
+    /// `controller_path_list` returns the container cgroup in the hierarchy of each controller
+    /// that is mounted on the host.
+    fn controller_path_list(&self) -> Vec<PathBuf> {
+        Self::CONTROLLER_LIST
+            .iter()
+            .filter(|controller| Path::new(CGROUP_ROOT).join(controller).exists())
+            .map(|controller| self.controller_path(controller))
+            .collect()
+    }
+
+    /// `apply_resources` applies the resource limits in `resources` to the container cgroup
+    /// in the hierarchy of each controller.
+    fn apply_resources(&self, resources: &LinuxResources) -> Result<()> {
+        if let Some(memory) = resources.memory() {
+            apply_memory_v1(&self.controller_path("memory"), memory)?;
+        }
+        if let Some(cpu) = resources.cpu() {
+            apply_cpu_v1(&self.controller_path("cpu"), cpu)?;
+        }
+        if let Some(pids) = resources.pids() {
+            apply_pids(&self.controller_path("pids"), pids)?;
+        }
+        if let Some(devices) = resources.devices() {
+            let rule_list = device_rules_v1(devices, &self.container_device_rules()?);
+            for (file, rule) in rule_list {
+                write_cgroup_file(&self.controller_path("devices"), file, rule)?;
+            }
+        }
+        if let Some(rdma) = resources.rdma() {
+            if Path::new(CGROUP_ROOT).join("rdma").exists() {
+                apply_rdma_limits(&self.controller_path("rdma"), rdma)?;
+            } else {
+                debug!("skipping the RDMA limits, since the rdma controller is not mounted");
+            }
+        }
+        Ok(())
+    }
+}
+
+impl CgroupManager for V1Manager {
+    fn apply(&self, resources: Option<&LinuxResources>, pid: Pid) -> Result<()> {
+        for controller_path in self.controller_path_list() {
+            create_cgroup_directory(&controller_path)?;
+        }
+
+        if let Some(resources) = resources {
+            self.apply_resources(resources)?;
+        }
+
+        for controller_path in self.controller_path_list() {
+            write_cgroup_file(&controller_path, "cgroup.procs", pid)?;
+        }
+        Ok(())
+    }
+
+    fn update(&self, resources: &LinuxResources) -> Result<()> {
+        for controller_path in self.controller_path_list() {
+            ensure_cgroup_exists(&controller_path)?;
+        }
+        self.apply_resources(resources)
+    }
+
+    fn remove(&self) -> Result<()> {
+        for controller_path in self.controller_path_list() {
+            remove_cgroup_directory(&controller_path)?;
+        }
+        Ok(())
+    }
+}
+
+/// `V2Manager` manages the container cgroup on the unified hierarchy,
+/// where every controller is available in a single hierarchy at `/sys/fs/cgroup`.
+pub struct V2Manager {
+    cgroup_path: PathBuf,
+    oom_group: bool,
+}
+
+impl V2Manager {
+    const CONTROLLER_LIST: [&'static str; 1] = ["memory"];
+
+    pub fn new(cgroup_path: &Path, oom_group: bool) -> Self {
+        V2Manager {
+            cgroup_path: Path::new(CGROUP_ROOT).join(cgroup_path),
+            oom_group,
+        }
+    }
+
+    /// `enable_controllers` enables the controllers that are used by `resources`
+    /// for the container cgroup.
+    fn enable_controllers(&self, resources: Option<&LinuxResources>) -> Result<()> {
+        // The `cpu` controller is only enabled if it's used, since it can't be enabled
+        // while the host runs realtime processes
+        let cpu = resources.and_then(|resources| resources.cpu().as_ref());
+        let mut controllers = Self::CONTROLLER_LIST.to_vec();
+        if cpu.is_some_and(|cpu| {
+            cpu.shares().is_some_and(|shares| shares != 0)
+                || cpu.burst().is_some()
+                || cpu.quota().is_some()
+                || cpu.period().is_some()
+        }) {
+            controllers.push("cpu");
+        }
+        if cpu.is_some_and(|cpu| cpu.cpus().is_some() || cpu.mems().is_some()) {
+            controllers.push("cpuset");
+        }
+        if resources.is_some_and(|resources| resources.pids().is_some()) {
+            controllers.push("pids");
+        }
+        let rdma = resources.and_then(|resources| resources.rdma().as_ref());
+        if rdma.is_some() && cgroup_v2_controller_available("rdma") {
+            controllers.push("rdma");
+        }
+        enable_controllers_v2(&self.cgroup_path, &controllers)
+    }
+
+    /// `apply_resources` applies the resource limits in `resources` to the container cgroup.
+    fn apply_resources(&self, resources: Option<&LinuxResources>) -> Result<()> {
+        let cpu = resources.and_then(|resources| resources.cpu().as_ref());
+        let rdma = resources.and_then(|resources| resources.rdma().as_ref());
+        let memory = resources.and_then(|resources| resources.memory().as_ref());
+        if let Some(memory) = memory {
+            apply_memory_v2(&self.cgroup_path, memory)?;
+        }
+        if self.oom_group {
+            apply_oom_group_v2(&self.cgroup_path, memory)?;
+        }
+        if let Some(cpu) = cpu {
+            apply_cpu_v2(&self.cgroup_path, cpu)?;
+        }
+        if let Some(pids) = resources.and_then(|resources| resources.pids().as_ref()) {
+            apply_pids(&self.cgroup_path, pids)?;
+        }
+        if resources.is_some_and(|resources| resources.devices().is_some()) {
+            warn!("skipping the device rules, since cgroup v2 requires eBPF to filter devices, which is not implemented");
+        }
+        if let Some(rdma) = rdma {
+            if cgroup_v2_controller_available("rdma") {
+                apply_rdma_limits(&self.cgroup_path, rdma)?;
+            } else {
+                debug!("skipping the RDMA limits, since the rdma controller is not available");
+            }
+        }
+        Ok(())
+    }
+}
+
+impl CgroupManager for V2Manager {
+    fn apply(&self, resources: Option<&LinuxResources>, pid: Pid) -> Result<()> {
+        self.enable_controllers(resources)?;
+        create_cgroup_directory(&self.cgroup_path)?;
+        self.apply_resources(resources)?;
+        write_cgroup_file(&self.cgroup_path, "cgroup.procs", pid)?;
+        Ok(())
+    }
+
+    fn update(&self, resources: &LinuxResources) -> Result<()> {
+        ensure_cgroup_exists(&self.cgroup_path)?;
+        self.enable_controllers(Some(resources))?;
+        self.apply_resources(Some(resources))
+    }
+
+    fn remove(&self) -> Result<()> {
+        remove_cgroup_directory(&self.cgroup_path)
+    }
+}
+
+/// The devices that are always accessible in the container, which are the
+/// [default devices](https://github.com/opencontainers/runtime-spec/blob/main/config-linux.md#default-devices),
+/// `/dev/pts/*`, and `/dev/ptmx`. Each entry contains the type, major, and minor number of the device.
+const DEFAULT_DEVICE_LIST: [(&str, i64, Option<i64>); 8] = [
+    ("c", 1, Some(3)),
+    ("c", 1, Some(5)),
+    ("c", 1, Some(7)),
+    ("c", 1, Some(8)),
+    ("c", 1, Some(9)),
+    ("c", 5, Some(0)),
+    ("c", 5, Some(2)),
+    ("c", 136, None),
+];
+
+/// `device_rules_v1` returns the rules of the device whitelist controller as pairs of the file,
+/// which is `devices.allow` or `devices.deny`, and the rule, which is `<type> <major>:<minor> <access>`.
+/// The access to every device is denied first, and then the rules in `devices` are applied in order.
+/// The default devices and the devices in `container_devices` are allowed after them, since
+/// `devices` usually starts with a rule that denies every device, such as the configuration
+/// generated by `runc spec`, which would deny the default devices otherwise.
+/// For more information, see the [device whitelist controller](https://docs.kernel.org/admin-guide/cgroup-v1/devices.html)
+/// documentation.
+fn device_rules_v1(
+    devices: &[LinuxDeviceCgroup],
+    container_devices: &[LinuxDeviceCgroup],
+) -> Vec<(&'static str, String)> {
+    let device_number =
+        |number: Option<i64>| number.map_or(String::from("*"), |number| number.to_string());
+    let device_rule = |device: &LinuxDeviceCgroup| {
+        let file = if device.allow() {
+            "devices.allow"
+        } else {
+            "devices.deny"
+        };
+        let rule = format!(
+            "{} {}:{} {}",
+            device.typ().unwrap_or(LinuxDeviceType::A).as_str(),
+            device_number(device.major()),
+            device_number(device.minor()),
+            device.access().as_deref().unwrap_or("rwm")
+        );
+        (file, rule)
+    };
+
+    let mut rule_list = vec![("devices.deny", String::from("a *:* rwm"))];
+    rule_list.extend(devices.iter().map(device_rule));
+    rule_list.extend(DEFAULT_DEVICE_LIST.iter().map(|(typ, major, minor)| {
+        (
+            "devices.allow",
+            format!("{} {}:{} rwm", typ, major, device_number(*minor)),
+        )
+    }));
+    rule_list.extend(container_devices.iter().map(device_rule));
+    rule_list
+}
+
+/// `apply_memory_v1` sets the memory limit, the memory and swap limit, and the kernel memory limit.
+/// The swap limit in the bundle configuration is the total amount of memory and swap,
+/// which should not be lower than the memory limit. Since the kernel rejects a memory limit that
+/// is higher than `memory.memsw.limit_in_bytes` at any time, the order of the two limits depends
+/// on the current memory limit, which is decided by [swap_before_limit].
+fn apply_memory_v1(cgroup_path: &Path, memory: &LinuxMemory) -> Result<()> {
+    validate_swap(memory)?;
+
+    match (memory.limit(), memory.swap()) {
+        (Some(limit), Some(swap)) => {
+            let current_limit = read_cgroup_file(cgroup_path, "memory.limit_in_bytes")?;
+            let current_limit: u64 = current_limit.trim().parse().context(format!(
+                "failed to parse the memory limit {}",
+                current_limit.trim()
+            ))?;
+            if swap_before_limit(current_limit, swap) {
+                write_cgroup_file(cgroup_path, "memory.memsw.limit_in_bytes", swap)?;
+                write_cgroup_file(cgroup_path, "memory.limit_in_bytes", limit)?;
+            } else {
+                write_cgroup_file(cgroup_path, "memory.limit_in_bytes", limit)?;
+                write_cgroup_file(cgroup_path, "memory.memsw.limit_in_bytes", swap)?;
+            }
+        }
+        (Some(limit), None) => write_cgroup_file(cgroup_path, "memory.limit_in_bytes", limit)?,
+        (None, Some(swap)) => write_cgroup_file(cgroup_path, "memory.memsw.limit_in_bytes", swap)?,
+        (None, None) => (),
+    }
+
+    // The kernel memory limit is deprecated since Linux 5.4, where the file might not exist
+    if let Some(kernel) = memory.kernel() {
+        if cgroup_path.join("memory.kmem.limit_in_bytes").exists() {
+            write_cgroup_file(cgroup_path, "memory.kmem.limit_in_bytes", kernel)?;
+        } else {
+            debug!("skipping the kernel memory limit, which is not supported by the kernel");
+        }
+    }
+
+    // The processes in the cgroup are paused instead of killed if the OOM killer is disabled
+    if let Some(disable_oom_killer) = memory.disable_oom_killer() {
+        write_cgroup_file(
+            cgroup_path,
+            "memory.oom_control",
+            u8::from(disable_oom_killer),
+        )?;
+    }
+    Ok(())
+}
+
+/// `swap_before_limit` checks if the memory and swap limit `swap` should be written before
+/// the memory limit, which is the case if it's unlimited or higher than `current_limit`, so that
+/// the memory limit is never higher than the memory and swap limit, as `runc` does. Otherwise,
+/// the new memory limit, which is not higher than `swap`, is written first.
+fn swap_before_limit(current_limit: u64, swap: i64) -> bool {
+    swap == -1 || current_limit < swap as u64
+}
+
+/// `apply_memory_v2` sets `memory.max` and `memory.swap.max`, which are independent of each other.
+/// Since the swap limit in the bundle configuration is the total amount of memory and swap,
+/// the memory limit is subtracted from it to get the swap limit of cgroup v2.
+fn apply_memory_v2(cgroup_path: &Path, memory: &LinuxMemory) -> Result<()> {
+    if let Some(limit) = memory.limit() {
+        write_cgroup_file(cgroup_path, "memory.max", cgroup_v2_limit(limit))?;
+    }
+
+    validate_swap(memory)?;
+    if let Some(swap) = memory.swap() {
+        let swap = match memory.limit() {
+            Some(limit) if limit != -1 && swap != -1 => swap - limit,
+            _ => swap,
+        };
+        write_cgroup_file(cgroup_path, "memory.swap.max", cgroup_v2_limit(swap))?;
+    }
+
+    if memory.kernel().is_some() {
+        debug!("skipping the kernel memory limit, which is not supported by cgroup v2");
+    }
+    if memory.disable_oom_killer() == Some(true) {
+        warn!("skipping disableOOMKiller, since the OOM killer can't be disabled on cgroup v2");
+    }
+    Ok(())
+}
+
+/// `apply_oom_group_v2` sets `memory.oom.group` to `1`, which makes the OOM killer kill every
+/// process in the cgroup together, so that the container isn't left with a random process killed.
+/// `disableOOMKiller` takes precedence over the [OOM_GROUP_ANNOTATION], where the group is not set,
+/// since the container asks not to be killed by the OOM killer.
+/// The file is only available since Linux 4.19, where it's skipped on older kernels.
+fn apply_oom_group_v2(cgroup_path: &Path, memory: Option<&LinuxMemory>) -> Result<()> {
+    if memory.and_then(|memory| memory.disable_oom_killer()) == Some(true) {
+        debug!("skipping memory.oom.group, since disableOOMKiller is set");
+        return Ok(());
+    }
+
+    if cgroup_v2_file_exists(cgroup_path, "memory.oom.group") {
+        write_cgroup_file(cgroup_path, "memory.oom.group", 1)?;
+    } else {
+        debug!("skipping memory.oom.group, which is not supported by the kernel");
+    }
+    Ok(())
+}
+
+/// `apply_cpu_v1` sets `cpu.shares`, which is the relative weight of the CPU time of the cgroup,
+/// and `cpu.cfs_period_us` and `cpu.cfs_quota_us`, which limit the CPU time of the cgroup
+/// to the quota in each period, where a quota of `-1` is unlimited.
+fn apply_cpu_v1(cgroup_path: &Path, cpu: &LinuxCpu) -> Result<()> {
+    if let Some(shares) = cpu_shares(cpu)? {
+        write_cgroup_file(cgroup_path, "cpu.shares", shares)?;
+    }
+    if let Some(period) = cpu.period() {
+        write_cgroup_file(cgroup_path, "cpu.cfs_period_us", period)?;
+    }
+    if let Some(quota) = cpu.quota() {
+        write_cgroup_file(cgroup_path, "cpu.cfs_quota_us", quota)?;
+    }
+    if cpu.cpus().is_some() || cpu.mems().is_some() {
+        warn!("skipping the cpuset, since the cpuset controller is not managed on cgroup v1");
+    }
+    Ok(())
+}
+
+/// `apply_cpu_v2` sets `cpu.weight` converted from the CPU shares with [cpu_shares_to_weight],
+/// `cpu.max`, which contains the quota and the period of the CPU time of the cgroup,
+/// `cpuset.cpus` and `cpuset.mems`, which restrict the CPUs and the memory nodes of the cgroup,
+/// and `cpu.max.burst`, which is the amount of CPU time in microseconds that the cgroup could
+/// accumulate while it's idle and use in addition to its quota in a period.
+/// `cpu.max.burst` is only available since Linux 5.14, where it's skipped on older kernels.
+fn apply_cpu_v2(cgroup_path: &Path, cpu: &LinuxCpu) -> Result<()> {
+    if let Some(shares) = cpu_shares(cpu)? {
+        write_cgroup_file(cgroup_path, "cpu.weight", cpu_shares_to_weight(shares))?;
+    }
+    match (cpu.quota(), cpu.period()) {
+        (Some(quota), Some(period)) => write_cgroup_file(
+            cgroup_path,
+            "cpu.max",
+            format!("{} {}", cgroup_v2_limit(quota), period),
+        )?,
+        // The period is unchanged if it's not specified
+        (Some(quota), None) => write_cgroup_file(cgroup_path, "cpu.max", cgroup_v2_limit(quota))?,
+        // The quota is unchanged if it's not specified, which is read from `cpu.max`
+        (None, Some(period)) => {
+            let cpu_max = fs::read_to_string(cgroup_path.join("cpu.max"))
+                .context("failed to read cpu.max")?;
+            let quota = cpu_max.split_whitespace().next().unwrap_or("max");
+            write_cgroup_file(cgroup_path, "cpu.max", format!("{} {}", quota, period))?;
+        }
+        (None, None) => (),
+    }
+    if let Some(cpus) = cpu.cpus() {
+        write_cgroup_file(cgroup_path, "cpuset.cpus", cpus)?;
+    }
+    if let Some(mems) = cpu.mems() {
+        write_cgroup_file(cgroup_path, "cpuset.mems", mems)?;
+    }
+    if let Some(burst) = cpu.burst() {
+        if cgroup_v2_file_exists(cgroup_path, "cpu.max.burst") {
+            write_cgroup_file(cgroup_path, "cpu.max.burst", burst)?;
+        } else {
+            debug!("skipping the CPU burst, which is not supported by the kernel");
+        }
+    }
+    Ok(())
+}
+
+/// `apply_pids` sets `pids.max`, which is the maximum number of processes in the cgroup,
+/// where a limit of `-1` or `0` is unlimited.
+fn apply_pids(cgroup_path: &Path, pids: &LinuxPids) -> Result<()> {
+    let limit = if pids.limit() > 0 { pids.limit() } else { -1 };
+    write_cgroup_file(cgroup_path, "pids.max", cgroup_v2_limit(limit))
+}
+
+/// `apply_rdma_limits` sets the limits of the RDMA devices in `rdma.max`, where each device
+/// is written as `<device> hca_handle=<n> hca_object=<n>`, and an unset limit is written as `max`.
+/// For more information, see the [RDMA controller](https://docs.kernel.org/admin-guide/cgroup-v1/rdma.html)
+/// documentation.
+fn apply_rdma_limits(cgroup_path: &Path, rdma_limits: &HashMap<String, LinuxRdma>) -> Result<()> {
+    let rdma_limit = |limit: Option<u32>| {
+        limit
+            .map(|limit| limit.to_string())
+            .unwrap_or_else(|| String::from("max"))
+    };
+    for (device, limits) in rdma_limits {
+        write_cgroup_file(
+            cgroup_path,
+            "rdma.max",
+            format!(
+                "{} hca_handle={} hca_object={}",
+                device,
+                rdma_limit(limits.hca_handles()),
+                rdma_limit(limits.hca_objects())
+            ),
+        )?;
+    }
+    Ok(())
+}
+
+/// `cgroup_v2_controller_available` checks if `controller` is available in the root cgroup,
+/// which is listed in `cgroup.controllers`.
+fn cgroup_v2_controller_available(controller: &str) -> bool {
+    fs::read_to_string(Path::new(CGROUP_ROOT).join("cgroup.controllers")).is_ok_and(|controllers| {
+        controllers
+            .split_whitespace()
+            .any(|name| name == controller)
+    })
+}
+
+/// `cgroup_v2_file_exists` checks if the interface file `filename` exists in the cgroup,
+/// which depends on the kernel version and the controllers enabled for the cgroup.
+fn cgroup_v2_file_exists(cgroup_path: &Path, filename: &str) -> bool {
+    cgroup_path.join(filename).exists()
+}
+
+/// `validate_swap` checks that the memory and swap limit is not lower than the memory limit.
+fn validate_swap(memory: &LinuxMemory) -> Result<()> {
+    if let (Some(limit), Some(swap)) = (memory.limit(), memory.swap()) {
+        if limit != -1 && swap != -1 && swap < limit {
+            bail!(
+                "the memory and swap limit {} should not be lower than the memory limit {}",
+                swap,
+                limit
+            );
+        }
+    }
+    Ok(())
+}
+
+/// The range of `cpu.shares` of cgroup v1.
+const CPU_SHARES_RANGE: RangeInclusive<u64> = 2..=262144;
+
+/// `cpu_shares` returns the CPU shares of `cpu` after checking that they are in [CPU_SHARES_RANGE],
+/// which are rejected or silently clamped by the kernel otherwise. The shares `0` are treated
+/// as unset, where `None` is returned, which matches the behavior of `runc`.
+fn cpu_shares(cpu: &LinuxCpu) -> Result<Option<u64>> {
+    match cpu.shares() {
+        None | Some(0) => Ok(None),
+        Some(shares) if CPU_SHARES_RANGE.contains(&shares) => Ok(Some(shares)),
+        Some(shares) => bail!(
+            "the CPU shares {} should be between {} and {}",
+            shares,
+            CPU_SHARES_RANGE.start(),
+            CPU_SHARES_RANGE.end()
+        ),
+    }
+}
+
+/// `cpu_shares_to_weight` converts the CPU shares of cgroup v1, which range from `2` to `262144`,
+/// to `cpu.weight` of cgroup v2, which ranges from `1` to `10000`. The ranges are mapped linearly,
+/// where `2` is mapped to `1` and `262144` is mapped to `10000`, so that the weight of a cgroup
+/// relative to the others is preserved. The default shares `1024` is mapped to `39` instead of
+/// the default weight `100`, which matches the conversion of `runc` and `crun`.
+fn cpu_shares_to_weight(shares: u64) -> u64 {
+    (1 + (shares.saturating_sub(2)) * 9999 / 262142).clamp(1, 10000)
+}
+
+/// `cgroup_v2_limit` converts a limit to the format of cgroup v2, where `-1` is written as `max`.
+fn cgroup_v2_limit(limit: i64) -> String {
+    if limit == -1 {
+        String::from("max")
+    } else {
+        limit.to_string()
+    }
+}
+
+/// `enable_controllers_v2` enables the `controllers` in `cgroup.subtree_control`
+/// of every ancestor of `cgroup_path` returned by [subtree_control_ancestors],
+/// which makes the controllers available in the cgroup.
+fn enable_controllers_v2(cgroup_path: &Path, controllers: &[&str]) -> Result<()> {
+    let subtree_control = controllers
+        .iter()
+        .map(|controller| format!("+{}", controller))
+        .collect::<Vec<String>>()
+        .join(" ");
+
+    for ancestor in subtree_control_ancestors(cgroup_path) {
+        create_cgroup_directory(ancestor)?;
+        write_cgroup_file(ancestor, "cgroup.subtree_control", &subtree_control)?;
+    }
+    Ok(())
+}
+
+/// `subtree_control_ancestors` returns the ancestors of `cgroup_path` below the cgroup root,
+/// from the outermost to the innermost. The cgroup root is excluded, since its controllers
+/// are managed by the host, such as `systemd`, instead of the runtime.
+fn subtree_control_ancestors(cgroup_path: &Path) -> Vec<&Path> {
+    let mut ancestors: Vec<&Path> = cgroup_path
+        .ancestors()
+        .skip(1)
+        .take_while(|ancestor| {
+            ancestor.starts_with(CGROUP_ROOT) && *ancestor != Path::new(CGROUP_ROOT)
+        })
+        .collect();
+    ancestors.reverse();
+    ancestors
+}
+
+fn create_cgroup_directory(cgroup_path: &Path) -> Result<()> {
+    fs::create_dir_all(cgroup_path).context(format!(
+        "failed to create the cgroup {}",
+        cgroup_path.display()
+    ))
+}
+
+/// `ensure_cgroup_exists` checks that the container cgroup is created,
+/// which is required to update its resource limits.
+fn ensure_cgroup_exists(cgroup_path: &Path) -> Result<()> {
+    if !cgroup_path.is_dir() {
+        bail!("the cgroup {} doesn't exist", cgroup_path.display());
+    }
+    Ok(())
+}
+
+fn remove_cgroup_directory(cgroup_path: &Path) -> Result<()> {
+    match fs::remove_dir(cgroup_path) {
+        Err(err) if err.kind() != ErrorKind::NotFound => Err(err).context(format!(
+            "failed to remove the cgroup {}",
+            cgroup_path.display()
+        )),
+        _ => Ok(()),
+    }
+}
+
+fn read_cgroup_file(cgroup_path: &Path, file: &str) -> Result<String> {
+    let path = cgroup_path.join(file);
+    fs::read_to_string(&path).context(format!("failed to read {}", path.display()))
+}
+
+fn write_cgroup_file(cgroup_path: &Path, file: &str, value: impl Display) -> Result<()> {
+    let path = cgroup_path.join(file);
+    fs::write(&path, value.to_string()).context(format!(
+        "failed to write {} to {}",
+        value,
+        path.display()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use oci_spec::runtime::{Linux, LinuxMemoryBuilder};
+
+    use super::*;
+    use crate::test_util;
+
+    fn device_rule(
+        allow: bool,
+        typ: Option<LinuxDeviceType>,
+        major: Option<i64>,
+        minor: Option<i64>,
+    ) -> LinuxDeviceCgroup {
+        let mut device = LinuxDeviceCgroup::default();
+        device.set_allow(allow);
+        device.set_typ(typ);
+        device.set_major(major);
+        device.set_minor(minor);
+        device.set_access(Some(String::from("rwm")));
+        device
+    }
+
+    #[test]
+    fn device_rules_v1_allows_default_devices_after_deny_all() {
+        let deny_all = device_rule(false, None, None, None);
+        let rule_list = device_rules_v1(&[deny_all], &[]);
+
+        let deny_all_index = rule_list
+            .iter()
+            .rposition(|rule| rule == &("devices.deny", String::from("a *:* rwm")))
+            .unwrap();
+        for default_device in [
+            "c 1:3 rwm",
+            "c 1:5 rwm",
+            "c 5:0 rwm",
+            "c 5:2 rwm",
+            "c 136:* rwm",
+        ] {
+            let allow_index = rule_list
+                .iter()
+                .position(|rule| rule == &("devices.allow", String::from(default_device)))
+                .unwrap();
+            assert!(allow_index > deny_all_index, "{} is denied", default_device);
+        }
+    }
+
+    #[test]
+    fn device_rules_v1_allows_container_devices_last() {
+        let deny_kvm = device_rule(false, Some(LinuxDeviceType::C), Some(10), Some(232));
+        let allow_kvm = device_rule(true, Some(LinuxDeviceType::C), Some(10), Some(232));
+        let rule_list = device_rules_v1(&[deny_kvm], &[allow_kvm]);
+
+        assert_eq!(
+            rule_list.first(),
+            Some(&("devices.deny", String::from("a *:* rwm")))
+        );
+        assert_eq!(rule_list[1], ("devices.deny", String::from("c 10:232 rwm")));
+        assert_eq!(
+            rule_list.last(),
+            Some(&("devices.allow", String::from("c 10:232 rwm")))
+        );
+    }
+
+    #[test]
+    fn device_rules_v1_keeps_order_of_spec_rules() {
+        let allow_all = device_rule(true, None, None, None);
+        let deny_block = device_rule(false, Some(LinuxDeviceType::B), None, None);
+        let rule_list = device_rules_v1(&[allow_all, deny_block], &[]);
+
+        assert_eq!(rule_list[1], ("devices.allow", String::from("a *:* rwm")));
+        assert_eq!(rule_list[2], ("devices.deny", String::from("b *:* rwm")));
+    }
+
+    #[test]
+    fn subtree_control_ancestors_exclude_root() {
+        assert_eq!(
+            subtree_control_ancestors(Path::new("/sys/fs/cgroup/reno/container")),
+            vec![Path::new("/sys/fs/cgroup/reno")]
+        );
+        assert!(subtree_control_ancestors(Path::new("/sys/fs/cgroup/container")).is_empty());
+    }
+
+    #[test]
+    fn cgroup_requested_by_spec() {
+        let mut linux = Linux::default();
+        linux.set_cgroups_path(None);
+        linux.set_resources(None);
+        let mut spec = Spec::default();
+        spec.set_linux(Some(linux.clone()));
+        assert!(!cgroup_requested(&spec));
+
+        let mut cgroups_path_linux = linux.clone();
+        cgroups_path_linux.set_cgroups_path(Some(PathBuf::from("/reno/container")));
+        spec.set_linux(Some(cgroups_path_linux));
+        assert!(cgroup_requested(&spec));
+
+        let mut resources_linux = linux;
+        resources_linux.set_resources(Some(LinuxResources::default()));
+        spec.set_linux(Some(resources_linux));
+        assert!(cgroup_requested(&spec));
+    }
+
+    #[test]
+    fn cpu_shares_zero_is_unset() {
+        let mut cpu = LinuxCpu::default();
+        assert_eq!(cpu_shares(&cpu).unwrap(), None);
+        cpu.set_shares(Some(0));
+        assert_eq!(cpu_shares(&cpu).unwrap(), None);
+        cpu.set_shares(Some(1024));
+        assert_eq!(cpu_shares(&cpu).unwrap(), Some(1024));
+        cpu.set_shares(Some(1));
+        assert!(cpu_shares(&cpu).is_err());
+        cpu.set_shares(Some(262145));
+        assert!(cpu_shares(&cpu).is_err());
+    }
+
+    #[test]
+    fn cpu_shares_to_weight_range() {
+        assert_eq!(cpu_shares_to_weight(2), 1);
+        assert_eq!(cpu_shares_to_weight(1024), 39);
+        assert_eq!(cpu_shares_to_weight(262144), 10000);
+    }
+
+    #[test]
+    fn swap_before_limit_keeps_limit_under_swap() {
+        // Raising both limits above the current memory limit writes the swap limit first
+        assert!(swap_before_limit(256 << 20, 1 << 30));
+        assert!(swap_before_limit(256 << 20, -1));
+        // Lowering both limits below the current memory limit writes the memory limit first
+        assert!(!swap_before_limit(1 << 30, 512 << 20));
+        assert!(!swap_before_limit(1 << 30, 1 << 30));
+    }
+
+    #[test]
+    fn apply_memory_v1_writes_both_limits() {
+        let cgroup_path = test_util::test_directory("cgroup", "memory");
+        fs::write(
+            cgroup_path.join("memory.limit_in_bytes"),
+            "9223372036854771712\n",
+        )
+        .unwrap();
+
+        let memory = LinuxMemoryBuilder::default()
+            .limit(256 << 20)
+            .swap(512 << 20)
+            .build()
+            .unwrap();
+        apply_memory_v1(&cgroup_path, &memory).unwrap();
+        assert_eq!(
+            fs::read_to_string(cgroup_path.join("memory.limit_in_bytes")).unwrap(),
+            (256 << 20).to_string()
+        );
+        assert_eq!(
+            fs::read_to_string(cgroup_path.join("memory.memsw.limit_in_bytes")).unwrap(),
+            (512 << 20).to_string()
+        );
+
+        let memory = LinuxMemoryBuilder::default()
+            .limit(256 << 20)
+            .swap(128 << 20)
+            .build()
+            .unwrap();
+        assert!(apply_memory_v1(&cgroup_path, &memory).is_err());
+    }
+
+    /// `TestManager` is a [CgroupManager] that records the resources of each update,
+    /// and fails the updates of the controllers in `failing_controllers`.
+    struct TestManager {
+        update_list: RefCell<Vec<LinuxResources>>,
+        failing_controllers: Vec<&'static str>,
+    }
+
+    impl CgroupManager for TestManager {
+        fn apply(&self, _: Option<&LinuxResources>, _: Pid) -> Result<()> {
+            Ok(())
+        }
+
+        fn update(&self, resources: &LinuxResources) -> Result<()> {
+            self.update_list.borrow_mut().push(resources.clone());
+            let controller = match resources.cpu() {
+                _ if resources.memory().is_some() => "memory",
+                Some(cpu) if cpu.cpus().is_some() || cpu.mems().is_some() => "cpuset",
+                Some(_) => "cpu",
+                None => "pids",
+            };
+            if self.failing_controllers.contains(&controller) {
+                bail!("the {} controller is not available", controller);
+            }
+            Ok(())
+        }
+
+        fn remove(&self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    fn test_resources() -> LinuxResources {
+        let memory = LinuxMemoryBuilder::default()
+            .limit(256 << 20)
+            .build()
+            .unwrap();
+        let mut cpu = LinuxCpu::default();
+        cpu.set_quota(Some(50000));
+        cpu.set_cpus(Some(String::from("0-1")));
+        let mut pids = LinuxPids::default();
+        pids.set_limit(64);
+
+        let mut resources = LinuxResources::default();
+        resources.set_memory(Some(memory));
+        resources.set_cpu(Some(cpu));
+        resources.set_pids(Some(pids));
+        resources
+    }
+
+    #[test]
+    fn update_resources_splits_controllers() {
+        let cgroup_manager = TestManager {
+            update_list: RefCell::new(Vec::new()),
+            failing_controllers: Vec::new(),
+        };
+        update_resources(&cgroup_manager, &test_resources()).unwrap();
+
+        let update_list = cgroup_manager.update_list.into_inner();
+        assert_eq!(update_list.len(), 4);
+        assert_eq!(update_list[0].memory().unwrap().limit(), Some(256 << 20));
+        assert!(update_list[0].cpu().is_none());
+        let cpu = update_list[1].cpu().as_ref().unwrap();
+        assert_eq!((cpu.quota(), cpu.cpus()), (Some(50000), &None));
+        let cpuset = update_list[2].cpu().as_ref().unwrap();
+        assert_eq!(
+            (cpuset.quota(), cpuset.cpus()),
+            (None, &Some(String::from("0-1")))
+        );
+        assert_eq!(update_list[3].pids().unwrap().limit(), 64);
+        assert!(update_list[3].memory().is_none());
+    }
+
+    #[test]
+    fn update_resources_aggregates_errors() {
+        let cgroup_manager = TestManager {
+            update_list: RefCell::new(Vec::new()),
+            failing_controllers: vec!["memory", "cpuset"],
+        };
+        let error = update_resources(&cgroup_manager, &test_resources()).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "failed to update the resource limits of the container: \
+            memory: the memory controller is not available; \
+            cpuset: the cpuset controller is not available"
+        );
+        // The controllers after a failing one are still updated
+        assert_eq!(cgroup_manager.update_list.borrow().len(), 4);
+    }
+}
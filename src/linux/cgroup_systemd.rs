@@ -0,0 +1,147 @@
+use anyhow::{Context, Result};
+use log::warn;
+use nix::unistd::Pid;
+use oci_spec::runtime::LinuxResources;
+use zbus::{
+    blocking::{Connection, Proxy},
+    zvariant::{OwnedObjectPath, Value},
+};
+
+use crate::linux::cgroup::CgroupManager;
+
+const SYSTEMD_DESTINATION: &str = "org.freedesktop.systemd1";
+const SYSTEMD_PATH: &str = "/org/freedesktop/systemd1";
+const SYSTEMD_MANAGER_INTERFACE: &str = "org.freedesktop.systemd1.Manager";
+
+/// `SystemdManager` manages the container cgroup with a transient scope unit of `systemd`,
+/// which owns the cgroup hierarchy on the host. The scope unit is named `reno-<id>.scope`,
+/// and it's only created if the bundle configuration asks for a container cgroup.
+/// For more information, see the [systemd.scope(5)](https://man7.org/linux/man-pages/man5/systemd.scope.5.html)
+/// man page.
+pub struct SystemdManager {
+    id: String,
+}
+
+impl SystemdManager {
+    pub fn new(id: &str) -> Self {
+        SystemdManager { id: id.to_string() }
+    }
+}
+
+impl CgroupManager for SystemdManager {
+    fn apply(&self, resources: Option<&LinuxResources>, pid: Pid) -> Result<()> {
+        create_transient_scope(&self.id, pid)?;
+        if let Some(resources) = resources {
+            set_unit_properties(&self.id, resources)?;
+        }
+        Ok(())
+    }
+
+    fn update(&self, resources: &LinuxResources) -> Result<()> {
+        set_unit_properties(&self.id, resources)
+    }
+
+    fn remove(&self) -> Result<()> {
+        let unit_name = scope_unit_name(&self.id);
+        let manager = systemd_manager()?;
+
+        // The scope unit is removed by `systemd` once the container process exits
+        let result: zbus::Result<OwnedObjectPath> =
+            manager.call("StopUnit", &(unit_name.as_str(), "replace"));
+        match result {
+            Err(zbus::Error::MethodError(name, _, _))
+                if name.as_str() == "org.freedesktop.systemd1.NoSuchUnit" =>
+            {
+                Ok(())
+            }
+            result => result
+                .map(|_| ())
+                .context(format!("failed to stop the unit {}", unit_name)),
+        }
+    }
+}
+
+/// `create_transient_scope` creates the transient scope unit `reno-<id>.scope`
+/// that contains the process `pid` with the `StartTransientUnit` method of `systemd`.
+/// The cgroup of the scope unit is delegated to the container.
+pub fn create_transient_scope(id: &str, pid: Pid) -> Result<()> {
+    let unit_name = scope_unit_name(id);
+    let description = format!("reno container {}", id);
+    let properties: Vec<(&str, Value)> = vec![
+        ("Description", Value::from(description.as_str())),
+        ("PIDs", Value::from(vec![pid.as_raw() as u32])),
+        ("Delegate", Value::from(true)),
+    ];
+    let auxiliary_units: Vec<(&str, Vec<(&str, Value)>)> = Vec::new();
+
+    let _: OwnedObjectPath = systemd_manager()?
+        .call(
+            "StartTransientUnit",
+            &(unit_name.as_str(), "fail", properties, auxiliary_units),
+        )
+        .context(format!("failed to create the transient unit {}", unit_name))?;
+    Ok(())
+}
+
+/// `set_unit_properties` applies the resource limits in `resources` to the scope unit
+/// of the container with the `SetUnitProperties` method of `systemd`.
+/// For more information, see the [systemd.resource-control(5)](https://man7.org/linux/man-pages/man5/systemd.resource-control.5.html)
+/// man page.
+fn set_unit_properties(id: &str, resources: &LinuxResources) -> Result<()> {
+    let mut properties: Vec<(&str, Value)> = Vec::new();
+    if let Some(memory) = resources.memory() {
+        if let Some(limit) = memory.limit() {
+            properties.push(("MemoryMax", Value::from(systemd_limit(limit))));
+        }
+        // The swap limit in the bundle configuration is the total amount of memory and swap
+        if let Some(swap) = memory.swap() {
+            let swap = match memory.limit() {
+                Some(limit) if limit != -1 && swap != -1 => swap - limit,
+                _ => swap,
+            };
+            properties.push(("MemorySwapMax", Value::from(systemd_limit(swap))));
+        }
+    }
+    if let Some(pids) = resources.pids() {
+        let limit = if pids.limit() > 0 { pids.limit() } else { -1 };
+        properties.push(("TasksMax", Value::from(systemd_limit(limit))));
+    }
+    if resources.cpu().is_some() {
+        warn!("skipping the CPU limits, which are not supported by the systemd cgroup manager");
+    }
+
+    if properties.is_empty() {
+        return Ok(());
+    }
+
+    let unit_name = scope_unit_name(id);
+    systemd_manager()?
+        .call::<_, _, ()>("SetUnitProperties", &(unit_name.as_str(), true, properties))
+        .context(format!(
+            "failed to set the resource limits of the unit {}",
+            unit_name
+        ))?;
+    Ok(())
+}
+
+/// `systemd_manager` connects to the `org.freedesktop.systemd1.Manager` interface
+/// on the system bus.
+fn systemd_manager() -> Result<Proxy<'static>> {
+    let connection = Connection::system().context("failed to connect to the system bus")?;
+    Proxy::new(
+        &connection,
+        SYSTEMD_DESTINATION,
+        SYSTEMD_PATH,
+        SYSTEMD_MANAGER_INTERFACE,
+    )
+    .context("failed to connect to systemd")
+}
+
+fn scope_unit_name(id: &str) -> String {
+    format!("reno-{}.scope", id)
+}
+
+/// `systemd_limit` converts a limit to the format of `systemd`, where `-1` is written as `u64::MAX`.
+fn systemd_limit(limit: i64) -> u64 {
+    u64::try_from(limit).unwrap_or(u64::MAX)
+}
@@ -1,16 +1,33 @@
 use std::{
-    fs::{self, Permissions},
-    os::{unix, unix::prelude::PermissionsExt},
+    fs::{self, DirBuilder, File, Metadata, OpenOptions, Permissions},
+    io::IoSlice,
+    os::{
+        fd::{AsRawFd, BorrowedFd, OwnedFd},
+        unix,
+        unix::{
+            fs::{DirBuilderExt, MetadataExt, OpenOptionsExt},
+            net::UnixStream,
+            prelude::PermissionsExt,
+        },
+    },
     path::{Path, PathBuf},
 };
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
+use log::debug;
 use nix::{
-    sys::stat::{self, Mode, SFlag},
+    errno::Errno,
+    mount::MsFlags,
+    sys::{
+        socket::{self, ControlMessage, MsgFlags},
+        stat::{self, Mode, SFlag},
+    },
     unistd::{self, Gid, Uid},
 };
 use oci_spec::runtime::{LinuxDevice, LinuxDeviceBuilder, LinuxDeviceType};
 
+use crate::linux::mount;
+
 /// `create_default_symlink` creates symbolic links for the default
 /// [dev symbolic links](https://github.com/opencontainers/runtime-spec/blob/main/runtime-linux.md#-dev-symbolic-links)
 /// specified in OCI runtime specification.
@@ -23,17 +40,126 @@ pub fn create_default_symlink(rootfs: &Path) -> Result<()> {
         ("pts/ptmx", "/dev/ptmx"),
     ];
 
+    // The image might not contain `/dev`
+    create_parent_directory(&symlink_path(rootfs, Path::new("/dev/ptmx"))?)?;
+
     for (source, destination) in default_symlink_list {
-        unix::fs::symlink(source, rootfs.join(destination.trim_start_matches('/'))).context(
-            format!(
-                "failed to create default symlink from {} to {}",
-                source, destination
-            ),
-        )?;
+        // The symbolic link might be shipped in the root file system, which is kept if it points
+        // to `source`, or replaced otherwise, such as a `/dev/ptmx` device node in the image,
+        // which would allocate the pseudoterminals of the host
+        let path = symlink_path(rootfs, Path::new(destination))?;
+        if let Ok(metadata) = path.symlink_metadata() {
+            if metadata.file_type().is_symlink()
+                && fs::read_link(&path).is_ok_and(|target| target == Path::new(source))
+            {
+                continue;
+            }
+            remove_conflicting_entry(&path, &metadata)?;
+        }
+
+        unix::fs::symlink(source, &path).context(format!(
+            "failed to create default symlink from {} to {}",
+            source, destination
+        ))?;
     }
     Ok(())
 }
 
+/// `symlink_path` resolves the parent directory of `destination` in `rootfs`
+/// without following `destination` itself, which might be a symbolic link.
+fn symlink_path(rootfs: &Path, destination: &Path) -> Result<PathBuf> {
+    match (destination.parent(), destination.file_name()) {
+        (Some(parent), Some(file_name)) => Ok(mount::secure_join(rootfs, parent)?.join(file_name)),
+        _ => bail!("the symbolic link {} is invalid", destination.display()),
+    }
+}
+
+/// `is_same_device` checks if the file of `metadata` is a device of type `sflag` with the device
+/// number `dev`, where a named pipe has no device number.
+fn is_same_device(metadata: &Metadata, sflag: SFlag, dev: u64) -> bool {
+    SFlag::from_bits_truncate(metadata.mode() & SFlag::S_IFMT.bits()) == sflag
+        && (sflag == SFlag::S_IFIFO || metadata.rdev() == dev)
+}
+
+/// `host_device_number` returns the device number of the device at `path` on the host,
+/// which should be a device of type `sflag`.
+fn host_device_number(path: &Path, sflag: SFlag) -> Result<u64> {
+    let metadata = fs::metadata(path).context(format!(
+        "the major and minor numbers of {} are omitted, and the host has no such device",
+        path.display()
+    ))?;
+    if SFlag::from_bits_truncate(metadata.mode() & SFlag::S_IFMT.bits()) != sflag {
+        bail!(
+            "the major and minor numbers of {} are omitted, and the device on the host has a different type",
+            path.display()
+        );
+    }
+    Ok(metadata.rdev())
+}
+
+/// `device_number` returns the device number of `device`, which is looked up from the same device
+/// on the host with [host_device_number] if the major and minor numbers are omitted or zero,
+/// since they might vary across kernels.
+pub fn device_number(device: &LinuxDevice) -> Result<u64> {
+    let sflag = linux_device_type_to_sflag(device.typ());
+    if !sflag.is_empty() && sflag != SFlag::S_IFIFO && device.major() == 0 && device.minor() == 0 {
+        host_device_number(device.path(), sflag)
+    } else {
+        Ok(stat::makedev(device.major() as u64, device.minor() as u64))
+    }
+}
+
+/// `remove_conflicting_entry` removes the file at `path` in the image that conflicts with
+/// a device or a symbolic link, such as a regular file or a stale symbolic link.
+/// A directory is never removed, since it might contain the data of the image.
+fn remove_conflicting_entry(path: &Path, metadata: &Metadata) -> Result<()> {
+    if metadata.is_dir() {
+        bail!(
+            "{} is a directory in the root file system, which conflicts with the device or the symbolic link",
+            path.display()
+        );
+    }
+    fs::remove_file(path).context(format!("failed to remove {}", path.display()))?;
+    Ok(())
+}
+
+/// `create_parent_directory` creates the missing parent directories of `path` with mode `0755`,
+/// where `path` is resolved in the root file system with [mount::secure_join],
+/// so that the directories are never created outside of the root file system.
+fn create_parent_directory(path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        DirBuilder::new()
+            .recursive(true)
+            .mode(0o755)
+            .create(parent)
+            .context(format!("failed to create {}", parent.display()))?;
+    }
+    Ok(())
+}
+
+/// `UmaskGuard` sets the umask of the process to `0` while the devices are created, so that
+/// `mknod` creates the devices with the exact mode in `file_mode`, and restores the previous umask
+/// when it's dropped, including when the creation fails.
+/// For more information, see the [umask(2)](https://man7.org/linux/man-pages/man2/umask.2.html)
+/// man page.
+struct UmaskGuard {
+    umask: Mode,
+}
+
+impl UmaskGuard {
+    fn new() -> Self {
+        UmaskGuard {
+            umask: stat::umask(Mode::empty()),
+        }
+    }
+}
+
+impl Drop for UmaskGuard {
+    fn drop(&mut self) {
+        stat::umask(self.umask);
+    }
+}
+
 /// `linux_device_type_to_sflag` converts [LinuxDeviceType] to [SFlag].
 fn linux_device_type_to_sflag(flag: LinuxDeviceType) -> SFlag {
     match flag {
@@ -44,23 +170,75 @@ fn linux_device_type_to_sflag(flag: LinuxDeviceType) -> SFlag {
     }
 }
 
-/// `create_device` creates a Linux device with `mknod`.
+/// `create_device` creates a Linux device with `mknod`, or bind mounts the device of the host
+/// with [create_device_bind] if the device type is not recognized or `mknod` is not permitted,
+/// such as in a rootless container. If the major and minor numbers are omitted or zero,
+/// they're looked up from the same device on the host with [host_device_number].
 /// For more information, see the [mknod(2)](https://man7.org/linux/man-pages/man2/mknod.2.html)
 /// man page.
 pub fn create_device(rootfs: &Path, device: &LinuxDevice) -> Result<()> {
-    let path = &rootfs.join(device.path().display().to_string().trim_start_matches('/'));
-    stat::mknod(
-        path,
-        linux_device_type_to_sflag(device.typ()),
-        Mode::from_bits_truncate(device.file_mode().unwrap_or(0o066)),
-        stat::makedev(device.major() as u64, device.minor() as u64),
-    )
-    .context(format!(
-        "failed to create {} with mknod",
-        device.path().display(),
-    ))?;
+    // `/dev/ptmx` is always the symbolic link to the `ptmx` of the `devpts` instance of the
+    // container, which is created by [create_default_symlink]
+    if device.path() == Path::new("/dev/ptmx") {
+        debug!("skipping /dev/ptmx, since it's a symbolic link to /dev/pts/ptmx");
+        return Ok(());
+    }
+
+    // The device itself is not resolved, since it might be a stale symbolic link in the image
+    let path = &symlink_path(rootfs, device.path())?;
+    let file_mode = device.file_mode().unwrap_or(0o666) & 0o7777;
+    let sflag = linux_device_type_to_sflag(device.typ());
+    if sflag.is_empty() {
+        return create_device_bind(rootfs, device);
+    }
+    create_parent_directory(path)?;
+    let _umask_guard = UmaskGuard::new();
+
+    let dev = device_number(device)?;
+
+    // The device might be shipped in the root file system, which is kept if it's the same device,
+    // or replaced otherwise
+    let existing_device = match path.symlink_metadata() {
+        Ok(metadata) => {
+            let same_device = is_same_device(&metadata, sflag, dev);
+            if !same_device {
+                remove_conflicting_entry(path, &metadata)?;
+            }
+            same_device
+        }
+        Err(_) => false,
+    };
+
+    if !existing_device {
+        match stat::mknod(path, sflag, Mode::from_bits_truncate(file_mode), dev) {
+            Err(Errno::EPERM) => {
+                // The device of the host is only bind mounted if it's the same device
+                let host_device = fs::metadata(device.path())
+                    .ok()
+                    .filter(|metadata| is_same_device(metadata, sflag, dev));
+                if host_device.is_none() {
+                    bail!(
+                        "failed to create {} with mknod, which is not permitted (EPERM), and the host has no such device to bind mount",
+                        device.path().display()
+                    );
+                }
 
-    fs::set_permissions(path, Permissions::from_mode(0o660)).context(format!(
+                debug!(
+                    "bind mounting {} from the host, since mknod is not permitted",
+                    device.path().display()
+                );
+                return create_device_bind(rootfs, device);
+            }
+            result => result.context(format!(
+                "failed to create {} with mknod in {}",
+                device.path().display(),
+                path.parent().unwrap_or(rootfs).display(),
+            ))?,
+        }
+    }
+
+    // The device that is shipped in the root file system might have a different mode
+    fs::set_permissions(path, Permissions::from_mode(file_mode)).context(format!(
         "failed to change the permission of {}",
         path.display(),
     ))?;
@@ -82,17 +260,140 @@ pub fn create_device(rootfs: &Path, device: &LinuxDevice) -> Result<()> {
     Ok(())
 }
 
+/// `create_device_bind` bind mounts the device at `device.path` of the host to the same path
+/// in `rootfs`, where an empty file is created as the mount point. The device keeps the mode
+/// and the ownership of the host.
+pub fn create_device_bind(rootfs: &Path, device: &LinuxDevice) -> Result<()> {
+    let path = &mount::secure_join(rootfs, device.path())?;
+    create_parent_directory(path)?;
+    if path.symlink_metadata().is_err() {
+        File::create(path).context(format!("failed to create {}", path.display()))?;
+    }
+
+    nix::mount::mount(
+        Some(device.path()),
+        path,
+        None::<&str>,
+        MsFlags::MS_BIND,
+        None::<&str>,
+    )
+    .context(format!(
+        "failed to bind mount {} from the host",
+        device.path().display()
+    ))?;
+    Ok(())
+}
+
+/// `create_console` allocates a pseudoterminal pair in the `devpts` instance of the container,
+/// which is accessible through `/dev/ptmx` in `rootfs`, and makes the slave the controlling terminal
+/// and the standard streams of the container process in a new session. The slave is owned by `owner`
+/// with the mode `0620`, and it's bind mounted onto `/dev/console` in `rootfs` if `mount_console`
+/// is `true`, where an empty file is created as the mount point. The master is returned,
+/// which should be sent to the `--console-socket` of the caller.
+/// For more information, see the [pty(7)](https://man7.org/linux/man-pages/man7/pty.7.html)
+/// and [console(4)](https://man7.org/linux/man-pages/man4/console.4.html) man pages.
+pub fn create_console(rootfs: &Path, owner: (Uid, Gid), mount_console: bool) -> Result<OwnedFd> {
+    let ptmx = symlink_path(rootfs, Path::new("/dev/ptmx"))?;
+    let master = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NOCTTY | libc::O_CLOEXEC)
+        .open(&ptmx)
+        .context(format!("failed to open {}", ptmx.display()))?;
+
+    let unlock = 0 as libc::c_int;
+    Errno::result(unsafe { libc::ioctl(master.as_raw_fd(), libc::TIOCSPTLCK, &unlock) })
+        .context("failed to unlock the pseudoterminal")?;
+    let mut index = 0 as libc::c_uint;
+    Errno::result(unsafe { libc::ioctl(master.as_raw_fd(), libc::TIOCGPTN, &mut index) })
+        .context("failed to get the index of the pseudoterminal")?;
+
+    let slave_path = symlink_path(rootfs, &Path::new("/dev/pts").join(index.to_string()))?;
+    let slave = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NOCTTY | libc::O_CLOEXEC)
+        .open(&slave_path)
+        .context(format!("failed to open {}", slave_path.display()))?;
+    let (uid, gid) = owner;
+    unistd::fchown(slave.as_raw_fd(), Some(uid), Some(gid)).context(format!(
+        "failed to change the owner of {}",
+        slave_path.display()
+    ))?;
+    slave
+        .set_permissions(Permissions::from_mode(0o620))
+        .context(format!(
+            "failed to change the mode of {}",
+            slave_path.display()
+        ))?;
+
+    if mount_console {
+        let path = &symlink_path(rootfs, Path::new("/dev/console"))?;
+        create_parent_directory(path)?;
+        if let Ok(metadata) = path.symlink_metadata() {
+            remove_conflicting_entry(path, &metadata)?;
+        }
+        File::create(path).context(format!("failed to create {}", path.display()))?;
+        nix::mount::mount(
+            Some(&slave_path),
+            path,
+            None::<&str>,
+            MsFlags::MS_BIND,
+            None::<&str>,
+        )
+        .context(format!(
+            "failed to bind mount {} to /dev/console",
+            slave_path.display()
+        ))?;
+    }
+
+    unistd::setsid().context("failed to create a new session")?;
+    Errno::result(unsafe { libc::ioctl(slave.as_raw_fd(), libc::TIOCSCTTY, 0) })
+        .context("failed to set the controlling terminal")?;
+    for fd in 0..=2 {
+        unistd::dup2(slave.as_raw_fd(), fd).context(format!(
+            "failed to redirect file descriptor {} to the pseudoterminal",
+            fd
+        ))?;
+    }
+    Ok(master.into())
+}
+
+/// `send_console` connects to `console_socket` and sends the master of the pseudoterminal
+/// allocated by [create_console], which matches the `--console-socket` flag of `runc`.
+/// For more information, see the [unix(7)](https://man7.org/linux/man-pages/man7/unix.7.html)
+/// man page.
+pub fn send_console(console_socket: &Path, master: BorrowedFd) -> Result<()> {
+    let stream = UnixStream::connect(console_socket).context(format!(
+        "failed to connect to the console socket {}",
+        console_socket.display()
+    ))?;
+    let fd_list = [master.as_raw_fd()];
+    socket::sendmsg::<()>(
+        stream.as_raw_fd(),
+        &[IoSlice::new(b"/dev/ptmx")],
+        &[ControlMessage::ScmRights(&fd_list)],
+        MsgFlags::empty(),
+        None,
+    )
+    .context(format!(
+        "failed to send the pseudoterminal to {}",
+        console_socket.display()
+    ))?;
+    Ok(())
+}
+
 /// `create_default_device` creates devices for the
 /// [default devices](https://github.com/opencontainers/runtime-spec/blob/main/config-linux.md#default-devices)
 /// specified in OCI runtime specification.
 pub fn create_default_device(rootfs: &Path) -> Result<()> {
     let default_device_list: [(&str, LinuxDeviceType, u32, u32, u32, u32, u32); 6] = [
-        ("/dev/null", LinuxDeviceType::C, 1, 3, 0o066, 0, 0),
-        ("/dev/zero", LinuxDeviceType::C, 1, 5, 0o066, 0, 0),
-        ("/dev/full", LinuxDeviceType::C, 1, 7, 0o066, 0, 0),
-        ("/dev/random", LinuxDeviceType::C, 1, 8, 0o066, 0, 0),
-        ("/dev/urandom", LinuxDeviceType::C, 1, 9, 0o066, 0, 0),
-        ("/dev/tty", LinuxDeviceType::C, 5, 0, 0o066, 0, 0),
+        ("/dev/null", LinuxDeviceType::C, 1, 3, 0o666, 0, 0),
+        ("/dev/zero", LinuxDeviceType::C, 1, 5, 0o666, 0, 0),
+        ("/dev/full", LinuxDeviceType::C, 1, 7, 0o666, 0, 0),
+        ("/dev/random", LinuxDeviceType::C, 1, 8, 0o666, 0, 0),
+        ("/dev/urandom", LinuxDeviceType::C, 1, 9, 0o666, 0, 0),
+        ("/dev/tty", LinuxDeviceType::C, 5, 0, 0o666, 0, 0),
     ];
 
     for (path, typ, major, minor, file_mode, uid, gid) in default_device_list {
@@ -110,3 +411,206 @@ pub fn create_default_device(rootfs: &Path) -> Result<()> {
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+
+    use nix::sys::signal::{self, SigHandler, Signal};
+
+    use super::*;
+    use crate::test_util::{self, UMASK_LOCK};
+
+    #[test]
+    fn create_default_symlink_replaces_conflicts() {
+        let rootfs = test_util::test_directory("device", "symlink");
+        fs::create_dir_all(rootfs.join("dev")).unwrap();
+        unix::fs::symlink("/proc/self/fd", rootfs.join("dev/fd")).unwrap();
+        unix::fs::symlink("/dev/pts/ptmx", rootfs.join("dev/ptmx")).unwrap();
+        fs::write(rootfs.join("dev/stdin"), "").unwrap();
+
+        create_default_symlink(&rootfs).unwrap();
+        for (source, destination) in [
+            ("/proc/self/fd", "dev/fd"),
+            ("/proc/self/fd/0", "dev/stdin"),
+            ("/proc/self/fd/1", "dev/stdout"),
+            ("/proc/self/fd/2", "dev/stderr"),
+            ("pts/ptmx", "dev/ptmx"),
+        ] {
+            assert_eq!(
+                fs::read_link(rootfs.join(destination)).unwrap(),
+                Path::new(source)
+            );
+        }
+    }
+
+    #[test]
+    fn create_default_symlink_rejects_directory() {
+        let rootfs = test_util::test_directory("device", "symlink-directory");
+        fs::create_dir_all(rootfs.join("dev/stdout")).unwrap();
+        assert!(create_default_symlink(&rootfs).is_err());
+    }
+
+    /// `fifo_device` returns a named pipe at `path`, which could be created without privileges.
+    fn fifo_device(path: &str, file_mode: Option<u32>) -> LinuxDevice {
+        let mut device = LinuxDevice::default();
+        device.set_path(PathBuf::from(path));
+        device.set_typ(LinuxDeviceType::P);
+        device.set_file_mode(file_mode);
+        device
+    }
+
+    #[test]
+    fn create_device_file_mode() {
+        let rootfs = test_util::test_directory("device", "file-mode");
+        let _lock = UMASK_LOCK.lock().unwrap();
+        let umask = stat::umask(Mode::from_bits_truncate(0o022));
+
+        create_device(&rootfs, &fifo_device("/dev/fifo", None)).unwrap();
+        create_device(&rootfs, &fifo_device("/dev/private", Some(0o640))).unwrap();
+        stat::umask(umask);
+
+        let mode = |path: &str| fs::metadata(rootfs.join(path)).unwrap().mode();
+        assert_eq!(mode("dev/fifo") & 0o7777, 0o666);
+        assert_eq!(mode("dev/private") & 0o7777, 0o640);
+        assert_eq!(
+            mode("dev/fifo") & SFlag::S_IFMT.bits(),
+            SFlag::S_IFIFO.bits()
+        );
+    }
+
+    #[test]
+    fn create_device_parent_directory() {
+        let rootfs = test_util::test_directory("device", "parent");
+        let _lock = UMASK_LOCK.lock().unwrap();
+        let umask = stat::umask(Mode::from_bits_truncate(0o022));
+        create_device(&rootfs, &fifo_device("/dev/net/fifo", None)).unwrap();
+        stat::umask(umask);
+
+        assert!(rootfs.join("dev/net/fifo").exists());
+        let mode = fs::metadata(rootfs.join("dev/net")).unwrap().mode();
+        assert_eq!(mode & 0o777, 0o755);
+    }
+
+    #[test]
+    fn create_device_keeps_or_replaces_existing_entry() {
+        let rootfs = test_util::test_directory("device", "existing");
+        fs::create_dir_all(rootfs.join("dev")).unwrap();
+        unistd::mkfifo(&rootfs.join("dev/fifo"), Mode::from_bits_truncate(0o600)).unwrap();
+        fs::write(rootfs.join("dev/file"), "").unwrap();
+        fs::create_dir_all(rootfs.join("dev/directory")).unwrap();
+        let inode = fs::metadata(rootfs.join("dev/fifo")).unwrap().ino();
+
+        let _lock = UMASK_LOCK.lock().unwrap();
+        create_device(&rootfs, &fifo_device("/dev/fifo", None)).unwrap();
+        create_device(&rootfs, &fifo_device("/dev/file", None)).unwrap();
+        assert!(create_device(&rootfs, &fifo_device("/dev/directory", None)).is_err());
+
+        // The same device is kept with the mode of the bundle configuration
+        let metadata = fs::metadata(rootfs.join("dev/fifo")).unwrap();
+        assert_eq!(metadata.ino(), inode);
+        assert_eq!(metadata.mode() & 0o7777, 0o666);
+        let mode = fs::metadata(rootfs.join("dev/file")).unwrap().mode();
+        assert_eq!(mode & SFlag::S_IFMT.bits(), SFlag::S_IFIFO.bits());
+    }
+
+    #[test]
+    fn create_device_skips_ptmx() {
+        let rootfs = test_util::test_directory("device", "ptmx");
+        let mut device = LinuxDevice::default();
+        device.set_path(PathBuf::from("/dev/ptmx"));
+        device.set_typ(LinuxDeviceType::C);
+        device.set_major(5);
+        device.set_minor(2);
+
+        create_device(&rootfs, &device).unwrap();
+        assert!(rootfs.join("dev/ptmx").symlink_metadata().is_err());
+        create_default_symlink(&rootfs).unwrap();
+        assert_eq!(
+            fs::read_link(rootfs.join("dev/ptmx")).unwrap(),
+            Path::new("pts/ptmx")
+        );
+    }
+
+    #[test]
+    fn umask_guard_clears_and_restores_umask() {
+        let _lock = UMASK_LOCK.lock().unwrap();
+        let umask = stat::umask(Mode::from_bits_truncate(0o027));
+        {
+            let _umask_guard = UmaskGuard::new();
+            assert_eq!(stat::umask(Mode::empty()), Mode::empty());
+        }
+        assert_eq!(stat::umask(umask).bits(), 0o027);
+    }
+
+    #[test]
+    fn host_device_number_of_null() {
+        assert_eq!(
+            host_device_number(Path::new("/dev/null"), SFlag::S_IFCHR).unwrap(),
+            stat::makedev(1, 3)
+        );
+        assert!(host_device_number(Path::new("/dev/null"), SFlag::S_IFBLK).is_err());
+        assert!(host_device_number(Path::new("/dev/reno-missing"), SFlag::S_IFCHR).is_err());
+    }
+
+    #[test]
+    fn device_number_omitted_or_explicit() {
+        let mut device = LinuxDevice::default();
+        device.set_path(PathBuf::from("/dev/null"));
+        device.set_typ(LinuxDeviceType::C);
+        device.set_major(0);
+        device.set_minor(0);
+        assert_eq!(device_number(&device).unwrap(), stat::makedev(1, 3));
+
+        device.set_path(PathBuf::from("/dev/reno-missing"));
+        device.set_major(10);
+        device.set_minor(200);
+        assert_eq!(device_number(&device).unwrap(), stat::makedev(10, 200));
+
+        let fifo = fifo_device("/dev/reno-missing", None);
+        assert_eq!(device_number(&fifo).unwrap(), 0);
+    }
+
+    #[test]
+    fn create_console_echoes_to_master() {
+        if !test_util::is_root() {
+            return;
+        }
+
+        let rootfs = test_util::test_directory("device", "console");
+        fs::create_dir_all(rootfs.join("dev/pts")).unwrap();
+        unix::fs::symlink("pts/ptmx", rootfs.join("dev/ptmx")).unwrap();
+        test_util::run_in_mount_namespace(|| {
+            nix::mount::mount(
+                Some("devpts"),
+                &rootfs.join("dev/pts"),
+                Some("devpts"),
+                MsFlags::empty(),
+                Some("newinstance,ptmxmode=0666"),
+            )
+            .unwrap();
+            let owner = (Uid::from_raw(1000), Gid::from_raw(5));
+            let master = File::from(create_console(&rootfs, owner, true).unwrap());
+
+            // Closing the master hangs up the controlling terminal once the test returns
+            unsafe { signal::signal(Signal::SIGHUP, SigHandler::SigIgn) }.unwrap();
+
+            let console = rootfs.join("dev/console");
+            let metadata = fs::metadata(&console).unwrap();
+            assert_eq!((metadata.uid(), metadata.gid()), (1000, 5));
+            assert_eq!(metadata.permissions().mode() & 0o7777, 0o620);
+            assert!(unistd::isatty(0).unwrap());
+            assert!(File::open("/dev/tty").is_ok());
+
+            OpenOptions::new()
+                .write(true)
+                .open(&console)
+                .unwrap()
+                .write_all(b"console\n")
+                .unwrap();
+            let mut buffer = [0; 64];
+            let length = (&master).read(&mut buffer).unwrap();
+            assert_eq!(&buffer[..length], b"console\r\n");
+        });
+    }
+}
@@ -1,4 +1,4 @@
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use nix::unistd;
 
 /// `set_hostname` updates the system hostname to the given string.
@@ -8,3 +8,19 @@ pub fn set_hostname(hostname: &str) -> Result<()> {
     unistd::sethostname(hostname).context("failed to set the system hostname")?;
     Ok(())
 }
+
+/// The maximum length of the hostname in bytes, which is `HOST_NAME_MAX` of the kernel.
+const HOST_NAME_MAX: usize = 64;
+
+/// `validate_hostname` checks that `hostname` is not empty and not longer than [HOST_NAME_MAX],
+/// which is rejected by `sethostname` with `EINVAL`.
+pub fn validate_hostname(hostname: &str) -> Result<()> {
+    if hostname.is_empty() || hostname.len() > HOST_NAME_MAX {
+        bail!(
+            "the hostname {} should be between 1 and {} bytes",
+            hostname,
+            HOST_NAME_MAX
+        );
+    }
+    Ok(())
+}
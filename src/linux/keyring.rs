@@ -0,0 +1,26 @@
+use std::ptr;
+
+use anyhow::{Context, Result};
+use nix::errno::Errno;
+
+const KEYCTL_JOIN_SESSION_KEYRING: libc::c_long = 1;
+
+/// `join_session_keyring` creates a new anonymous session keyring and attaches it to the
+/// container process, which prevents the container from reading the keys in the session keyring
+/// of the runtime. The kernel might be compiled without the key retention service,
+/// in which case the container process keeps running without a session keyring.
+/// For more information, see the [keyctl(2)](https://man7.org/linux/man-pages/man2/keyctl.2.html)
+/// man page.
+pub fn join_session_keyring() -> Result<()> {
+    let result = unsafe {
+        libc::syscall(
+            libc::SYS_keyctl,
+            KEYCTL_JOIN_SESSION_KEYRING,
+            ptr::null::<libc::c_char>(),
+        )
+    };
+    match Errno::result(result) {
+        Ok(_) | Err(Errno::ENOSYS) => Ok(()),
+        Err(err) => Err(err).context("failed to join a new session keyring"),
+    }
+}
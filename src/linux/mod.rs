@@ -1,8 +1,14 @@
 pub mod cap;
+pub mod cgroup;
+pub mod cgroup_systemd;
 pub mod device;
 pub mod hostname;
+pub mod keyring;
 pub mod mount;
+pub mod mount_api;
 pub mod namespace;
+pub mod personality;
 pub mod process;
 pub mod rlimit;
+pub mod seccomp;
 pub mod sysctl;
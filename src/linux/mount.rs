@@ -1,24 +1,50 @@
-use std::{ffi::OsString, fs, path::Path};
+use std::{
+    cmp::Reverse,
+    collections::VecDeque,
+    ffi::{CString, OsStr, OsString},
+    fs::{self, File},
+    io::ErrorKind,
+    os::unix::{
+        ffi::{OsStrExt, OsStringExt},
+        fs::{symlink, MetadataExt},
+    },
+    path::{Component, Path, PathBuf},
+};
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
+use log::{debug, warn};
 use nix::{
+    errno::Errno,
+    fcntl::AtFlags,
     mount::{self, MntFlags, MsFlags},
-    unistd,
+    sys::{
+        stat::{self, Mode, SFlag},
+        statvfs::{self, FsFlags},
+    },
+    unistd::{self, Gid, Uid},
 };
-use oci_spec::runtime::Mount;
+use oci_spec::runtime::{self, Mount};
+
+use crate::linux::mount_api;
 
 /// `mount_rootfs` changes the propagation type of the root mount
 /// from "shared" to "private", and then remounts the root mount to
-/// clone it in the current namespace.
-pub fn mount_rootfs(rootfs: &Path) -> Result<()> {
+/// clone it in the current namespace. If `propagation` is "shared" or "slave",
+/// the root mount is changed to "slave" instead, which keeps receiving mount events from the host.
+pub fn mount_rootfs(rootfs: &Path, propagation: MsFlags) -> Result<()> {
+    let root_propagation = if propagation.intersects(MsFlags::MS_SHARED | MsFlags::MS_SLAVE) {
+        MsFlags::MS_SLAVE | MsFlags::MS_REC
+    } else {
+        MsFlags::MS_PRIVATE | MsFlags::MS_REC
+    };
     mount::mount(
         None::<&str>,
         "/",
         None::<&str>,
-        MsFlags::MS_PRIVATE | MsFlags::MS_REC,
+        root_propagation,
         None::<&str>,
     )
-    .context("failed to change the propagation type of the root mount to private")?;
+    .context("failed to change the propagation type of the root mount")?;
 
     mount::mount(
         Some(rootfs),
@@ -32,80 +58,240 @@ pub fn mount_rootfs(rootfs: &Path) -> Result<()> {
     Ok(())
 }
 
-/// `pivot_rootfs` changes the root mount in the mount namespace.
-pub fn pivot_rootfs(rootfs: &Path, readonly: bool) -> Result<()> {
-    unistd::chdir(rootfs).context("failed to invoke chdir")?;
-    fs::create_dir_all(rootfs.join("root_archive")).context("failed to create ./root_archive")?;
+/// `switch_rootfs` changes the root mount to `rootfs` with [pivot_rootfs] if `use_pivot` is `true`,
+/// or with [chroot_rootfs] otherwise, which is also the fallback if `pivot_root` is not permitted.
+/// If `readonly` is `true`, the new root mount is remounted as read-only.
+pub fn switch_rootfs(rootfs: &Path, readonly: bool, use_pivot: bool) -> Result<()> {
+    if use_pivot {
+        pivot_rootfs(rootfs)?;
+    } else {
+        chroot_rootfs(rootfs)?;
+    }
 
-    // `pivot_root` moves the root mount to `root_archive` and makes `rootfs` as the new root mount
-    unistd::pivot_root(rootfs.as_os_str(), rootfs.join("root_archive").as_os_str())
-        .context("failed to invoke pivot_root")?;
+    if readonly {
+        remount_readonly(Path::new("/"))?;
+    }
 
-    mount::umount2("./root_archive", MntFlags::MNT_DETACH)
-        .context("failed to umount ./root_archive")?;
-    fs::remove_dir_all("./root_archive").context("failed to remove ./root_archive")?;
     unistd::chdir("/").context("failed to invoke chdir")?;
+    Ok(())
+}
 
-    if readonly {
-        mount::mount(
-            None::<&str>,
-            "/",
-            None::<&str>,
-            MsFlags::MS_RDONLY | MsFlags::MS_REMOUNT | MsFlags::MS_BIND,
-            None::<&str>,
-        )?;
+/// `remount_readonly` remounts the bind mount at `path` as read-only.
+/// In a rootless container, the mount is created in the mount namespace of the host, such as
+/// a `tmpfs` root, and the kernel locks its `nosuid`, `nodev`, `noexec`, and atime flags
+/// in the user namespace, so the remount that clears them fails with `EPERM`, or with `EINVAL`
+/// on some kernels. The remount is retried with the flags of the mount from `statvfs`.
+/// For more information, see the [mount_namespaces(7)](https://man7.org/linux/man-pages/man7/mount_namespaces.7.html)
+/// man page.
+fn remount_readonly(path: &Path) -> Result<()> {
+    let flags = MsFlags::MS_RDONLY | MsFlags::MS_REMOUNT | MsFlags::MS_BIND;
+    let errno = match mount::mount(None::<&str>, path, None::<&str>, flags, None::<&str>) {
+        Err(errno @ (Errno::EPERM | Errno::EINVAL)) => errno,
+        result => return result.context("failed to remount the root mount as read-only"),
+    };
+
+    let locked_flags = locked_mount_flags(path)?;
+    mount::mount(
+        None::<&str>,
+        path,
+        None::<&str>,
+        flags | locked_flags,
+        None::<&str>,
+    )
+    .map_err(|_| {
+        anyhow!(
+            "failed to remount the root mount as read-only ({}), which is not permitted on the root file system of the container, such as a tmpfs in a rootless container, even with the locked flags {:?}",
+            errno,
+            locked_flags
+        )
+    })?;
+    Ok(())
+}
+
+/// `locked_mount_flags` returns the flags of the mount at `path` that are locked
+/// in a user namespace, which must be preserved by a remount.
+/// For more information, see the [statvfs(3)](https://man7.org/linux/man-pages/man3/statvfs.3.html)
+/// man page.
+fn locked_mount_flags(path: &Path) -> Result<MsFlags> {
+    let stat = statvfs::statvfs(path).context(format!(
+        "failed to inspect the mount flags of {}",
+        path.display()
+    ))?;
+    let flag_list = [
+        (FsFlags::ST_NOSUID, MsFlags::MS_NOSUID),
+        (FsFlags::ST_NODEV, MsFlags::MS_NODEV),
+        (FsFlags::ST_NOEXEC, MsFlags::MS_NOEXEC),
+        (FsFlags::ST_NOATIME, MsFlags::MS_NOATIME),
+        (FsFlags::ST_NODIRATIME, MsFlags::MS_NODIRATIME),
+        (FsFlags::ST_RELATIME, MsFlags::MS_RELATIME),
+    ];
+    Ok(flag_list
+        .into_iter()
+        .filter(|(fs_flag, _)| stat.flags().contains(*fs_flag))
+        .fold(MsFlags::empty(), |flags, (_, ms_flag)| flags | ms_flag))
+}
+
+/// `pivot_rootfs` changes the root mount in the mount namespace with `pivot_root`,
+/// which detaches the old root mount from the mount namespace.
+/// `rootfs` is pivoted onto itself, which stacks the old root mount on top of `rootfs`,
+/// so that no directory is created in `rootfs` to hold the old root mount.
+/// `pivot_root` returns `EINVAL` if `rootfs` shares a mount with `/`, such as in a nested container
+/// or on an initramfs, where the root mount is changed with [chroot_rootfs] instead.
+/// For more information, see the [pivot_root(2)](https://man7.org/linux/man-pages/man2/pivot_root.2.html)
+/// man page.
+fn pivot_rootfs(rootfs: &Path) -> Result<()> {
+    unistd::chdir(rootfs).context("failed to invoke chdir")?;
+    match unistd::pivot_root(".", ".") {
+        Err(Errno::EINVAL) => {
+            warn!(
+                "pivot_root is not permitted on {}, falling back to chroot",
+                rootfs.display()
+            );
+            return chroot_rootfs(rootfs);
+        }
+        result => result.context("failed to invoke pivot_root")?,
     }
+
+    // Prevent the unmount of the old root mount from propagating to the mount namespace of the host
+    mount::mount(
+        None::<&str>,
+        "/",
+        None::<&str>,
+        MsFlags::MS_SLAVE | MsFlags::MS_REC,
+        None::<&str>,
+    )
+    .context("failed to change the propagation type of the old root mount")?;
+    mount::umount2(".", MntFlags::MNT_DETACH).context("failed to umount the old root mount")?;
     Ok(())
 }
 
+/// `chroot_rootfs` moves `rootfs` on top of `/` and changes the root directory to it with `chroot`,
+/// which works where `pivot_root` is not permitted, such as in a container whose root mount
+/// is on `rootfs`. Unlike `pivot_root`, the old root mount remains in the mount namespace
+/// beneath `rootfs`, which could be accessed by a process that escapes the `chroot`.
+/// For more information, see the [chroot(2)](https://man7.org/linux/man-pages/man2/chroot.2.html)
+/// man page.
+fn chroot_rootfs(rootfs: &Path) -> Result<()> {
+    warn!("changing the root mount with chroot, which is less secure than pivot_root, since the root mount of the host remains in the mount namespace");
+
+    unistd::chdir(rootfs).context("failed to invoke chdir")?;
+    mount::mount(Some("."), "/", None::<&str>, MsFlags::MS_MOVE, None::<&str>)
+        .context("failed to move the root file system to /")?;
+    unistd::chroot(".").context("failed to invoke chroot")?;
+    Ok(())
+}
+
+/// `set_rootfs_propagation` changes the propagation type of the new root mount after
+/// [pivot_rootfs], which determines whether mount events are propagated between
+/// the container and the host.
+/// For more information, see the [mount_namespaces(7)](https://man7.org/linux/man-pages/man7/mount_namespaces.7.html)
+/// man page.
+pub fn set_rootfs_propagation(propagation: MsFlags) -> Result<()> {
+    mount::mount(None::<&str>, "/", None::<&str>, propagation, None::<&str>)
+        .context("failed to change the propagation type of the new root mount")?;
+    Ok(())
+}
+
+/// `rootfs_propagation_to_msflags` converts `linux.rootfsPropagation` in the bundle configuration
+/// to [MsFlags], which is "private" if `propagation` is `None`.
+pub fn rootfs_propagation_to_msflags(propagation: Option<&str>) -> Result<MsFlags> {
+    const PROPAGATION_LIST: [&str; 8] = [
+        "private",
+        "rprivate",
+        "shared",
+        "rshared",
+        "slave",
+        "rslave",
+        "unbindable",
+        "runbindable",
+    ];
+
+    let propagation = propagation.unwrap_or("private");
+    MOUNT_OPTION_LIST
+        .iter()
+        .filter(|(option, _, _)| PROPAGATION_LIST.contains(option))
+        .find(|(option, _, _)| *option == propagation)
+        .map(|(_, _, flag)| *flag)
+        .context(format!(
+            "the root mount propagation {} is not supported",
+            propagation
+        ))
+}
+
+/// The mount options that are converted to [MsFlags], where each entry contains the option,
+/// whether the option clears the flag, and the flag.
+/// Other options are passed to the file system as the mount data.
+pub const MOUNT_OPTION_LIST: [(&str, bool, MsFlags); 39] = [
+    ("defaults", false, MsFlags::empty()),
+    ("ro", false, MsFlags::MS_RDONLY),
+    ("rw", true, MsFlags::MS_RDONLY),
+    ("suid", true, MsFlags::MS_NOSUID),
+    ("nosuid", false, MsFlags::MS_NOSUID),
+    ("dev", true, MsFlags::MS_NODEV),
+    ("nodev", false, MsFlags::MS_NODEV),
+    ("exec", true, MsFlags::MS_NOEXEC),
+    ("noexec", false, MsFlags::MS_NOEXEC),
+    ("sync", false, MsFlags::MS_SYNCHRONOUS),
+    ("async", true, MsFlags::MS_SYNCHRONOUS),
+    ("dirsync", false, MsFlags::MS_DIRSYNC),
+    ("remount", false, MsFlags::MS_REMOUNT),
+    ("mand", false, MsFlags::MS_MANDLOCK),
+    ("nomand", true, MsFlags::MS_MANDLOCK),
+    ("atime", true, MsFlags::MS_NOATIME),
+    ("noatime", false, MsFlags::MS_NOATIME),
+    ("diratime", true, MsFlags::MS_NODIRATIME),
+    ("nodiratime", false, MsFlags::MS_NODIRATIME),
+    ("bind", false, MsFlags::MS_BIND),
+    ("rbind", false, MsFlags::MS_BIND.union(MsFlags::MS_REC)),
+    ("unbindable", false, MsFlags::MS_UNBINDABLE),
+    (
+        "runbindable",
+        false,
+        MsFlags::MS_UNBINDABLE.union(MsFlags::MS_REC),
+    ),
+    ("private", true, MsFlags::MS_PRIVATE),
+    ("rprivate", true, MsFlags::MS_PRIVATE.union(MsFlags::MS_REC)),
+    ("shared", true, MsFlags::MS_SHARED),
+    ("rshared", true, MsFlags::MS_SHARED.union(MsFlags::MS_REC)),
+    ("slave", true, MsFlags::MS_SLAVE),
+    ("rslave", true, MsFlags::MS_SLAVE.union(MsFlags::MS_REC)),
+    ("relatime", true, MsFlags::MS_RELATIME),
+    ("norelatime", true, MsFlags::MS_RELATIME),
+    ("strictatime", true, MsFlags::MS_STRICTATIME),
+    ("nostrictatime", true, MsFlags::MS_STRICTATIME),
+    ("lazytime", false, MsFlags::MS_LAZYTIME),
+    ("nolazytime", true, MsFlags::MS_LAZYTIME),
+    ("silent", false, MsFlags::MS_SILENT),
+    ("loud", true, MsFlags::MS_SILENT),
+    ("symfollow", true, MS_NOSYMFOLLOW),
+    ("nosymfollow", false, MS_NOSYMFOLLOW),
+];
+
+/// `MS_NOSYMFOLLOW` prevents following symbolic links when resolving paths in the mount,
+/// which is supported since Linux 5.10.
+const MS_NOSYMFOLLOW: MsFlags = MsFlags::from_bits_retain(0x100);
+
 fn mount_to_msflags(mount: &Mount) -> (MsFlags, OsString) {
     let mut mount_flags = MsFlags::empty();
     let mut mount_data = Vec::new();
 
     if let Some(options) = &mount.options() {
         for option in options {
-            if let Some((is_clear, flag)) = match option.as_ref() {
-                "defaults" => Some((false, MsFlags::empty())),
-                "ro" => Some((false, MsFlags::MS_RDONLY)),
-                "rw" => Some((true, MsFlags::MS_RDONLY)),
-                "suid" => Some((true, MsFlags::MS_NOSUID)),
-                "nosuid" => Some((false, MsFlags::MS_NOSUID)),
-                "dev" => Some((true, MsFlags::MS_NODEV)),
-                "nodev" => Some((false, MsFlags::MS_NODEV)),
-                "exec" => Some((true, MsFlags::MS_NOEXEC)),
-                "noexec" => Some((false, MsFlags::MS_NOEXEC)),
-                "sync" => Some((false, MsFlags::MS_SYNCHRONOUS)),
-                "async" => Some((true, MsFlags::MS_SYNCHRONOUS)),
-                "dirsync" => Some((false, MsFlags::MS_DIRSYNC)),
-                "remount" => Some((false, MsFlags::MS_REMOUNT)),
-                "mand" => Some((false, MsFlags::MS_MANDLOCK)),
-                "nomand" => Some((true, MsFlags::MS_MANDLOCK)),
-                "atime" => Some((true, MsFlags::MS_NOATIME)),
-                "noatime" => Some((false, MsFlags::MS_NOATIME)),
-                "diratime" => Some((true, MsFlags::MS_NODIRATIME)),
-                "nodiratime" => Some((false, MsFlags::MS_NODIRATIME)),
-                "bind" => Some((false, MsFlags::MS_BIND)),
-                "rbind" => Some((false, MsFlags::MS_BIND | MsFlags::MS_REC)),
-                "unbindable" => Some((false, MsFlags::MS_UNBINDABLE)),
-                "runbindable" => Some((false, MsFlags::MS_UNBINDABLE | MsFlags::MS_REC)),
-                "private" => Some((true, MsFlags::MS_PRIVATE)),
-                "rprivate" => Some((true, MsFlags::MS_PRIVATE | MsFlags::MS_REC)),
-                "shared" => Some((true, MsFlags::MS_SHARED)),
-                "rshared" => Some((true, MsFlags::MS_SHARED | MsFlags::MS_REC)),
-                "slave" => Some((true, MsFlags::MS_SLAVE)),
-                "rslave" => Some((true, MsFlags::MS_SLAVE | MsFlags::MS_REC)),
-                "relatime" => Some((true, MsFlags::MS_RELATIME)),
-                "norelatime" => Some((true, MsFlags::MS_RELATIME)),
-                "strictatime" => Some((true, MsFlags::MS_STRICTATIME)),
-                "nostrictatime" => Some((true, MsFlags::MS_STRICTATIME)),
-                _ => None,
-            } {
-                if is_clear {
-                    mount_flags &= !flag;
+            if let Some((_, is_clear, flag)) =
+                MOUNT_OPTION_LIST.iter().find(|(name, _, _)| name == option)
+            {
+                if *is_clear {
+                    mount_flags &= !*flag;
                 } else {
-                    mount_flags |= flag;
+                    mount_flags |= *flag;
                 }
-            } else {
+            } else if option != "tmpcopyup" && option != "rro" {
+                // `tmpcopyup` and `rro` are handled by the runtime instead of the file system
+                debug!(
+                    "passing the mount option {} of {} to the file system",
+                    option,
+                    mount.destination().display()
+                );
                 mount_data.push(option.as_ref());
             }
         }
@@ -114,28 +300,1767 @@ fn mount_to_msflags(mount: &Mount) -> (MsFlags, OsString) {
     (mount_flags, mount_data.join(",").into())
 }
 
+/// `secure_join` joins `subpath` to `rootfs` and resolves every symbolic link in the result
+/// as if `rootfs` were the root directory, so that the result never escapes `rootfs`.
+/// Absolute symbolic links are resolved relative to `rootfs`, and `..` components in the
+/// symbolic links stop at `rootfs`, while `..` components in `subpath` that lexically traverse
+/// beyond `rootfs` are rejected. The components that don't exist are joined without resolution.
+/// For more information, see the [path_resolution(7)](https://man7.org/linux/man-pages/man7/path_resolution.7.html)
+/// man page.
+pub fn secure_join(rootfs: &Path, subpath: &Path) -> Result<PathBuf> {
+    const MAX_SYMLINK_COUNT: usize = 255;
+
+    // The `..` components are checked lexically, since a symbolic link in `subpath`
+    // might point to `/`, where the following `..` components are not an escape
+    let mut depth: usize = 0;
+    for component in subpath.components() {
+        match component {
+            Component::Normal(_) => depth += 1,
+            Component::ParentDir if depth == 0 => bail!(
+                "the path {} escapes the root file system",
+                subpath.display()
+            ),
+            Component::ParentDir => depth -= 1,
+            _ => (),
+        }
+    }
+
+    let mut component_list: VecDeque<OsString> = subpath
+        .components()
+        .map(|component| component.as_os_str().to_os_string())
+        .collect();
+    let mut resolved = PathBuf::new();
+    let mut symlink_count = 0;
+
+    while let Some(component) = component_list.pop_front() {
+        match Path::new(&component).components().next() {
+            Some(Component::RootDir | Component::CurDir) | None => continue,
+            Some(Component::ParentDir) => {
+                resolved.pop();
+                continue;
+            }
+            _ => (),
+        }
+
+        let candidate = resolved.join(&component);
+        let path = rootfs.join(&candidate);
+        match fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                symlink_count += 1;
+                if symlink_count > MAX_SYMLINK_COUNT {
+                    bail!(
+                        "the path {} contains too many symbolic links",
+                        subpath.display()
+                    );
+                }
+
+                let target = fs::read_link(&path).context(format!(
+                    "failed to read the symbolic link {}",
+                    path.display()
+                ))?;
+                if target.is_absolute() {
+                    resolved = PathBuf::new();
+                }
+                for target_component in target.components().rev() {
+                    component_list.push_front(target_component.as_os_str().to_os_string());
+                }
+            }
+            _ => resolved = candidate,
+        }
+    }
+    Ok(rootfs.join(resolved))
+}
+
 /// `custom_mount` accepts a [Mount] struct defined in the bundle configuration
 /// and mounts the source to the destination with specified options.
 pub fn custom_mount(rootfs: &Path, mount: &Mount) -> Result<()> {
-    let destination = rootfs.join(
-        mount
-            .destination()
-            .display()
-            .to_string()
-            .trim_start_matches('/'),
-    );
-    if !destination.exists() {
-        fs::create_dir_all(&destination)?;
+    let destination = secure_join(rootfs, mount.destination())?;
+    let (mount_flags, mount_data) = mount_to_msflags(mount);
+    if mount_flags.contains(MS_NOSYMFOLLOW) && kernel_version()? < (5, 10) {
+        bail!(
+            "nosymfollow of {} is not supported on this kernel, which is older than Linux 5.10",
+            mount.destination().display()
+        );
     }
 
-    let (mount_flags, mount_data) = mount_to_msflags(mount);
+    // The bind mount of a file, such as `/etc/resolv.conf` or a socket,
+    // requires a file as its destination
+    let file_bind_mount = mount_flags.contains(MsFlags::MS_BIND)
+        && mount
+            .source()
+            .as_ref()
+            .and_then(|source| fs::metadata(source).ok())
+            .is_some_and(|metadata| !metadata.is_dir());
+
+    match fs::metadata(&destination) {
+        Ok(metadata) if file_bind_mount && metadata.is_dir() => bail!(
+            "the destination {} of the file bind mount is a directory",
+            mount.destination().display()
+        ),
+        Ok(metadata) if !file_bind_mount && !metadata.is_dir() => bail!(
+            "the destination {} of the mount is not a directory",
+            mount.destination().display()
+        ),
+        _ => (),
+    }
+
+    let tmpcopyup = mount.typ().as_deref() == Some("tmpfs")
+        && mount
+            .options()
+            .as_ref()
+            .is_some_and(|options| options.iter().any(|option| option == "tmpcopyup"));
+    if tmpcopyup && destination.is_dir() {
+        return tmpcopyup_mount(rootfs, &destination, mount_flags, &mount_data)
+            .context(format!("failed to mount {}", mount.destination().display()));
+    }
+
+    let mount_destination = |mount_flags: MsFlags| {
+        if let Some(source) = mount.source() {
+            if mount_flags.contains(MsFlags::MS_BIND) {
+                return bind_mount(
+                    rootfs,
+                    mount.destination(),
+                    &destination,
+                    source,
+                    mount_flags,
+                );
+            }
+        }
+
+        mount::mount(
+            mount.source().as_ref(),
+            &destination,
+            mount.typ().as_deref(),
+            mount_flags,
+            Some(mount_data.as_os_str()),
+        )
+    };
+
+    // The destination is only created if it doesn't exist, since its parent might be covered
+    // by an earlier mount, such as a read-only mount, where the destination can't be created
+    let mut result = match mount_destination(mount_flags) {
+        Err(Errno::ENOENT) => {
+            create_mount_destination(&destination, file_bind_mount)?;
+            mount_destination(mount_flags)
+        }
+        result => result,
+    };
+
+    // Some file systems reject the optional flags, which are dropped before the mount is retried
+    let mut mount_flags = mount_flags;
+    let optional_flags = mount_flags & OPTIONAL_FLAGS;
+    if result.is_err() && !optional_flags.is_empty() {
+        warn!(
+            "retrying the mount of {} without the optional flags {:?}",
+            mount.destination().display(),
+            optional_flags
+        );
+        mount_flags -= optional_flags;
+        result = mount_destination(mount_flags);
+    }
+    result.map_err(|errno| {
+        anyhow!(
+            "failed to mount {} with type {}, flags {:?}, and data {:?}: {}",
+            mount.destination().display(),
+            mount.typ().as_deref().unwrap_or("none"),
+            mount_flags,
+            mount_data,
+            errno
+        )
+    })?;
+
+    let recursive_readonly = mount
+        .options()
+        .as_ref()
+        .is_some_and(|options| options.iter().any(|option| option == "rro"));
+    if recursive_readonly {
+        mount_api::set_readonly(&destination, true).context(format!(
+            "failed to make {} recursively read-only, which requires Linux 5.12 or later",
+            mount.destination().display()
+        ))?;
+    } else if mount_flags.contains(MsFlags::MS_BIND) && mount_flags.intersects(REMOUNT_FLAGS) {
+        remount_bind(&destination, mount_flags).context(format!(
+            "failed to remount {}",
+            mount.destination().display()
+        ))?;
+    }
+    Ok(())
+}
+
+/// `bind_mount` bind mounts `source` to `destination` in `rootfs`, which is attached with
+/// [mount_api::bind_mount_tree] if the kernel supports the new mount API, or with `mount`
+/// on `resolved_destination` otherwise, which is `destination` resolved with [secure_join].
+/// The per-mount flags in `mount_flags` are ignored by both, which should be applied with
+/// [remount_bind].
+fn bind_mount(
+    rootfs: &Path,
+    destination: &Path,
+    resolved_destination: &Path,
+    source: &Path,
+    mount_flags: MsFlags,
+) -> nix::Result<()> {
+    with_enosys_fallback(
+        || {
+            mount_api::bind_mount_tree(
+                rootfs,
+                destination,
+                source,
+                mount_flags.contains(MsFlags::MS_REC),
+            )
+        },
+        || legacy_bind_mount(resolved_destination, source, mount_flags),
+    )
+}
+
+/// `legacy_bind_mount` bind mounts `source` to `resolved_destination` with `mount`,
+/// which is the fallback of [bind_mount] on kernels without the new mount API.
+fn legacy_bind_mount(
+    resolved_destination: &Path,
+    source: &Path,
+    mount_flags: MsFlags,
+) -> nix::Result<()> {
     mount::mount(
-        mount.source().as_ref(),
-        &destination,
-        mount.typ().as_deref(),
+        Some(source),
+        resolved_destination,
+        None::<&str>,
         mount_flags,
-        Some(mount_data).as_deref(),
+        None::<&str>,
+    )
+}
+
+/// `with_enosys_fallback` returns the result of `mount_api`, or the result of `fallback`
+/// if `mount_api` fails with `ENOSYS`, which means the kernel doesn't support the new mount API.
+fn with_enosys_fallback(
+    mount_api: impl FnOnce() -> nix::Result<()>,
+    fallback: impl FnOnce() -> nix::Result<()>,
+) -> nix::Result<()> {
+    match mount_api() {
+        Err(Errno::ENOSYS) => fallback(),
+        result => result,
+    }
+}
+
+/// `readonly_path` makes `path` in `linux.readonlyPaths` read-only, together with its submounts,
+/// by bind mounting it onto itself and remounting the bind mount, which should be invoked
+/// after the root mount is changed. A path that doesn't exist is skipped.
+/// For more information, see the
+/// [runtime specification](https://github.com/opencontainers/runtime-spec/blob/main/config-linux.md#readonly-paths).
+pub fn readonly_path(path: &Path) -> Result<()> {
+    let mount_flags = MsFlags::MS_BIND | MsFlags::MS_REC;
+    match bind_mount(Path::new("/"), path, path, path, mount_flags) {
+        Err(Errno::ENOENT) => return Ok(()),
+        result => result.context(format!("failed to bind mount {}", path.display()))?,
+    }
+    remount_bind(path, mount_flags | MsFlags::MS_RDONLY)
+        .context(format!("failed to make {} read-only", path.display()))?;
+    Ok(())
+}
+
+/// `mask_path` hides `path` in `linux.maskedPaths` from the container, where a directory is covered
+/// by a read-only `tmpfs`, and a file is covered by a bind mount of `/dev/null`,
+/// which should be invoked after the root mount is changed. A path that doesn't exist is skipped.
+/// For more information, see the
+/// [runtime specification](https://github.com/opencontainers/runtime-spec/blob/main/config-linux.md#masked-paths).
+pub fn mask_path(path: &Path) -> Result<()> {
+    let metadata = match fs::metadata(path) {
+        Err(error) if error.kind() == ErrorKind::NotFound => return Ok(()),
+        result => result.context(format!("failed to inspect {}", path.display()))?,
+    };
+
+    let result = if metadata.is_dir() {
+        mount::mount(
+            Some("tmpfs"),
+            path,
+            Some("tmpfs"),
+            MsFlags::MS_RDONLY,
+            None::<&str>,
+        )
+    } else {
+        bind_mount(
+            Path::new("/"),
+            path,
+            path,
+            Path::new("/dev/null"),
+            MsFlags::MS_BIND,
+        )
+    };
+    match result {
+        Err(Errno::ENOENT) => Ok(()),
+        result => result.context(format!("failed to mask {}", path.display())),
+    }
+}
+
+/// The flags that don't affect the security of the mount, which are dropped if the file system
+/// rejects them. `MS_RDONLY`, `MS_NOSUID`, `MS_NODEV`, and `MS_NOEXEC` are never dropped.
+const OPTIONAL_FLAGS: MsFlags = MsFlags::MS_DIRSYNC
+    .union(MsFlags::MS_SYNCHRONOUS)
+    .union(MsFlags::MS_MANDLOCK)
+    .union(MsFlags::MS_NOATIME)
+    .union(MsFlags::MS_NODIRATIME)
+    .union(MsFlags::MS_RELATIME)
+    .union(MsFlags::MS_STRICTATIME)
+    .union(MsFlags::MS_LAZYTIME)
+    .union(MsFlags::MS_SILENT);
+
+/// The per-mount flags that are ignored when a bind mount is created,
+/// which are applied by remounting the bind mount.
+const REMOUNT_FLAGS: MsFlags = MsFlags::MS_RDONLY
+    .union(MsFlags::MS_NOSUID)
+    .union(MsFlags::MS_NODEV)
+    .union(MsFlags::MS_NOEXEC)
+    .union(MsFlags::MS_NOATIME)
+    .union(MsFlags::MS_NODIRATIME)
+    .union(MsFlags::MS_RELATIME)
+    .union(MsFlags::MS_STRICTATIME);
+
+/// `remount_bind` applies the per-mount flags in `mount_flags` to the bind mount at `destination`,
+/// since the kernel ignores them when the bind mount is created, which leaves a `ro` bind mount
+/// writable. The flags of the mount are merged into the remount, since the kernel rejects
+/// a remount that clears the flags locked by a less privileged mount namespace.
+/// A remount doesn't affect the submounts of a recursive bind mount, which are made read-only
+/// with `mount_setattr` on kernels that support it.
+fn remount_bind(destination: &Path, mount_flags: MsFlags) -> Result<()> {
+    let current_flags = current_mount_flags(destination)?;
+    mount::mount(
+        None::<&str>,
+        destination,
+        None::<&str>,
+        MsFlags::MS_BIND | MsFlags::MS_REMOUNT | (mount_flags & REMOUNT_FLAGS) | current_flags,
+        None::<&str>,
     )?;
 
+    if mount_flags.contains(MsFlags::MS_RDONLY | MsFlags::MS_REC) {
+        match mount_api::set_readonly(destination, true) {
+            Err(Errno::ENOSYS) => warn!(
+                "the submounts of {} are writable, since making them read-only requires Linux 5.12 or later",
+                destination.display()
+            ),
+            result => result.context(format!(
+                "failed to make the submounts of {} read-only",
+                destination.display()
+            ))?,
+        }
+    }
+    Ok(())
+}
+
+/// `current_mount_flags` returns the per-mount flags of the mount at `destination`,
+/// which are read from `/proc/self/mountinfo`.
+/// For more information, see the [proc_pid_mountinfo(5)](https://man7.org/linux/man-pages/man5/proc_pid_mountinfo.5.html)
+/// man page.
+fn current_mount_flags(destination: &Path) -> Result<MsFlags> {
+    let mount_info = read_mountinfo()?
+        .into_iter()
+        .rev()
+        .find(|mount_info| mount_info.mount_point == destination)
+        .context(format!(
+            "failed to find the mount {}",
+            destination.display()
+        ))?;
+
+    let mount_flags = mount_info
+        .mount_options
+        .iter()
+        .filter_map(|option| {
+            MOUNT_OPTION_LIST
+                .iter()
+                .find(|(name, is_clear, flag)| {
+                    name == option && !is_clear && REMOUNT_FLAGS.contains(*flag)
+                })
+                .map(|(_, _, flag)| *flag)
+        })
+        .fold(MsFlags::empty(), |mount_flags, flag| mount_flags | flag);
+    Ok(mount_flags)
+}
+
+/// `MountInfo` is a line of `/proc/self/mountinfo`, which describes a mount in the mount namespace.
+/// For more information, see the [proc_pid_mountinfo(5)](https://man7.org/linux/man-pages/man5/proc_pid_mountinfo.5.html)
+/// man page.
+#[derive(Debug, PartialEq, Eq)]
+pub struct MountInfo {
+    pub id: u32,
+    pub parent_id: u32,
+    pub mount_point: PathBuf,
+    pub mount_options: Vec<String>,
+    pub optional_fields: Vec<String>,
+    pub fs_type: String,
+}
+
+/// `parse_mountinfo` parses the content of `/proc/self/mountinfo`, where the optional fields
+/// are terminated by a single hyphen, and the mount point escapes the space, tab, newline,
+/// and backslash as octal sequences, such as `\040`.
+pub fn parse_mountinfo(content: &str) -> Result<Vec<MountInfo>> {
+    content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let mut field_list = line.split_whitespace();
+            let mut next_field = || {
+                field_list
+                    .next()
+                    .context(format!("invalid mountinfo line: {}", line))
+            };
+            let id = next_field()?
+                .parse()
+                .context(format!("invalid mount id: {}", line))?;
+            let parent_id = next_field()?
+                .parse()
+                .context(format!("invalid parent mount id: {}", line))?;
+            let _device = next_field()?;
+            let _root = next_field()?;
+            let mount_point = PathBuf::from(OsString::from_vec(unescape_mountinfo(next_field()?)));
+            let mount_options = next_field()?.split(',').map(String::from).collect();
+            let mut optional_fields = Vec::new();
+            loop {
+                match next_field()? {
+                    "-" => break,
+                    field => optional_fields.push(field.to_string()),
+                }
+            }
+            let fs_type = next_field()?.to_string();
+            Ok(MountInfo {
+                id,
+                parent_id,
+                mount_point,
+                mount_options,
+                optional_fields,
+                fs_type,
+            })
+        })
+        .collect()
+}
+
+/// `read_mountinfo` reads and parses `/proc/self/mountinfo` with [parse_mountinfo].
+fn read_mountinfo() -> Result<Vec<MountInfo>> {
+    let content = fs::read_to_string("/proc/self/mountinfo")
+        .context("failed to read /proc/self/mountinfo")?;
+    parse_mountinfo(&content)
+}
+
+/// `unescape_mountinfo` decodes the octal sequences of a field in `/proc/self/mountinfo`.
+fn unescape_mountinfo(field: &str) -> Vec<u8> {
+    let bytes = field.as_bytes();
+    let mut unescaped = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let escape = bytes.get(index + 1..index + 4).filter(|digits| {
+            bytes[index] == b'\\' && digits.iter().all(|digit| (b'0'..=b'7').contains(digit))
+        });
+        match escape
+            .and_then(|digits| u8::from_str_radix(std::str::from_utf8(digits).ok()?, 8).ok())
+        {
+            Some(byte) => {
+                unescaped.push(byte);
+                index += 4;
+            }
+            None => {
+                unescaped.push(bytes[index]);
+                index += 1;
+            }
+        }
+    }
+    unescaped
+}
+
+/// `submount_points` returns the mount points strictly below `path`, where the submounts are
+/// ordered before their parent mounts. The mount at `path` itself is excluded,
+/// since it might be managed by the caller, such as the `overlay` rootfs prepared by `containerd`.
+pub fn submount_points(mount_info_list: &[MountInfo], path: &Path) -> Vec<PathBuf> {
+    let mut mount_point_list: Vec<PathBuf> = mount_info_list
+        .iter()
+        .map(|mount_info| &mount_info.mount_point)
+        .filter(|mount_point| mount_point.starts_with(path) && mount_point.as_path() != path)
+        .cloned()
+        .collect();
+    mount_point_list.sort_by_key(|mount_point| Reverse(mount_point.components().count()));
+    mount_point_list
+}
+
+/// `umount_recursive` detaches every mount below `path`, where the submounts are detached
+/// before their parent mounts, which are read from `/proc/self/mountinfo`.
+/// The mount at `path` itself is kept, and a mount that is already gone,
+/// such as the submount of a detached mount, is skipped.
+pub fn umount_recursive(path: &Path) -> Result<()> {
+    for mount_point in submount_points(&read_mountinfo()?, path) {
+        match mount::umount2(&mount_point, MntFlags::MNT_DETACH) {
+            Err(Errno::EINVAL | Errno::ENOENT) => (),
+            result => result.context(format!("failed to umount {}", mount_point.display()))?,
+        }
+    }
     Ok(())
 }
+
+/// `kernel_version` returns the major and minor version of the running kernel,
+/// which is parsed from `/proc/sys/kernel/osrelease`, such as `6.8.0-40-generic`.
+fn kernel_version() -> Result<(u32, u32)> {
+    let release = fs::read_to_string("/proc/sys/kernel/osrelease")
+        .context("failed to read the kernel version")?;
+    let mut version = release
+        .trim()
+        .split(|c: char| !c.is_ascii_digit())
+        .map(|number| number.parse::<u32>());
+    match (version.next(), version.next()) {
+        (Some(Ok(major)), Some(Ok(minor))) => Ok((major, minor)),
+        _ => bail!("failed to parse the kernel version {}", release.trim()),
+    }
+}
+
+/// `tmpcopyup_mount` mounts a `tmpfs` on `destination` that contains a copy of the original
+/// content of `destination`, which would otherwise be hidden by the `tmpfs`.
+/// The `tmpfs` is mounted on a unique temporary directory in `rootfs` and populated with
+/// the content, and then moved to `destination`. The temporary directory is created in `rootfs`
+/// instead of `/tmp`, which might be shared with other containers, such as in a PID namespace
+/// where the PID is always `1`, and it's removed once the `tmpfs` is moved.
+fn tmpcopyup_mount(
+    rootfs: &Path,
+    destination: &Path,
+    mount_flags: MsFlags,
+    mount_data: &OsStr,
+) -> Result<()> {
+    let staging = unistd::mkdtemp(&rootfs.join(".reno-tmpcopyup-XXXXXX")).context(format!(
+        "failed to create a temporary directory in {}",
+        rootfs.display()
+    ))?;
+
+    // The `tmpfs` is mounted as writable to be populated, and then remounted with `mount_flags`
+    mount::mount(
+        Some("tmpfs"),
+        &staging,
+        Some("tmpfs"),
+        mount_flags & !MsFlags::MS_RDONLY,
+        Some(mount_data),
+    )
+    .context(format!("failed to mount tmpfs on {}", staging.display()))?;
+
+    let result = copy_metadata(destination, &staging)
+        .and_then(|_| copy_directory(destination, &staging))
+        .and_then(|_| {
+            mount::mount(
+                Some(&staging),
+                destination,
+                None::<&str>,
+                MsFlags::MS_MOVE,
+                None::<&str>,
+            )
+            .context(format!(
+                "failed to move the tmpfs to {}",
+                destination.display()
+            ))
+        });
+    if result.is_err() {
+        let _ = mount::umount2(&staging, MntFlags::MNT_DETACH);
+    }
+    let _ = fs::remove_dir(&staging);
+    result?;
+
+    if mount_flags.contains(MsFlags::MS_RDONLY) {
+        mount::mount(
+            None::<&str>,
+            destination,
+            None::<&str>,
+            mount_flags | MsFlags::MS_REMOUNT,
+            Some(mount_data),
+        )
+        .context(format!("failed to remount {}", destination.display()))?;
+    }
+    Ok(())
+}
+
+/// `copy_directory` recursively copies the content of the directory `source` to `destination`,
+/// which preserves the ownership, permissions, symbolic links, and extended attributes.
+fn copy_directory(source: &Path, destination: &Path) -> Result<()> {
+    for entry in fs::read_dir(source).context(format!("failed to read {}", source.display()))? {
+        let entry = entry.context(format!("failed to read {}", source.display()))?;
+        let source_path = entry.path();
+        let destination_path = destination.join(entry.file_name());
+        let metadata = fs::symlink_metadata(&source_path)
+            .context(format!("failed to read {}", source_path.display()))?;
+
+        let file_type = metadata.file_type();
+        if file_type.is_dir() {
+            fs::create_dir(&destination_path)
+                .context(format!("failed to create {}", destination_path.display()))?;
+            copy_directory(&source_path, &destination_path)?;
+        } else if file_type.is_symlink() {
+            let target = fs::read_link(&source_path)
+                .context(format!("failed to read {}", source_path.display()))?;
+            symlink(target, &destination_path)
+                .context(format!("failed to create {}", destination_path.display()))?;
+        } else if file_type.is_file() {
+            fs::copy(&source_path, &destination_path)
+                .context(format!("failed to copy {}", source_path.display()))?;
+        } else {
+            stat::mknod(
+                &destination_path,
+                SFlag::from_bits_truncate(metadata.mode()),
+                Mode::from_bits_truncate(metadata.mode()),
+                metadata.rdev(),
+            )
+            .context(format!("failed to create {}", destination_path.display()))?;
+        }
+        copy_metadata(&source_path, &destination_path)?;
+    }
+    Ok(())
+}
+
+/// `copy_metadata` copies the ownership, permissions, and extended attributes of `source`
+/// to `destination` without following symbolic links.
+fn copy_metadata(source: &Path, destination: &Path) -> Result<()> {
+    let metadata =
+        fs::symlink_metadata(source).context(format!("failed to read {}", source.display()))?;
+    unistd::fchownat(
+        None,
+        destination,
+        Some(Uid::from_raw(metadata.uid())),
+        Some(Gid::from_raw(metadata.gid())),
+        AtFlags::AT_SYMLINK_NOFOLLOW,
+    )
+    .context(format!(
+        "failed to change the ownership of {}",
+        destination.display()
+    ))?;
+    if !metadata.file_type().is_symlink() {
+        fs::set_permissions(destination, metadata.permissions()).context(format!(
+            "failed to change the permissions of {}",
+            destination.display()
+        ))?;
+    }
+    copy_xattrs(source, destination)
+}
+
+/// `copy_xattrs` copies the extended attributes of `source` to `destination`
+/// without following symbolic links. Extended attributes that can't be set on `destination`,
+/// such as the ones that are not supported by `tmpfs`, are skipped.
+/// For more information, see the [xattr(7)](https://man7.org/linux/man-pages/man7/xattr.7.html)
+/// man page.
+fn copy_xattrs(source: &Path, destination: &Path) -> Result<()> {
+    let source_cstring = CString::new(source.as_os_str().as_bytes())?;
+    let destination_cstring = CString::new(destination.as_os_str().as_bytes())?;
+
+    let name_list = match read_xattr(|buffer, size| unsafe {
+        libc::llistxattr(source_cstring.as_ptr(), buffer, size)
+    }) {
+        Ok(name_list) => name_list,
+        Err(Errno::ENOTSUP) => return Ok(()),
+        Err(error) => {
+            return Err(error).context(format!(
+                "failed to list the extended attributes of {}",
+                source.display()
+            ))
+        }
+    };
+
+    for name in name_list
+        .split(|byte| *byte == 0)
+        .filter(|name| !name.is_empty())
+    {
+        let name = CString::new(name)?;
+        let value = read_xattr(|buffer, size| unsafe {
+            libc::lgetxattr(source_cstring.as_ptr(), name.as_ptr(), buffer.cast(), size)
+        })
+        .context(format!(
+            "failed to read the extended attribute {:?} of {}",
+            name,
+            source.display()
+        ))?;
+        let result = unsafe {
+            libc::lsetxattr(
+                destination_cstring.as_ptr(),
+                name.as_ptr(),
+                value.as_ptr().cast(),
+                value.len(),
+                0,
+            )
+        };
+        if let Err(error) = Errno::result(result) {
+            warn!(
+                "failed to copy the extended attribute {:?} to {}: {}",
+                name,
+                destination.display(),
+                error
+            );
+        }
+    }
+    Ok(())
+}
+
+/// `read_xattr` invokes `read` with a buffer that is large enough for the result,
+/// where `read` is a `listxattr` or `getxattr` call that returns the size of the result
+/// if the buffer size is `0`.
+fn read_xattr(read: impl Fn(*mut libc::c_char, usize) -> libc::ssize_t) -> nix::Result<Vec<u8>> {
+    loop {
+        let size = Errno::result(read(std::ptr::null_mut(), 0))? as usize;
+        let mut buffer = vec![0u8; size];
+        match Errno::result(read(buffer.as_mut_ptr().cast(), size)) {
+            Ok(size) => {
+                buffer.truncate(size as usize);
+                return Ok(buffer);
+            }
+            // The result grows between the two calls
+            Err(Errno::ERANGE) => continue,
+            Err(error) => return Err(error),
+        }
+    }
+}
+
+/// `create_mount_destination` creates the destination of a mount, which is a file
+/// if `file_bind_mount` is `true`, or a directory otherwise.
+fn create_mount_destination(destination: &Path, file_bind_mount: bool) -> Result<()> {
+    if file_bind_mount {
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent).context(format!("failed to create {}", parent.display()))?;
+        }
+        File::create(destination).context(format!("failed to create {}", destination.display()))?;
+    } else {
+        fs::create_dir_all(destination)
+            .context(format!("failed to create {}", destination.display()))?;
+    }
+    Ok(())
+}
+
+/// `mount_all` mounts `mounts` defined in the bundle configuration and the default file systems
+/// selected by [select_default_mounts]. The default file systems are mounted before `mounts`,
+/// except the ones nested in the destination of an explicit mount, such as `/dev/pts` if `/dev`
+/// is mounted explicitly, which are mounted after `mounts` to avoid being hidden by them.
+/// The order of `mounts` is determined by [sort_mounts], and relative paths in the options of
+/// `overlay` mounts are resolved against `bundle`. If `tmpfs_owner` is not `None`, which is the
+/// user of the container process in a new user namespace, the root of each `tmpfs` in `mounts`
+/// without `uid=` or `gid=` options is owned by the user.
+pub fn mount_all(
+    rootfs: &Path,
+    bundle: &Path,
+    mounts: &[Mount],
+    add_default_mounts: bool,
+    tmpfs_owner: Option<(Uid, Gid)>,
+) -> Result<()> {
+    let mounts = &sort_mounts(mounts);
+    let (default_mounts, nested_default_mounts) = select_default_mounts(mounts, add_default_mounts);
+
+    // The default file systems, such as the `tmpfs` on `/dev`, are always owned by root
+    let mount_list = default_mounts
+        .iter()
+        .map(|mount| (mount, false))
+        .chain(mounts.iter().map(|mount| (mount, true)))
+        .chain(nested_default_mounts.iter().map(|mount| (mount, false)));
+    for (mount, explicit) in mount_list {
+        let mount = &match mount.typ().as_deref() {
+            Some("devpts") => devpts_mount(mount)?,
+            Some("overlay") => overlay_mount(bundle, mount)?,
+            Some("tmpfs") => tmpfs_mount(mount)?,
+            _ => mount.clone(),
+        };
+        custom_mount(rootfs, mount)
+            .context(format!("failed to mount {}", mount.destination().display()))?;
+
+        if let (true, Some("tmpfs"), Some((uid, gid))) =
+            (explicit, mount.typ().as_deref(), tmpfs_owner)
+        {
+            let owner_specified = mount.options().as_ref().is_some_and(|options| {
+                options
+                    .iter()
+                    .any(|option| option.starts_with("uid=") || option.starts_with("gid="))
+            });
+            if !owner_specified {
+                let destination = secure_join(rootfs, mount.destination())?;
+                unistd::chown(&destination, Some(uid), Some(gid)).context(format!(
+                    "failed to change the ownership of {}",
+                    mount.destination().display()
+                ))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `select_default_mounts` returns the [default file systems](https://github.com/opencontainers/runtime-spec/blob/main/config-linux.md#default-filesystems)
+/// whose destinations are not covered by `mounts`, which are partitioned into the ones mounted
+/// before `mounts` and the ones nested in the destination of a mount in `mounts`.
+/// Every default file system is selected if `mounts` is empty, since a bundle configuration
+/// without mounts can't run most programs. Otherwise, the `tmpfs` on `/dev/shm` is always selected,
+/// since the programs that use POSIX shared memory expect it, while the others are only selected
+/// if `add_default_mounts` is `true`, so that the bundle configuration decides the rest of
+/// the file systems in the container, as `runc` does.
+fn select_default_mounts(mounts: &[Mount], add_default_mounts: bool) -> (Vec<Mount>, Vec<Mount>) {
+    let add_default_mounts = add_default_mounts || mounts.is_empty();
+    let (nested_default_mounts, default_mounts) = default_mounts()
+        .into_iter()
+        .filter(|default_mount| {
+            add_default_mounts || default_mount.destination() == Path::new("/dev/shm")
+        })
+        .filter(|default_mount| {
+            mounts
+                .iter()
+                .all(|mount| mount.destination() != default_mount.destination())
+        })
+        .partition(|default_mount| {
+            mounts
+                .iter()
+                .any(|mount| default_mount.destination().starts_with(mount.destination()))
+        });
+    (default_mounts, nested_default_mounts)
+}
+
+/// The destinations of `tmpfs` mounts that are shared by every user,
+/// whose root is writable by everyone with the sticky bit by default.
+const STICKY_TMPFS_LIST: [&str; 3] = ["/tmp", "/var/tmp", "/dev/shm"];
+
+/// `tmpfs_mount` returns the `tmpfs` [Mount] with the default `mode=` option, which is `1777`
+/// for the directories shared by every user, such as `/tmp`, or `755` otherwise.
+/// The `size=` and `nr_inodes=` options are validated, since the kernel rejects invalid values
+/// with `EINVAL` that doesn't explain which option is invalid.
+/// For more information, see the [tmpfs(5)](https://man7.org/linux/man-pages/man5/tmpfs.5.html)
+/// man page.
+fn tmpfs_mount(mount: &Mount) -> Result<Mount> {
+    let mut options = mount.options().clone().unwrap_or_default();
+    for option in &options {
+        let (key, value, percentage) = match option.split_once('=') {
+            Some(("size", value)) => ("size", value, true),
+            Some(("nr_inodes", value)) => ("nr_inodes", value, false),
+            _ => continue,
+        };
+        if !valid_tmpfs_size(value, percentage) {
+            bail!(
+                "the {} option {} of the tmpfs on {} is invalid, which should be a number with an optional k, m, or g suffix{}",
+                key,
+                value,
+                mount.destination().display(),
+                if percentage { ", or a percentage" } else { "" }
+            );
+        }
+    }
+
+    if !options.iter().any(|option| option.starts_with("mode=")) {
+        let sticky = STICKY_TMPFS_LIST
+            .iter()
+            .any(|destination| mount.destination() == Path::new(destination));
+        options.push(String::from(if sticky { "mode=1777" } else { "mode=755" }));
+    }
+
+    let mut mount = mount.clone();
+    mount.set_options(Some(options));
+    Ok(mount)
+}
+
+/// `valid_tmpfs_size` checks if `value` is a number with an optional `k`, `m`, `g`, `t`, `p`,
+/// or `e` suffix, or a percentage if `percentage` is `true`.
+fn valid_tmpfs_size(value: &str, percentage: bool) -> bool {
+    let number = match value.char_indices().last() {
+        Some((index, '%')) if percentage => &value[..index],
+        Some((index, suffix)) if "kKmMgGtTpPeE".contains(suffix) => &value[..index],
+        _ => value,
+    };
+    !number.is_empty() && number.chars().all(|c| c.is_ascii_digit())
+}
+
+/// `sort_mounts` sorts `mounts` by the depth of their destinations, so that a mount is mounted
+/// after the mounts of its ancestors, such as `/var/log` after `/var`, which would otherwise hide it.
+/// The order of mounts with the same depth is preserved. If several mounts have the same
+/// destination, only the last one is kept.
+pub fn sort_mounts(mounts: &[Mount]) -> Vec<Mount> {
+    let mut sorted_mounts: Vec<Mount> = mounts
+        .iter()
+        .enumerate()
+        .filter(|(index, mount)| {
+            let overridden = mounts[index + 1..]
+                .iter()
+                .any(|later_mount| later_mount.destination() == mount.destination());
+            if overridden {
+                warn!(
+                    "the mount on {} is overridden by a later mount with the same destination",
+                    mount.destination().display()
+                );
+            }
+            !overridden
+        })
+        .map(|(_, mount)| mount.clone())
+        .collect();
+
+    sorted_mounts.sort_by_key(|mount| {
+        mount
+            .destination()
+            .components()
+            .filter(|component| matches!(component, Component::Normal(_)))
+            .count()
+    });
+    sorted_mounts
+}
+
+/// `default_mounts` returns the [default file systems](https://github.com/opencontainers/runtime-spec/blob/main/config-linux.md#default-filesystems),
+/// which are `proc` on `/proc`, `tmpfs` on `/dev`, `devpts` on `/dev/pts`, `tmpfs` on `/dev/shm`,
+/// `mqueue` on `/dev/mqueue`, and `sysfs` on `/sys`. The `cgroup` mount is excluded,
+/// since the cgroup hierarchy of the container is only mounted if the bundle requests it.
+pub fn default_mounts() -> Vec<Mount> {
+    runtime::get_default_mounts()
+        .into_iter()
+        .filter(|mount| mount.destination() != Path::new("/sys/fs/cgroup"))
+        .collect()
+}
+
+/// `overlay_mount` returns the `overlay` [Mount] whose `lowerdir`, `upperdir`, and `workdir` options
+/// are resolved against `bundle` to absolute paths, since relative paths would be resolved against
+/// the working directory of the container process. Every directory in `lowerdir` must exist,
+/// while `upperdir` and `workdir` are created if they don't exist.
+/// For more information, see the [overlayfs](https://docs.kernel.org/filesystems/overlayfs.html)
+/// documentation.
+fn overlay_mount(bundle: &Path, mount: &Mount) -> Result<Mount> {
+    let resolve = |path: &str| -> Result<String> {
+        let path = bundle.join(path);
+        let path = fs::canonicalize(&path).context(format!(
+            "failed to resolve the overlay directory {}",
+            path.display()
+        ))?;
+        Ok(path.to_string_lossy().into_owned())
+    };
+
+    let mut overlay_options = Vec::new();
+    for option in mount.options().clone().unwrap_or_default() {
+        let option = match option.split_once('=') {
+            Some(("lowerdir", lowerdir_list)) => {
+                let lowerdir_list = lowerdir_list
+                    .split(':')
+                    .map(resolve)
+                    .collect::<Result<Vec<String>>>()?;
+                format!("lowerdir={}", lowerdir_list.join(":"))
+            }
+            Some((key @ ("upperdir" | "workdir"), directory)) => {
+                let path = bundle.join(directory);
+                fs::create_dir_all(&path).context(format!(
+                    "failed to create the overlay directory {}",
+                    path.display()
+                ))?;
+                format!("{}={}", key, resolve(directory)?)
+            }
+            _ => option,
+        };
+        overlay_options.push(option);
+    }
+
+    let mut mount = mount.clone();
+    mount.set_options(Some(overlay_options));
+    Ok(mount)
+}
+
+/// `devpts_mount` returns the `devpts` [Mount] with the options that create a private instance
+/// of `devpts` for the container, whose `/dev/pts/ptmx` is accessible through the `/dev/ptmx`
+/// symbolic link. The `gid=<gid>` option is dropped if the group isn't mapped
+/// in the user namespace of the container, since the kernel rejects the mount.
+/// For more information, see the [pts(4)](https://man7.org/linux/man-pages/man4/pts.4.html)
+/// man page.
+fn devpts_mount(mount: &Mount) -> Result<Mount> {
+    let mut options = mount.options().clone().unwrap_or_default();
+    if !options.iter().any(|option| option == "newinstance") {
+        options.push(String::from("newinstance"));
+    }
+    if !options.iter().any(|option| option.starts_with("ptmxmode=")) {
+        options.push(String::from("ptmxmode=0666"));
+    }
+
+    let mut devpts_options = Vec::new();
+    for option in options {
+        if let Some(gid) = option.strip_prefix("gid=") {
+            let gid: u32 = gid
+                .parse()
+                .context(format!("the devpts option {} is invalid", option))?;
+            if !gid_mapped(gid)? {
+                warn!(
+                    "dropping the devpts option {}, since the group {} isn't mapped in the user namespace",
+                    option, gid
+                );
+                continue;
+            }
+        }
+        devpts_options.push(option);
+    }
+
+    let mut mount = mount.clone();
+    mount.set_options(Some(devpts_options));
+    Ok(mount)
+}
+
+/// `gid_mapped` checks if `gid` is mapped in the user namespace of the current process.
+/// For more information, see the [user_namespaces(7)](https://man7.org/linux/man-pages/man7/user_namespaces.7.html)
+/// man page.
+fn gid_mapped(gid: u32) -> Result<bool> {
+    let gid_map =
+        fs::read_to_string("/proc/self/gid_map").context("failed to read /proc/self/gid_map")?;
+    Ok(gid_map.lines().any(|line| {
+        match line
+            .split_whitespace()
+            .map(|field| field.parse::<u64>())
+            .collect::<Result<Vec<u64>, _>>()
+            .as_deref()
+        {
+            Ok([inside, _, count]) => (*inside..inside + count).contains(&u64::from(gid)),
+            _ => false,
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use nix::sys::statfs;
+
+    use crate::test_util;
+
+    /// `test_mount` returns a [Mount] of the type `typ` on `destination` with `options`.
+    fn test_mount(typ: &str, destination: &str, options: &[&str]) -> Mount {
+        let mut mount = Mount::default();
+        mount.set_typ(Some(String::from(typ)));
+        mount.set_source(Some(PathBuf::from(typ)));
+        mount.set_destination(PathBuf::from(destination));
+        mount.set_options(Some(
+            options.iter().map(|option| option.to_string()).collect(),
+        ));
+        mount
+    }
+
+    #[test]
+    fn mount_to_msflags_converts_options() {
+        let mount = test_mount(
+            "tmpfs",
+            "/tmp",
+            &[
+                "nosuid",
+                "nodev",
+                "nosymfollow",
+                "lazytime",
+                "silent",
+                "mode=1777",
+                "size=64m",
+            ],
+        );
+        let (mount_flags, mount_data) = mount_to_msflags(&mount);
+        assert_eq!(
+            mount_flags,
+            MsFlags::MS_NOSUID
+                | MsFlags::MS_NODEV
+                | MS_NOSYMFOLLOW
+                | MsFlags::MS_LAZYTIME
+                | MsFlags::MS_SILENT
+        );
+        assert_eq!(mount_data, "mode=1777,size=64m");
+    }
+
+    #[test]
+    fn mount_to_msflags_clears_flags() {
+        let mount = test_mount(
+            "bind",
+            "/data",
+            &[
+                "rbind",
+                "ro",
+                "rw",
+                "nosymfollow",
+                "symfollow",
+                "loud",
+                "rro",
+            ],
+        );
+        let (mount_flags, mount_data) = mount_to_msflags(&mount);
+        assert_eq!(mount_flags, MsFlags::MS_BIND | MsFlags::MS_REC);
+        assert!(mount_data.is_empty());
+    }
+
+    #[test]
+    fn mount_option_list_is_unique() {
+        for (index, (name, _, _)) in MOUNT_OPTION_LIST.iter().enumerate() {
+            assert!(
+                MOUNT_OPTION_LIST[index + 1..]
+                    .iter()
+                    .all(|(other_name, _, _)| other_name != name),
+                "the mount option {} is duplicated",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn optional_flags_keep_security_flags() {
+        for flag in [
+            MsFlags::MS_RDONLY,
+            MsFlags::MS_NOSUID,
+            MsFlags::MS_NODEV,
+            MsFlags::MS_NOEXEC,
+            MsFlags::MS_BIND,
+            MsFlags::MS_REC,
+        ] {
+            assert!(!OPTIONAL_FLAGS.intersects(flag), "{:?} is optional", flag);
+        }
+
+        let (mount_flags, _) = mount_to_msflags(&test_mount(
+            "tmpfs",
+            "/tmp",
+            &["ro", "nosuid", "noatime", "lazytime", "sync"],
+        ));
+        assert_eq!(
+            mount_flags - (mount_flags & OPTIONAL_FLAGS),
+            MsFlags::MS_RDONLY | MsFlags::MS_NOSUID
+        );
+    }
+
+    /// `destinations` returns the destinations of `mounts`.
+    fn destinations(mounts: &[Mount]) -> Vec<&Path> {
+        mounts
+            .iter()
+            .map(|mount| mount.destination().as_path())
+            .collect()
+    }
+
+    #[test]
+    fn select_default_mounts_always_selects_dev_shm() {
+        let (default_mounts, nested_default_mounts) =
+            select_default_mounts(&[test_mount("tmpfs", "/run", &[])], false);
+        assert_eq!(destinations(&default_mounts), [Path::new("/dev/shm")]);
+        assert!(nested_default_mounts.is_empty());
+        assert_eq!(
+            default_mounts[0].options().as_deref(),
+            Some(&["nosuid", "noexec", "nodev", "mode=1777", "size=65536k"].map(String::from)[..])
+        );
+
+        let (default_mounts, nested_default_mounts) = select_default_mounts(
+            &[
+                test_mount("tmpfs", "/run", &[]),
+                test_mount("tmpfs", "/dev/shm", &[]),
+            ],
+            false,
+        );
+        assert!(default_mounts.is_empty());
+        assert!(nested_default_mounts.is_empty());
+    }
+
+    #[test]
+    fn select_default_mounts_skips_covered_destinations() {
+        let mounts = [
+            test_mount("proc", "/proc", &[]),
+            test_mount("tmpfs", "/dev", &[]),
+        ];
+        let (default_mounts, nested_default_mounts) = select_default_mounts(&mounts, true);
+        assert_eq!(destinations(&default_mounts), [Path::new("/sys")]);
+        assert_eq!(
+            destinations(&nested_default_mounts),
+            ["/dev/pts", "/dev/shm", "/dev/mqueue"].map(Path::new)
+        );
+
+        let (default_mounts, nested_default_mounts) = select_default_mounts(&mounts, false);
+        assert!(default_mounts.is_empty());
+        assert_eq!(
+            destinations(&nested_default_mounts),
+            [Path::new("/dev/shm")]
+        );
+    }
+
+    #[test]
+    fn select_default_mounts_without_bundle_mounts() {
+        for add_default_mounts in [false, true] {
+            let (default_mounts, nested_default_mounts) =
+                select_default_mounts(&[], add_default_mounts);
+            assert_eq!(
+                destinations(&default_mounts),
+                [
+                    "/proc",
+                    "/dev",
+                    "/dev/pts",
+                    "/dev/shm",
+                    "/dev/mqueue",
+                    "/sys"
+                ]
+                .map(Path::new)
+            );
+            assert!(nested_default_mounts.is_empty());
+        }
+    }
+
+    #[test]
+    fn mount_all_mounts_default_file_systems() {
+        if !test_util::is_root() {
+            return;
+        }
+
+        let run = [test_mount("tmpfs", "/run", &[])];
+        for (index, (mounts, add_default_mounts)) in
+            [(&run[..], false), (&run[..], true), (&[][..], false)]
+                .into_iter()
+                .enumerate()
+        {
+            let rootfs = test_util::test_directory("mount", &format!("default-{}", index));
+            test_util::run_in_mount_namespace(|| {
+                mount_all(&rootfs, &rootfs, mounts, add_default_mounts, None).unwrap();
+
+                let mount_info_list = read_mountinfo().unwrap();
+                let fs_type = |destination: &str| {
+                    mount_info_list
+                        .iter()
+                        .find(|mount_info| mount_info.mount_point == rootfs.join(destination))
+                        .map(|mount_info| mount_info.fs_type.clone())
+                };
+                if !mounts.is_empty() {
+                    assert_eq!(fs_type("run").as_deref(), Some("tmpfs"));
+                }
+                assert_eq!(fs_type("dev/shm").as_deref(), Some("tmpfs"));
+                if add_default_mounts || mounts.is_empty() {
+                    assert_eq!(fs_type("proc").as_deref(), Some("proc"));
+                    assert_eq!(fs_type("dev").as_deref(), Some("tmpfs"));
+                    assert_eq!(fs_type("dev/pts").as_deref(), Some("devpts"));
+                    assert_eq!(fs_type("dev/mqueue").as_deref(), Some("mqueue"));
+                    assert_eq!(fs_type("sys").as_deref(), Some("sysfs"));
+                } else {
+                    assert_eq!(fs_type("proc"), None);
+                    assert_eq!(fs_type("dev"), None);
+                }
+            });
+        }
+    }
+
+    #[test]
+    fn tmpcopyup_mount_preserves_files() {
+        if !test_util::is_root() {
+            return;
+        }
+
+        let rootfs = test_util::test_directory("mount", "tmpcopyup");
+        fs::create_dir_all(rootfs.join("data/sub")).unwrap();
+        fs::write(rootfs.join("data/file"), "file").unwrap();
+        fs::write(rootfs.join("data/sub/nested"), "nested").unwrap();
+        test_util::run_in_mount_namespace(|| {
+            custom_mount(&rootfs, &test_mount("tmpfs", "/data", &["tmpcopyup"])).unwrap();
+
+            let data = rootfs.join("data");
+            let statfs = statfs::statfs(&data).unwrap();
+            assert_eq!(statfs.filesystem_type(), statfs::TMPFS_MAGIC);
+            assert_eq!(fs::read_to_string(data.join("file")).unwrap(), "file");
+            assert_eq!(
+                fs::read_to_string(data.join("sub/nested")).unwrap(),
+                "nested"
+            );
+            fs::write(data.join("new"), "new").unwrap();
+
+            // The temporary directory is removed once the `tmpfs` is moved
+            let entry_list: Vec<_> = fs::read_dir(&rootfs)
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name())
+                .collect();
+            assert_eq!(entry_list, ["data"]);
+        });
+        assert!(!rootfs.join("data/new").exists());
+    }
+
+    /// `test_bind_mount` returns a bind [Mount] of `source` on `destination` with `options`.
+    fn test_bind_mount(source: &Path, destination: &str, options: &[&str]) -> Mount {
+        let mut mount = test_mount("bind", destination, options);
+        mount.set_source(Some(source.to_path_buf()));
+        mount
+    }
+
+    #[test]
+    fn custom_mount_read_only_bind_rejects_writes() {
+        if !test_util::is_root() {
+            return;
+        }
+
+        let source = test_util::test_directory("mount", "ro-bind-source");
+        fs::write(source.join("file"), "file").unwrap();
+        let rootfs = test_util::test_directory("mount", "ro-bind");
+        test_util::run_in_mount_namespace(|| {
+            custom_mount(&rootfs, &test_bind_mount(&source, "/data", &["ro", "bind"])).unwrap();
+
+            let data = rootfs.join("data");
+            assert_eq!(fs::read_to_string(data.join("file")).unwrap(), "file");
+            let error = fs::write(data.join("new"), "new").unwrap_err();
+            assert_eq!(error.raw_os_error(), Some(Errno::EROFS as i32));
+            // The source stays writable, since only the bind mount is read-only
+            fs::write(source.join("new"), "new").unwrap();
+        });
+    }
+
+    #[test]
+    fn with_enosys_fallback_only_on_enosys() {
+        let mut fallback_count = 0;
+        let result = with_enosys_fallback(
+            || Err(Errno::ENOSYS),
+            || {
+                fallback_count += 1;
+                Ok(())
+            },
+        );
+        assert_eq!(result, Ok(()));
+        assert_eq!(fallback_count, 1);
+
+        for mount_api_result in [Ok(()), Err(Errno::EPERM)] {
+            let result = with_enosys_fallback(
+                || mount_api_result,
+                || {
+                    fallback_count += 1;
+                    Ok(())
+                },
+            );
+            assert_eq!(result, mount_api_result);
+        }
+        assert_eq!(fallback_count, 1);
+    }
+
+    #[test]
+    fn bind_mount_with_fallback() {
+        if !test_util::is_root() {
+            return;
+        }
+
+        let source = test_util::test_directory("mount", "fallback-source");
+        fs::write(source.join("file"), "file").unwrap();
+        let rootfs = test_util::test_directory("mount", "fallback");
+        fs::create_dir_all(rootfs.join("api")).unwrap();
+        fs::create_dir_all(rootfs.join("legacy")).unwrap();
+        test_util::run_in_mount_namespace(|| {
+            let mount_flags = MsFlags::MS_BIND | MsFlags::MS_REC;
+            bind_mount(
+                &rootfs,
+                Path::new("/api"),
+                &rootfs.join("api"),
+                &source,
+                mount_flags,
+            )
+            .unwrap();
+            assert_eq!(fs::read_to_string(rootfs.join("api/file")).unwrap(), "file");
+
+            // The kernel without the new mount API is emulated by failing it with `ENOSYS`
+            with_enosys_fallback(
+                || Err(Errno::ENOSYS),
+                || legacy_bind_mount(&rootfs.join("legacy"), &source, mount_flags),
+            )
+            .unwrap();
+            assert_eq!(
+                fs::read_to_string(rootfs.join("legacy/file")).unwrap(),
+                "file"
+            );
+        });
+    }
+
+    #[test]
+    fn custom_mount_bind_creates_destinations() {
+        if !test_util::is_root() {
+            return;
+        }
+
+        let source = test_util::test_directory("mount", "bind-source");
+        fs::write(source.join("file"), "file").unwrap();
+        let rootfs = test_util::test_directory("mount", "bind");
+        fs::create_dir_all(rootfs.join("etc")).unwrap();
+        symlink("/run/resolv.conf", rootfs.join("etc/resolv.conf")).unwrap();
+        test_util::run_in_mount_namespace(|| {
+            // The file destination and its parent are created as the file and the directory
+            let file = source.join("file");
+            custom_mount(&rootfs, &test_bind_mount(&file, "/etc/app/file", &["bind"])).unwrap();
+            let destination = rootfs.join("etc/app/file");
+            assert!(destination.is_file());
+            assert_eq!(fs::read_to_string(destination).unwrap(), "file");
+
+            // The dangling symbolic link is resolved inside `rootfs`, where its target is created
+            custom_mount(
+                &rootfs,
+                &test_bind_mount(&file, "/etc/resolv.conf", &["bind"]),
+            )
+            .unwrap();
+            assert!(fs::symlink_metadata(rootfs.join("etc/resolv.conf"))
+                .unwrap()
+                .is_symlink());
+            assert_eq!(
+                fs::read_to_string(rootfs.join("run/resolv.conf")).unwrap(),
+                "file"
+            );
+
+            custom_mount(&rootfs, &test_bind_mount(&source, "/data/dir", &["rbind"])).unwrap();
+            assert!(rootfs.join("data/dir").is_dir());
+            assert_eq!(
+                fs::read_to_string(rootfs.join("data/dir/file")).unwrap(),
+                "file"
+            );
+        });
+    }
+
+    #[test]
+    fn custom_mount_bind_rejects_wrong_destination_type() {
+        let source = test_util::test_directory("mount", "bind-type-source");
+        fs::write(source.join("file"), "file").unwrap();
+        let rootfs = test_util::test_directory("mount", "bind-type");
+        fs::create_dir_all(rootfs.join("dir")).unwrap();
+        fs::write(rootfs.join("file"), "").unwrap();
+
+        let error = custom_mount(
+            &rootfs,
+            &test_bind_mount(&source.join("file"), "/dir", &["bind"]),
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("is a directory"));
+        let error =
+            custom_mount(&rootfs, &test_bind_mount(&source, "/file", &["bind"])).unwrap_err();
+        assert!(error.to_string().contains("is not a directory"));
+    }
+
+    #[test]
+    fn custom_mount_creates_nested_destinations() {
+        if !test_util::is_root() {
+            return;
+        }
+
+        let rootfs = test_util::test_directory("mount", "nested");
+        test_util::run_in_mount_namespace(|| {
+            // Neither destination exists, where `/data/sub` is created in the `tmpfs` on `/data`
+            let mounts = [
+                test_mount("tmpfs", "/data/sub", &["size=1m"]),
+                test_mount("tmpfs", "/data", &["size=1m"]),
+            ];
+            for mount in sort_mounts(&mounts) {
+                custom_mount(&rootfs, &mount).unwrap();
+            }
+
+            let mount_info_list = read_mountinfo().unwrap();
+            let data = mount_info_list
+                .iter()
+                .find(|mount_info| mount_info.mount_point == rootfs.join("data"))
+                .unwrap();
+            let sub = mount_info_list
+                .iter()
+                .find(|mount_info| mount_info.mount_point == rootfs.join("data/sub"))
+                .unwrap();
+            assert_eq!(sub.parent_id, data.id);
+            assert_eq!(sub.fs_type, "tmpfs");
+        });
+        assert!(rootfs.join("data").is_dir());
+        assert!(!rootfs.join("data/sub").exists());
+    }
+
+    #[test]
+    fn custom_mount_rro_makes_submounts_read_only() {
+        if !test_util::is_root() {
+            return;
+        }
+
+        let source = test_util::test_directory("mount", "rro-source");
+        fs::create_dir_all(source.join("sub")).unwrap();
+        let rootfs = test_util::test_directory("mount", "rro");
+        test_util::run_in_mount_namespace(|| {
+            mount::mount(
+                Some("tmpfs"),
+                &source.join("sub"),
+                Some("tmpfs"),
+                MsFlags::empty(),
+                None::<&str>,
+            )
+            .unwrap();
+
+            custom_mount(
+                &rootfs,
+                &test_bind_mount(&source, "/data", &["rbind", "rro"]),
+            )
+            .unwrap();
+            for path in ["data/new", "data/sub/new"] {
+                let error = fs::write(rootfs.join(path), "new").unwrap_err();
+                assert_eq!(error.raw_os_error(), Some(Errno::EROFS as i32), "{}", path);
+            }
+            fs::write(source.join("sub/new"), "new").unwrap();
+            assert!(rootfs.join("data/sub/new").exists());
+        });
+    }
+
+    #[test]
+    fn custom_mount_remount_bind_merges_flags() {
+        if !test_util::is_root() {
+            return;
+        }
+
+        let source = test_util::test_directory("mount", "remount-source");
+        let rootfs = test_util::test_directory("mount", "remount");
+        test_util::run_in_mount_namespace(|| {
+            mount::mount(
+                Some("tmpfs"),
+                &*source,
+                Some("tmpfs"),
+                MsFlags::MS_NODEV | MsFlags::MS_NOSUID,
+                None::<&str>,
+            )
+            .unwrap();
+
+            custom_mount(
+                &rootfs,
+                &test_bind_mount(&source, "/data", &["bind", "ro", "noexec"]),
+            )
+            .unwrap();
+            let mount_flags = current_mount_flags(&rootfs.join("data")).unwrap();
+            assert!(mount_flags.contains(
+                MsFlags::MS_RDONLY | MsFlags::MS_NOEXEC | MsFlags::MS_NODEV | MsFlags::MS_NOSUID
+            ));
+            let error = fs::write(rootfs.join("data/new"), "new").unwrap_err();
+            assert_eq!(error.raw_os_error(), Some(Errno::EROFS as i32));
+        });
+    }
+
+    #[test]
+    fn switch_rootfs_falls_back_to_chroot() {
+        if !test_util::is_root() {
+            return;
+        }
+
+        for readonly in [false, true] {
+            let root = test_util::test_directory("mount", &format!("chroot-{}", readonly));
+            fs::create_dir_all(root.join("rootfs")).unwrap();
+            fs::write(root.join("rootfs/marker"), "marker").unwrap();
+            test_util::run_in_mount_namespace(|| {
+                let rootfs = root.join("rootfs");
+                mount::mount(
+                    Some(&rootfs),
+                    &rootfs,
+                    None::<&str>,
+                    MsFlags::MS_BIND,
+                    None::<&str>,
+                )
+                .unwrap();
+
+                // `pivot_root` fails with `EINVAL` if the current root is not a mount point,
+                // such as after `chroot` into a directory
+                unistd::chroot(&*root).unwrap();
+                unistd::chdir("/").unwrap();
+                switch_rootfs(Path::new("/rootfs"), readonly, true).unwrap();
+
+                assert_eq!(fs::read_to_string("/marker").unwrap(), "marker");
+                assert!(!Path::new("/rootfs").exists());
+                match fs::write("/new", "new") {
+                    Err(error) if readonly => {
+                        assert_eq!(error.raw_os_error(), Some(Errno::EROFS as i32))
+                    }
+                    result => assert!(result.is_ok() && !readonly),
+                }
+            });
+        }
+    }
+
+    #[test]
+    fn sort_mounts_by_depth() {
+        let mounts = [
+            test_mount("tmpfs", "/var/log", &[]),
+            test_mount("proc", "/proc", &[]),
+            test_mount("tmpfs", "/var", &[]),
+            test_mount("devpts", "/dev/pts", &[]),
+            test_mount("tmpfs", "/dev", &[]),
+        ];
+        let destination_list: Vec<PathBuf> = sort_mounts(&mounts)
+            .iter()
+            .map(|mount| mount.destination().clone())
+            .collect();
+        assert_eq!(
+            destination_list,
+            ["/proc", "/var", "/dev", "/var/log", "/dev/pts"]
+                .iter()
+                .map(PathBuf::from)
+                .collect::<Vec<PathBuf>>()
+        );
+    }
+
+    #[test]
+    fn sort_mounts_keep_last_duplicate() {
+        let mounts = [
+            test_mount("tmpfs", "/tmp", &["size=1m"]),
+            test_mount("tmpfs", "/run", &[]),
+            test_mount("tmpfs", "/tmp", &["size=2m"]),
+        ];
+        let sorted_mounts = sort_mounts(&mounts);
+        assert_eq!(sorted_mounts.len(), 2);
+        assert_eq!(sorted_mounts[0].destination(), Path::new("/run"));
+        assert_eq!(
+            sorted_mounts[1].options().as_deref(),
+            Some(&[String::from("size=2m")][..])
+        );
+    }
+
+    #[test]
+    fn overlay_mount_resolves_directories() {
+        let bundle = test_util::test_directory("mount", "overlay");
+        fs::create_dir_all(bundle.join("lower1")).unwrap();
+        fs::create_dir_all(bundle.join("lower2")).unwrap();
+        let mount = test_mount(
+            "overlay",
+            "/",
+            &[
+                "lowerdir=lower1:lower2",
+                "upperdir=upper",
+                "workdir=work",
+                "index=off",
+            ],
+        );
+
+        let bundle_path = fs::canonicalize(&bundle).unwrap();
+        let options = overlay_mount(&bundle, &mount).unwrap().options().clone();
+        assert_eq!(
+            options.unwrap(),
+            vec![
+                format!(
+                    "lowerdir={}:{}",
+                    bundle_path.join("lower1").display(),
+                    bundle_path.join("lower2").display()
+                ),
+                format!("upperdir={}", bundle_path.join("upper").display()),
+                format!("workdir={}", bundle_path.join("work").display()),
+                String::from("index=off"),
+            ]
+        );
+        assert!(bundle.join("upper").is_dir());
+        assert!(bundle.join("work").is_dir());
+    }
+
+    #[test]
+    fn overlay_mount_requires_lowerdir() {
+        let bundle = test_util::test_directory("mount", "overlay-missing");
+        let mount = test_mount("overlay", "/", &["lowerdir=missing"]);
+        assert!(overlay_mount(&bundle, &mount).is_err());
+    }
+
+    #[test]
+    fn tmpfs_mount_default_mode() {
+        let options = |mount: Mount| tmpfs_mount(&mount).unwrap().options().clone().unwrap();
+        assert_eq!(
+            options(test_mount("tmpfs", "/tmp", &["nosuid"])),
+            vec![String::from("nosuid"), String::from("mode=1777")]
+        );
+        assert_eq!(
+            options(test_mount("tmpfs", "/run", &[])),
+            vec![String::from("mode=755")]
+        );
+        assert_eq!(
+            options(test_mount("tmpfs", "/dev/shm", &["mode=700"])),
+            vec![String::from("mode=700")]
+        );
+    }
+
+    #[test]
+    fn tmpfs_mount_rejects_invalid_size() {
+        assert!(tmpfs_mount(&test_mount("tmpfs", "/tmp", &["size=64m", "nr_inodes=1k"])).is_ok());
+        assert!(tmpfs_mount(&test_mount("tmpfs", "/tmp", &["size=64mb"])).is_err());
+        assert!(tmpfs_mount(&test_mount("tmpfs", "/tmp", &["nr_inodes=50%"])).is_err());
+    }
+
+    #[test]
+    fn valid_tmpfs_size_suffix() {
+        for value in ["65536", "64k", "64M", "1g", "2T", "1p", "1e", "50%"] {
+            assert!(valid_tmpfs_size(value, true), "{} is invalid", value);
+        }
+        for value in ["", "m", "%", "-1", "1.5g", "64mb", "50%%"] {
+            assert!(!valid_tmpfs_size(value, true), "{} is valid", value);
+        }
+        assert!(!valid_tmpfs_size("50%", false));
+    }
+
+    #[test]
+    fn secure_join_resolves_symlinks_inside_rootfs() {
+        let rootfs = test_util::test_directory("mount", "secure-join");
+        fs::create_dir_all(rootfs.join("var/lib")).unwrap();
+        symlink("/var/lib", rootfs.join("absolute")).unwrap();
+        symlink("../../../../etc", rootfs.join("var/lib/relative")).unwrap();
+        symlink("/", rootfs.join("root")).unwrap();
+
+        assert_eq!(
+            secure_join(&rootfs, Path::new("/absolute/data")).unwrap(),
+            rootfs.join("var/lib/data")
+        );
+        assert_eq!(
+            secure_join(&rootfs, Path::new("/var/lib/relative/passwd")).unwrap(),
+            rootfs.join("etc/passwd")
+        );
+        assert_eq!(
+            secure_join(&rootfs, Path::new("/root/../proc")).unwrap(),
+            rootfs.join("proc")
+        );
+        assert_eq!(
+            secure_join(&rootfs, Path::new("/missing/./path")).unwrap(),
+            rootfs.join("missing/path")
+        );
+    }
+
+    #[test]
+    fn secure_join_rejects_escape() {
+        let rootfs = test_util::test_directory("mount", "secure-join-escape");
+        assert!(secure_join(&rootfs, Path::new("/../etc")).is_err());
+        assert!(secure_join(&rootfs, Path::new("/dev/../../etc")).is_err());
+        assert_eq!(
+            secure_join(&rootfs, Path::new("/dev/../etc")).unwrap(),
+            rootfs.join("etc")
+        );
+
+        symlink("loop", rootfs.join("loop")).unwrap();
+        assert!(secure_join(&rootfs, Path::new("/loop")).is_err());
+    }
+
+    const MOUNTINFO_CONTAINERD: &str = include_str!("testdata/mountinfo-containerd");
+    const MOUNTINFO_STACKED: &str = include_str!("testdata/mountinfo-stacked");
+
+    #[test]
+    fn parse_mountinfo_fields() {
+        let mount_info_list = parse_mountinfo(MOUNTINFO_CONTAINERD).unwrap();
+        assert_eq!(mount_info_list.len(), 7);
+        assert_eq!(
+            mount_info_list[1],
+            MountInfo {
+                id: 30,
+                parent_id: 22,
+                mount_point: PathBuf::from("/run/containerd/rootfs"),
+                mount_options: vec![String::from("rw"), String::from("relatime")],
+                optional_fields: vec![String::from("shared:12"), String::from("master:3")],
+                fs_type: String::from("overlay"),
+            }
+        );
+        assert!(mount_info_list[2].optional_fields.is_empty());
+        assert_eq!(mount_info_list[2].fs_type, "proc");
+    }
+
+    #[test]
+    fn parse_mountinfo_escape() {
+        let mount_info_list = parse_mountinfo(MOUNTINFO_CONTAINERD).unwrap();
+        assert_eq!(
+            mount_info_list[6].mount_point,
+            Path::new("/run/containerd/rootfs/mnt/with space")
+        );
+        assert_eq!(unescape_mountinfo("a\\134b\\011"), b"a\\b\t");
+        assert_eq!(unescape_mountinfo("a\\9"), b"a\\9");
+    }
+
+    #[test]
+    fn parse_mountinfo_stacked_mounts() {
+        let mount_info_list = parse_mountinfo(MOUNTINFO_STACKED).unwrap();
+        assert_eq!(mount_info_list.len(), 10);
+        let dev_pts: Vec<(u32, u32)> = mount_info_list
+            .iter()
+            .filter(|mount_info| mount_info.mount_point == Path::new("/dev/pts"))
+            .map(|mount_info| (mount_info.id, mount_info.parent_id))
+            .collect();
+        assert_eq!(dev_pts, [(27, 25), (30, 27)]);
+        assert_eq!(
+            mount_info_list[6].mount_options,
+            ["ro", "nosuid", "nodev", "relatime"]
+        );
+        assert!(mount_info_list
+            .iter()
+            .all(|mount_info| mount_info.optional_fields.is_empty()));
+    }
+
+    #[test]
+    fn parse_mountinfo_invalid() {
+        assert!(parse_mountinfo("22 1 0:21 / / rw shared:1").is_err());
+        assert!(parse_mountinfo("x 1 0:21 / / rw - ext4 /dev/sda1 rw").is_err());
+    }
+
+    #[test]
+    fn submount_points_exclude_path() {
+        let mount_info_list = parse_mountinfo(MOUNTINFO_CONTAINERD).unwrap();
+        let mount_point_list =
+            submount_points(&mount_info_list, Path::new("/run/containerd/rootfs"));
+        assert_eq!(mount_point_list.len(), 4);
+        assert!(!mount_point_list.contains(&PathBuf::from("/run/containerd/rootfs")));
+        assert!(!mount_point_list.contains(&PathBuf::from("/run/containerd/rootfs-other")));
+        let dev_pts = mount_point_list
+            .iter()
+            .position(|mount_point| mount_point.ends_with("dev/pts"));
+        let dev = mount_point_list
+            .iter()
+            .position(|mount_point| mount_point.ends_with("dev"));
+        assert!(dev_pts < dev);
+    }
+}
@@ -0,0 +1,248 @@
+use std::{
+    ffi::CString,
+    mem,
+    os::{
+        fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd},
+        unix::ffi::OsStrExt,
+    },
+    path::Path,
+};
+
+use nix::errno::Errno;
+
+const OPEN_TREE_CLONE: libc::c_uint = 1;
+const MOVE_MOUNT_F_EMPTY_PATH: libc::c_uint = 0x00000004;
+const MOVE_MOUNT_T_EMPTY_PATH: libc::c_uint = 0x00000040;
+const MOUNT_ATTR_RDONLY: u64 = 0x00000001;
+
+/// `MountAttr` is the `mount_attr` structure accepted by `mount_setattr`.
+#[repr(C)]
+struct MountAttr {
+    attr_set: u64,
+    attr_clr: u64,
+    propagation: u64,
+    userns_fd: u64,
+}
+
+/// `bind_mount_tree` bind mounts `source` to `destination` in `rootfs` with the new mount API,
+/// which resolves `destination` with `openat2` as if `rootfs` were the root directory,
+/// clones the mount tree of `source` with `open_tree`, and attaches the clone to the resolved
+/// `destination` with `move_mount`. Since `destination` is resolved by the kernel and referred
+/// to by a file descriptor, a symbolic link swapped into `rootfs` between the resolution and
+/// the mount can't redirect the mount outside `rootfs`. `ENOSYS` is returned on kernels
+/// older than 5.6, where the caller should fall back to `mount`.
+/// For more information, see the [open_tree(2)](https://man7.org/linux/man-pages/man2/open_tree.2.html)
+/// and [move_mount(2)](https://man7.org/linux/man-pages/man2/move_mount.2.html) man pages.
+pub fn bind_mount_tree(
+    rootfs: &Path,
+    destination: &Path,
+    source: &Path,
+    recursive: bool,
+) -> nix::Result<()> {
+    let rootfs = open_path(None, rootfs, 0)?;
+    let destination = open_path(
+        Some(rootfs.as_raw_fd()),
+        destination,
+        libc::RESOLVE_IN_ROOT | libc::RESOLVE_NO_MAGICLINKS,
+    )?;
+
+    let mut flags = OPEN_TREE_CLONE | libc::O_CLOEXEC as libc::c_uint;
+    if recursive {
+        flags |= libc::AT_RECURSIVE as libc::c_uint;
+    }
+    let tree = open_tree(source, flags)?;
+    move_mount(tree.as_fd(), destination.as_fd())
+}
+
+/// `set_readonly` makes the mount at `path` read-only with `mount_setattr`, together with
+/// every mount beneath it if `recursive` is `true`. Unlike remounting with `MS_RDONLY`,
+/// which only affects a single mount, the submounts of a recursive bind mount are also
+/// made read-only. `ENOSYS` is returned on kernels older than 5.12.
+/// For more information, see the [mount_setattr(2)](https://man7.org/linux/man-pages/man2/mount_setattr.2.html)
+/// man page.
+pub fn set_readonly(path: &Path, recursive: bool) -> nix::Result<()> {
+    let path = path_to_cstring(path)?;
+    let attr = MountAttr {
+        attr_set: MOUNT_ATTR_RDONLY,
+        attr_clr: 0,
+        propagation: 0,
+        userns_fd: 0,
+    };
+    let flags = if recursive { libc::AT_RECURSIVE } else { 0 };
+
+    let result = unsafe {
+        libc::syscall(
+            libc::SYS_mount_setattr,
+            libc::AT_FDCWD,
+            path.as_ptr(),
+            flags as libc::c_uint,
+            &attr as *const MountAttr,
+            mem::size_of::<MountAttr>(),
+        )
+    };
+    Errno::result(result)?;
+    Ok(())
+}
+
+/// `open_path` opens `path` as an `O_PATH` file descriptor with `openat2`, where `dirfd`
+/// is the directory that relative paths are resolved against, which is the
+/// current working directory if `dirfd` is `None`. `resolve` is a set of `RESOLVE_*` flags.
+/// For more information, see the [openat2(2)](https://man7.org/linux/man-pages/man2/openat2.2.html)
+/// man page.
+fn open_path(dirfd: Option<RawFd>, path: &Path, resolve: u64) -> nix::Result<OwnedFd> {
+    let path = path_to_cstring(path)?;
+    let mut how: libc::open_how = unsafe { mem::zeroed() };
+    how.flags = (libc::O_PATH | libc::O_CLOEXEC) as u64;
+    how.resolve = resolve;
+
+    let fd = unsafe {
+        libc::syscall(
+            libc::SYS_openat2,
+            dirfd.unwrap_or(libc::AT_FDCWD),
+            path.as_ptr(),
+            &how as *const libc::open_how,
+            mem::size_of::<libc::open_how>(),
+        )
+    };
+    let fd = Errno::result(fd)?;
+    Ok(unsafe { OwnedFd::from_raw_fd(fd as RawFd) })
+}
+
+/// `open_tree` returns a file descriptor of the mount tree at `path`, which is a detached clone
+/// of the mount tree if `flags` contains `OPEN_TREE_CLONE`.
+fn open_tree(path: &Path, flags: libc::c_uint) -> nix::Result<OwnedFd> {
+    let path = path_to_cstring(path)?;
+    let fd = unsafe { libc::syscall(libc::SYS_open_tree, libc::AT_FDCWD, path.as_ptr(), flags) };
+    let fd = Errno::result(fd)?;
+    Ok(unsafe { OwnedFd::from_raw_fd(fd as RawFd) })
+}
+
+/// `move_mount` attaches the mount tree referred to by `from` to the location referred to by `to`.
+fn move_mount(from: BorrowedFd, to: BorrowedFd) -> nix::Result<()> {
+    let empty_path = c"";
+    let result = unsafe {
+        libc::syscall(
+            libc::SYS_move_mount,
+            from.as_raw_fd(),
+            empty_path.as_ptr(),
+            to.as_raw_fd(),
+            empty_path.as_ptr(),
+            MOVE_MOUNT_F_EMPTY_PATH | MOVE_MOUNT_T_EMPTY_PATH,
+        )
+    };
+    Errno::result(result)?;
+    Ok(())
+}
+
+fn path_to_cstring(path: &Path) -> nix::Result<CString> {
+    CString::new(path.as_os_str().as_bytes()).map_err(|_| Errno::EINVAL)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        os::unix::fs::{symlink, MetadataExt},
+    };
+
+    use nix::{
+        mount::{self, MsFlags},
+        sys::stat,
+    };
+
+    use super::*;
+    use crate::test_util;
+
+    #[test]
+    fn path_to_cstring_rejects_nul() {
+        assert_eq!(
+            path_to_cstring(Path::new("/tmp/a\0b")).unwrap_err(),
+            Errno::EINVAL
+        );
+        assert_eq!(
+            path_to_cstring(Path::new("/tmp")).unwrap().as_bytes(),
+            b"/tmp"
+        );
+    }
+
+    #[test]
+    fn open_path_resolves_in_root() {
+        let rootfs = test_util::test_directory("mount-api", "resolve");
+        fs::create_dir_all(rootfs.join("etc")).unwrap();
+        symlink("/", rootfs.join("escape")).unwrap();
+
+        let root = open_path(None, &rootfs, 0).unwrap();
+        let etc = open_path(
+            Some(root.as_raw_fd()),
+            Path::new("/escape/../escape/etc"),
+            libc::RESOLVE_IN_ROOT,
+        )
+        .unwrap();
+        let etc_stat = stat::fstat(etc.as_raw_fd()).unwrap();
+        assert_eq!(
+            etc_stat.st_ino,
+            fs::metadata(rootfs.join("etc")).unwrap().ino()
+        );
+    }
+
+    #[test]
+    fn bind_mount_tree_with_submounts() {
+        if !test_util::is_root() {
+            return;
+        }
+
+        let source = test_util::test_directory("mount-api", "tree-source");
+        fs::create_dir_all(source.join("sub")).unwrap();
+        let rootfs = test_util::test_directory("mount-api", "tree");
+        fs::create_dir_all(rootfs.join("recursive")).unwrap();
+        fs::create_dir_all(rootfs.join("single")).unwrap();
+        test_util::run_in_mount_namespace(|| {
+            mount::mount(
+                Some("tmpfs"),
+                &source.join("sub"),
+                Some("tmpfs"),
+                MsFlags::empty(),
+                None::<&str>,
+            )
+            .unwrap();
+            fs::write(source.join("sub/file"), "file").unwrap();
+
+            bind_mount_tree(&rootfs, Path::new("/recursive"), &source, true).unwrap();
+            bind_mount_tree(&rootfs, Path::new("/single"), &source, false).unwrap();
+            assert!(rootfs.join("recursive/sub/file").exists());
+            assert!(!rootfs.join("single/sub/file").exists());
+
+            // Both mounts are made read-only, together with the submount of the recursive one
+            set_readonly(&rootfs.join("recursive"), true).unwrap();
+            set_readonly(&rootfs.join("single"), false).unwrap();
+            for path in ["recursive/new", "recursive/sub/new", "single/new"] {
+                let error = fs::write(rootfs.join(path), "new").unwrap_err();
+                assert_eq!(error.raw_os_error(), Some(Errno::EROFS as i32), "{}", path);
+            }
+            fs::write(source.join("sub/new"), "new").unwrap();
+        });
+    }
+
+    #[test]
+    fn bind_mount_tree_stays_in_rootfs() {
+        if !test_util::is_root() {
+            return;
+        }
+
+        let source = test_util::test_directory("mount-api", "escape-source");
+        fs::write(source.join("file"), "file").unwrap();
+        let rootfs = test_util::test_directory("mount-api", "escape");
+        let outside = test_util::test_directory("mount-api", "escape-outside");
+        symlink(&outside, rootfs.join("link")).unwrap();
+        fs::create_dir_all(rootfs.join(outside.strip_prefix("/").unwrap())).unwrap();
+        test_util::run_in_mount_namespace(|| {
+            // The symbolic link to the absolute path is resolved inside `rootfs`
+            bind_mount_tree(&rootfs, Path::new("/link"), &source, false).unwrap();
+            assert!(!outside.join("file").exists());
+            assert!(rootfs
+                .join(outside.strip_prefix("/").unwrap())
+                .join("file")
+                .exists());
+        });
+    }
+}
@@ -1,13 +1,17 @@
-use std::os::fd::BorrowedFd;
+use std::{collections::HashMap, fs, os::fd::BorrowedFd, path::Path};
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use nix::{
     fcntl::{self, OFlag},
     sched,
     sched::CloneFlags,
     sys::stat::Mode,
+    unistd::{self, Pid},
 };
-use oci_spec::runtime::{LinuxNamespace, LinuxNamespaceType};
+use oci_spec::runtime::{LinuxIdMapping, LinuxNamespace, LinuxNamespaceType};
+
+/// The clone flag of the time namespace, which is not defined in [CloneFlags].
+pub const CLONE_NEWTIME: CloneFlags = CloneFlags::from_bits_retain(libc::CLONE_NEWTIME);
 
 /// `set_namespace` moves the container process into namespaces associated with different paths.
 /// For more information, see the [setns(2)](https://man7.org/linux/man-pages/man2/setns.2.html)
@@ -43,6 +47,148 @@ pub fn linux_namespace_to_clone_flags(namespace: &LinuxNamespace) -> CloneFlags
         LinuxNamespaceType::User => CloneFlags::CLONE_NEWUSER,
         LinuxNamespaceType::Pid => CloneFlags::CLONE_NEWPID,
         LinuxNamespaceType::Network => CloneFlags::CLONE_NEWNET,
-        LinuxNamespaceType::Time => CloneFlags::empty(),
+        LinuxNamespaceType::Time => CLONE_NEWTIME,
+    }
+}
+
+/// `set_time_namespace` creates a new time namespace if `namespace_list` contains
+/// a time namespace without a path. The time namespace can't be created with `clone`,
+/// so the container process creates it with `unshare`, which only applies to its children,
+/// and enters it when it invokes `execvp`.
+/// For more information, see the [time_namespaces(7)](https://man7.org/linux/man-pages/man7/time_namespaces.7.html)
+/// man page.
+pub fn set_time_namespace(namespace_list: &[LinuxNamespace]) -> Result<()> {
+    if new_time_namespace(namespace_list) {
+        sched::unshare(CLONE_NEWTIME).context("failed to create a new time namespace")?;
+    }
+    Ok(())
+}
+
+/// `set_cgroup_namespace` creates a new cgroup namespace if `namespace_list` contains a cgroup
+/// namespace without a path. The root of a cgroup namespace is the cgroup of the process that
+/// creates it, so it's created after the runtime moves the container process into the container
+/// cgroup. Otherwise, the root would be the cgroup of the runtime, where `/proc/self/cgroup` shows
+/// the path of the container cgroup on the host, and a nested container runtime that manages
+/// cgroups relative to it would create them outside of the container cgroup.
+/// For more information, see the [cgroup_namespaces(7)](https://man7.org/linux/man-pages/man7/cgroup_namespaces.7.html)
+/// man page.
+pub fn set_cgroup_namespace(namespace_list: &[LinuxNamespace]) -> Result<()> {
+    let new_cgroup_namespace = namespace_list.iter().any(|namespace| {
+        namespace.typ() == LinuxNamespaceType::Cgroup && namespace.path().is_none()
+    });
+    if new_cgroup_namespace {
+        sched::unshare(CloneFlags::CLONE_NEWCGROUP)
+            .context("failed to create a new cgroup namespace")?;
+    }
+    Ok(())
+}
+
+/// `write_time_offsets` writes `time_offsets` to `/proc/<pid>/timens_offsets`, which offsets
+/// the monotonic and boot-time clocks in the time namespace created by the process `pid`.
+/// The offsets must be written before any process enters the namespace, so the runtime writes them
+/// after the container process creates the namespace and before it invokes `execvp`.
+/// Each offset is in the form of `<secs> <nanosecs>`, such as `{"monotonic": "86400 0"}`,
+/// and `<nanosecs>` defaults to `0`.
+pub fn write_time_offsets(
+    pid: Pid,
+    namespace_list: &[LinuxNamespace],
+    time_offsets: &HashMap<String, String>,
+) -> Result<()> {
+    if time_offsets.is_empty() {
+        return Ok(());
+    }
+    if !new_time_namespace(namespace_list) {
+        bail!("the time offsets require a new time namespace");
+    }
+
+    let mut offset_list = Vec::new();
+    for (clock, offset) in time_offsets {
+        if clock != "monotonic" && clock != "boottime" {
+            bail!("the time offset of clock {} is not supported", clock);
+        }
+        let offset = match offset.split_whitespace().collect::<Vec<&str>>()[..] {
+            [secs] => format!("{} 0", secs),
+            [secs, nanosecs] => format!("{} {}", secs, nanosecs),
+            _ => bail!("the time offset {} of clock {} is invalid", offset, clock),
+        };
+        offset_list.push(format!("{} {}", clock, offset));
+    }
+
+    let path = Path::new("/proc")
+        .join(pid.to_string())
+        .join("timens_offsets");
+    let offsets = offset_list.join("\n");
+    fs::write(&path, &offsets).context(format!(
+        "failed to write the time offsets {} to {}",
+        offsets,
+        path.display()
+    ))?;
+    Ok(())
+}
+
+/// `new_time_namespace` checks if `namespace_list` contains a time namespace without a path.
+fn new_time_namespace(namespace_list: &[LinuxNamespace]) -> bool {
+    namespace_list
+        .iter()
+        .any(|namespace| namespace.typ() == LinuxNamespaceType::Time && namespace.path().is_none())
+}
+
+/// `write_id_mappings` writes the uid and gid mappings of the user namespace created by
+/// the process `pid` to `/proc/<pid>/uid_map` and `/proc/<pid>/gid_map`, where each mapping
+/// is in the form of `<container_id> <host_id> <size>`. If the runtime is unprivileged,
+/// `setgroups` is denied in the user namespace first, since the kernel rejects the gid mappings
+/// of an unprivileged process otherwise.
+/// The mappings must be written before the process uses its privileges in the user namespace,
+/// so the container process waits for the runtime to write them, which is described in
+/// [fork_container](crate::container::fork::fork_container).
+/// For more information, see the [user_namespaces(7)](https://man7.org/linux/man-pages/man7/user_namespaces.7.html)
+/// man page.
+pub fn write_id_mappings(
+    pid: Pid,
+    uid_mappings: &[LinuxIdMapping],
+    gid_mappings: &[LinuxIdMapping],
+) -> Result<()> {
+    let proc_path = Path::new("/proc").join(pid.to_string());
+    let id_map = |mappings: &[LinuxIdMapping]| {
+        mappings
+            .iter()
+            .map(|mapping| {
+                format!(
+                    "{} {} {}",
+                    mapping.container_id(),
+                    mapping.host_id(),
+                    mapping.size()
+                )
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    };
+
+    if !uid_mappings.is_empty() {
+        let path = proc_path.join("uid_map");
+        fs::write(&path, id_map(uid_mappings)).context(format!(
+            "failed to write the uid mappings to {}",
+            path.display()
+        ))?;
+    }
+    if !gid_mappings.is_empty() {
+        if !unistd::geteuid().is_root() {
+            let path = proc_path.join("setgroups");
+            fs::write(&path, "deny")
+                .context(format!("failed to write deny to {}", path.display()))?;
+        }
+        let path = proc_path.join("gid_map");
+        fs::write(&path, id_map(gid_mappings)).context(format!(
+            "failed to write the gid mappings to {}",
+            path.display()
+        ))?;
     }
+    Ok(())
+}
+
+/// `new_user_namespace` checks if `namespace_list` contains a user namespace without a path.
+pub fn new_user_namespace(namespace_list: &[LinuxNamespace]) -> bool {
+    namespace_list
+        .iter()
+        .any(|namespace| namespace.typ() == LinuxNamespaceType::User && namespace.path().is_none())
 }
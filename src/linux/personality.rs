@@ -0,0 +1,48 @@
+use anyhow::{bail, Context, Result};
+use nix::errno::Errno;
+use oci_spec::runtime::{LinuxPersonality, LinuxPersonalityDomain};
+
+/// The execution domains defined in `<linux/personality.h>`.
+const PER_LINUX: libc::c_ulong = 0x0000;
+const PER_LINUX32: libc::c_ulong = 0x0008;
+
+/// The personality flags that could be combined with the execution domain,
+/// where each entry contains the flag name and its value.
+const PERSONALITY_FLAG_LIST: [(&str, libc::c_ulong); 11] = [
+    ("UNAME26", 0x0020000),
+    ("ADDR_NO_RANDOMIZE", 0x0040000),
+    ("FDPIC_FUNCPTRS", 0x0080000),
+    ("MMAP_PAGE_ZERO", 0x0100000),
+    ("ADDR_COMPAT_LAYOUT", 0x0200000),
+    ("READ_IMPLIES_EXEC", 0x0400000),
+    ("ADDR_LIMIT_32BIT", 0x0800000),
+    ("SHORT_INODE", 0x1000000),
+    ("WHOLE_SECONDS", 0x2000000),
+    ("STICKY_TIMEOUTS", 0x4000000),
+    ("ADDR_LIMIT_3GB", 0x8000000),
+];
+
+/// `set_personality` sets the execution domain and the flags of the container process,
+/// such as `LINUX32`, which makes `uname` report a 32-bit architecture for legacy workloads.
+/// For more information, see the [personality(2)](https://man7.org/linux/man-pages/man2/personality.2.html)
+/// man page.
+pub fn set_personality(personality: &LinuxPersonality) -> Result<()> {
+    let mut persona = match personality.domain() {
+        LinuxPersonalityDomain::PerLinux => PER_LINUX,
+        LinuxPersonalityDomain::PerLinux32 => PER_LINUX32,
+    };
+
+    for flag in personality.flags().as_deref().unwrap_or_default() {
+        match PERSONALITY_FLAG_LIST.iter().find(|(name, _)| name == flag) {
+            Some((_, value)) => persona |= value,
+            None => bail!("the personality flag {} is not supported", flag),
+        }
+    }
+
+    let result = unsafe { libc::personality(persona) };
+    Errno::result(result).context(format!(
+        "failed to set the personality to {}",
+        personality.domain()
+    ))?;
+    Ok(())
+}
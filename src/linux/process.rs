@@ -1,12 +1,28 @@
-use anyhow::{Context, Result};
+use std::{
+    fs::{self, OpenOptions},
+    os::{
+        fd::{AsFd, AsRawFd, FromRawFd, OwnedFd, RawFd},
+        unix::fs::{FileTypeExt, MetadataExt},
+    },
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
 use nix::{
+    errno::Errno,
+    fcntl::{self, FcntlArg, FdFlag, OFlag},
+    poll::{self, PollFd, PollFlags, PollTimeout},
     sched::{self, CloneFlags},
-    unistd::Pid,
+    sys::{prctl, stat::Mode},
+    unistd::{self, Pid},
 };
 use oci_spec::runtime::LinuxNamespace;
 use procfs::process::{ProcState, Process};
+use serde::Serialize;
 
-use crate::linux::namespace;
+use crate::linux::{cgroup, namespace};
 
 /// `clone_child` creates a child process that invokes `function` in seperated
 /// Linux namespaces specified in `namespace_list`.
@@ -23,15 +39,84 @@ pub fn clone_child(
         .iter()
         .map(namespace::linux_namespace_to_clone_flags)
         .reduce(|flag_1, flag_2| flag_1 | flag_2)
-        .unwrap_or(CloneFlags::empty());
+        .unwrap_or(CloneFlags::empty())
+        // `CLONE_NEWTIME` overlaps with the exit signal in the flags of `clone`,
+        // so the time namespace is created with `unshare` in the container process
+        .difference(namespace::CLONE_NEWTIME)
+        // The cgroup namespace is created with `unshare` in the container process after
+        // the runtime moves it into the container cgroup, which becomes the root of the namespace
+        .difference(CloneFlags::CLONE_NEWCGROUP);
 
-    unsafe {
-        let pid = sched::clone(Box::new(child_fn), &mut stack, clone_flags, None)
-            .context("failed to clone the container process")?;
-        Ok(pid)
+    let pid = unsafe { sched::clone(Box::new(child_fn), &mut stack, clone_flags, None) };
+    pid.map_err(|errno| {
+        let flag_list: Vec<&str> = clone_flags.iter_names().map(|(name, _)| name).collect();
+        let flag_list = if flag_list.is_empty() {
+            String::from("no flags")
+        } else {
+            flag_list.join(" | ")
+        };
+        match clone_error_hint(errno, clone_flags) {
+            Some(hint) => anyhow!(errno).context(format!(
+                "failed to clone the container process with {} ({})",
+                flag_list, hint
+            )),
+            None => anyhow!(errno).context(format!(
+                "failed to clone the container process with {}",
+                flag_list
+            )),
+        }
+    })
+}
+
+/// `clone_error_hint` explains the common causes of `errno` returned by `clone`
+/// with `clone_flags`, which are usually caused by the restrictions of the host.
+fn clone_error_hint(errno: Errno, clone_flags: CloneFlags) -> Option<&'static str> {
+    match errno {
+        Errno::EPERM if clone_flags.contains(CloneFlags::CLONE_NEWUSER) => Some(
+            "CLONE_NEWUSER requires either privilege or unprivileged user namespaces being enabled",
+        ),
+        Errno::EPERM if !clone_flags.is_empty() => {
+            Some("creating namespaces requires CAP_SYS_ADMIN")
+        }
+        Errno::EPERM => Some("clone might be denied by the seccomp filter of the runtime"),
+        Errno::ENOSPC | Errno::EUSERS => {
+            Some("the limit of namespaces in /proc/sys/user/max_*_namespaces is reached")
+        }
+        Errno::EINVAL if !clone_flags.is_empty() => {
+            Some("a namespace might not be supported by the kernel")
+        }
+        Errno::ENOMEM | Errno::EAGAIN => {
+            Some("the runtime might run out of memory or reach the limit of processes")
+        }
+        _ => None,
     }
 }
 
+/// `setup_subreaper` marks the current process as a child subreaper, so that its orphaned
+/// descendants are reparented to it instead of the init process, where they can be reaped
+/// with `waitpid`. The attribute is kept across `execve`, so it should be set in a long-lived
+/// process, such as the container process, since it has no effect once the process exits.
+/// For more information, see the [PR_SET_CHILD_SUBREAPER(2const)](https://man7.org/linux/man-pages/man2/PR_SET_CHILD_SUBREAPER.2const.html)
+/// man page.
+pub fn setup_subreaper() -> Result<()> {
+    prctl::set_child_subreaper(true).context("failed to set PR_SET_CHILD_SUBREAPER to true")?;
+    Ok(())
+}
+
+/// `set_nice` sets the nice value of the current process to `nice`, which ranges from `-20`
+/// (the highest priority) to `19` (the lowest priority). Lowering the nice value requires
+/// `CAP_SYS_NICE`, so it should be set before the process drops its privileges.
+/// For more information, see the [setpriority(2)](https://man7.org/linux/man-pages/man2/setpriority.2.html)
+/// man page.
+pub fn set_nice(nice: i32) -> Result<()> {
+    if !(-20..=19).contains(&nice) {
+        bail!("the nice value {} is out of range [-20, 19]", nice);
+    }
+    let result = unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, nice) };
+    Errno::result(result).context(format!("failed to set the nice value to {}", nice))?;
+    Ok(())
+}
+
 /// `inspect_process` inspects the status of the process in `/proc/<pid>/stat`
 /// and returns a variant of the [ProcState] enum that represents the process status.
 pub fn inspect_process(pid: i32) -> Result<ProcState> {
@@ -44,3 +129,354 @@ pub fn inspect_process(pid: i32) -> Result<ProcState> {
         .context(format!("failed to inspect the state of process {}", pid))?;
     Ok(state)
 }
+
+/// `detach_terminal` moves the container process into a new session without a controlling terminal,
+/// so that it doesn't receive signals meant for the terminal session of the `reno` CLI.
+/// The standard streams that are attached to a terminal are redirected to `/dev/null`,
+/// while the streams that were explicitly redirected by the caller are kept.
+/// For more information, see the [setsid(2)](https://man7.org/linux/man-pages/man2/setsid.2.html)
+/// man page.
+pub fn detach_terminal() -> Result<()> {
+    unistd::setsid().context("failed to create a new session")?;
+
+    let dev_null = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/null")
+        .context("failed to open /dev/null")?;
+    for fd in 0..=2 {
+        if unistd::isatty(fd).unwrap_or(false) {
+            unistd::dup2(dev_null.as_raw_fd(), fd).context(format!(
+                "failed to redirect file descriptor {} to /dev/null",
+                fd
+            ))?;
+        }
+    }
+    Ok(())
+}
+
+/// `daemonize` moves the monitor process of the `reno` CLI into a new session, and redirects
+/// all of its standard streams to `/dev/null`, so that the caller that captures the output
+/// of the `reno` CLI, such as a pipe, doesn't wait for the monitor process to exit.
+/// For more information, see the [daemon(7)](https://man7.org/linux/man-pages/man7/daemon.7.html)
+/// man page.
+pub fn daemonize() -> Result<()> {
+    unistd::setsid().context("failed to create a new session")?;
+
+    let dev_null = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/null")
+        .context("failed to open /dev/null")?;
+    for fd in 0..=2 {
+        unistd::dup2(dev_null.as_raw_fd(), fd).context(format!(
+            "failed to redirect file descriptor {} to /dev/null",
+            fd
+        ))?;
+    }
+    Ok(())
+}
+
+/// `create_fifo` creates the named pipe at `path` if it doesn't exist.
+/// For more information, see the [mkfifo(3)](https://man7.org/linux/man-pages/man3/mkfifo.3.html)
+/// man page.
+pub fn create_fifo(path: &Path) -> Result<()> {
+    match fs::metadata(path) {
+        Ok(metadata) if metadata.file_type().is_fifo() => Ok(()),
+        Ok(_) => bail!("{} is not a named pipe", path.display()),
+        Err(_) => unistd::mkfifo(path, Mode::from_bits_truncate(0o600)).context(format!(
+            "failed to create the named pipe {}",
+            path.display()
+        )),
+    }
+}
+
+/// `preserve_fds` clears the close-on-exec flag of the `count` file descriptors after the stderr,
+/// which are `3` to `3 + count - 1`, so that they are inherited by the container process.
+/// For more information, see the [fcntl(2)](https://man7.org/linux/man-pages/man2/fcntl.2.html)
+/// man page.
+pub fn preserve_fds(count: u32) -> Result<()> {
+    for fd in 3..3 + count as RawFd {
+        fcntl::fcntl(fd, FcntlArg::F_SETFD(FdFlag::empty()))
+            .context(format!("failed to preserve the file descriptor {}", fd))?;
+    }
+    Ok(())
+}
+
+/// `redirect_output_to_fifo` redirects the stdout and stderr to the named pipe at `path`,
+/// which streams the output of the container to the reader of the named pipe, such as
+/// a log collector. The named pipe is opened without blocking, which fails with `ENXIO` if
+/// no reader is connected, where the open is retried until a reader connects or it times out.
+/// For more information, see the [fifo(7)](https://man7.org/linux/man-pages/man7/fifo.7.html)
+/// man page.
+pub fn redirect_output_to_fifo(path: &Path) -> Result<()> {
+    const RETRY_INTERVAL: Duration = Duration::from_millis(100);
+    const RETRY_LIMIT: u32 = 100;
+
+    let mut retry = 0;
+    let fifo = loop {
+        match fcntl::open(
+            path,
+            OFlag::O_WRONLY | OFlag::O_NONBLOCK | OFlag::O_CLOEXEC,
+            Mode::empty(),
+        ) {
+            Err(Errno::ENXIO) if retry < RETRY_LIMIT => {
+                retry += 1;
+                thread::sleep(RETRY_INTERVAL);
+            }
+            Err(Errno::ENXIO) => bail!(
+                "no reader is connected to the named pipe {}",
+                path.display()
+            ),
+            result => {
+                let fd =
+                    result.context(format!("failed to open the named pipe {}", path.display()))?;
+                break unsafe { OwnedFd::from_raw_fd(fd) };
+            }
+        }
+    };
+
+    // The writes are blocking once the reader is connected, which doesn't drop the output
+    // if the reader is slower than the container
+    fcntl::fcntl(fifo.as_raw_fd(), FcntlArg::F_SETFL(OFlag::O_WRONLY)).context(format!(
+        "failed to configure the named pipe {}",
+        path.display()
+    ))?;
+    for fd in 1..=2 {
+        unistd::dup2(fifo.as_raw_fd(), fd).context(format!(
+            "failed to redirect file descriptor {} to {}",
+            fd,
+            path.display()
+        ))?;
+    }
+    Ok(())
+}
+
+/// `NamespaceProcess` is a process of the container.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NamespaceProcess {
+    /// `pid` is the PID of the process in the PID namespace of the runtime
+    pub pid: i32,
+    /// `namespace_pid` is the PID of the process in the PID namespace of the container
+    pub namespace_pid: i32,
+    pub comm: String,
+    pub cmdline: Vec<String>,
+}
+
+/// `list_container_processes` lists the processes of the container whose container process
+/// is `pid`. If the container has its own PID namespace, they're the processes in the namespace.
+/// Otherwise, they're the processes in the cgroups of `pid` or their descendants, which requires
+/// `container_cgroup` to be `true`, since the container would share the cgroups of the runtime.
+pub fn list_container_processes(pid: i32, container_cgroup: bool) -> Result<Vec<NamespaceProcess>> {
+    let namespace = pid_namespace(pid).context(format!(
+        "failed to inspect the PID namespace of process {}",
+        pid
+    ))?;
+    let runtime_namespace = pid_namespace(unistd::getpid().as_raw())
+        .context("failed to inspect the PID namespace of the runtime")?;
+    if namespace != runtime_namespace {
+        return list_processes(|process_pid| pid_namespace(process_pid).ok() == Some(namespace));
+    }
+
+    if !container_cgroup {
+        bail!("the container shares the PID namespace and the cgroups of the runtime, so its processes can't be told apart from the other processes");
+    }
+    let cgroup_path_list = cgroup::process_cgroup_paths(Pid::from_raw(pid))?;
+    list_processes(|process_pid| {
+        cgroup::process_cgroup_paths(Pid::from_raw(process_pid)).is_ok_and(
+            |process_cgroup_path_list| within_cgroups(&process_cgroup_path_list, &cgroup_path_list),
+        )
+    })
+}
+
+/// `within_cgroups` checks if the cgroups of a process in `process_cgroup_path_list` are
+/// in the cgroups of `cgroup_path_list` or their descendants in every hierarchy.
+fn within_cgroups(process_cgroup_path_list: &[PathBuf], cgroup_path_list: &[PathBuf]) -> bool {
+    !cgroup_path_list.is_empty()
+        && cgroup_path_list.iter().all(|cgroup_path| {
+            process_cgroup_path_list
+                .iter()
+                .any(|process_cgroup_path| process_cgroup_path.starts_with(cgroup_path))
+        })
+}
+
+/// `list_processes` lists the processes whose PID is selected by `filter`.
+/// Every process in the container is visible in `/proc`, since the PID namespace of the runtime
+/// is the same as or an ancestor of the PID namespace of the container.
+/// For more information, see the [pid_namespaces(7)](https://man7.org/linux/man-pages/man7/pid_namespaces.7.html)
+/// man page.
+fn list_processes(filter: impl Fn(i32) -> bool) -> Result<Vec<NamespaceProcess>> {
+    let mut process_list = Vec::new();
+    for process in procfs::process::all_processes().context("failed to list the processes")? {
+        // Processes that exit while they are inspected are skipped
+        let Ok(process) = process else {
+            continue;
+        };
+        if !filter(process.pid()) {
+            continue;
+        }
+        let (Ok(stat), Ok(status), Ok(cmdline)) =
+            (process.stat(), process.status(), process.cmdline())
+        else {
+            continue;
+        };
+
+        process_list.push(NamespaceProcess {
+            pid: process.pid(),
+            namespace_pid: status
+                .nspid
+                .and_then(|nspid| nspid.last().copied())
+                .unwrap_or(process.pid()),
+            comm: stat.comm,
+            cmdline,
+        });
+    }
+    Ok(process_list)
+}
+
+/// `pid_namespace` returns the device and inode number of the PID namespace of the process `pid`,
+/// which identify the namespace.
+fn pid_namespace(pid: i32) -> Result<(u64, u64)> {
+    let metadata = fs::metadata(format!("/proc/{}/ns/pid", pid))?;
+    Ok((metadata.dev(), metadata.ino()))
+}
+
+/// `open_pidfd` returns a file descriptor that refers to the process `pid`,
+/// which becomes readable once the process exits.
+/// For more information, see the [pidfd_open(2)](https://man7.org/linux/man-pages/man2/pidfd_open.2.html)
+/// man page.
+pub fn open_pidfd(pid: Pid) -> Result<OwnedFd> {
+    let pidfd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid.as_raw(), 0) };
+    let pidfd = Errno::result(pidfd).context(format!(
+        "failed to open the file descriptor of process {}",
+        pid
+    ))?;
+    Ok(unsafe { OwnedFd::from_raw_fd(pidfd as RawFd) })
+}
+
+/// `wait_process_exit` blocks until the process `pid` exits. Unlike `waitpid`, it works for
+/// processes that are not children of the caller, such as the container process after
+/// the `reno` CLI that created it has exited. The process is referred to by a file descriptor
+/// returned by [open_pidfd].
+pub fn wait_process_exit(pid: Pid) -> Result<()> {
+    let pidfd = open_pidfd(pid)?;
+    let mut poll_fd_list = [PollFd::new(pidfd.as_fd(), PollFlags::POLLIN)];
+    loop {
+        match poll::poll(&mut poll_fd_list, PollTimeout::NONE) {
+            Err(Errno::EINTR) => continue,
+            result => {
+                result.context(format!("failed to wait process {} to exit", pid))?;
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use nix::sys::signal::{self, SigHandler, Signal};
+
+    use super::*;
+    use crate::test_util;
+
+    #[test]
+    fn set_nice_in_range() {
+        assert!(set_nice(-21).is_err());
+        assert!(set_nice(20).is_err());
+
+        // Raising the nice value doesn't require privileges
+        test_util::run_in_child(|| {
+            set_nice(10).unwrap();
+            let nice = unsafe { libc::getpriority(libc::PRIO_PROCESS, 0) };
+            assert_eq!(nice, 10);
+        });
+    }
+
+    #[test]
+    fn detach_terminal_without_controlling_terminal() {
+        test_util::run_in_child(|| {
+            // The child process becomes a session leader with a pseudoterminal
+            // as its controlling terminal and its stdin and stdout
+            unistd::setsid().unwrap();
+            let master = unsafe { libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY) };
+            let master = unsafe { OwnedFd::from_raw_fd(Errno::result(master).unwrap()) };
+            Errno::result(unsafe { libc::grantpt(master.as_raw_fd()) }).unwrap();
+            Errno::result(unsafe { libc::unlockpt(master.as_raw_fd()) }).unwrap();
+            let mut slave_path = [0; 64];
+            Errno::result(unsafe {
+                libc::ptsname_r(
+                    master.as_raw_fd(),
+                    slave_path.as_mut_ptr(),
+                    slave_path.len(),
+                )
+            })
+            .unwrap();
+            let slave_path = unsafe { std::ffi::CStr::from_ptr(slave_path.as_ptr()) };
+            let slave = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(slave_path.to_str().unwrap())
+                .unwrap();
+            Errno::result(unsafe { libc::ioctl(slave.as_raw_fd(), libc::TIOCSCTTY, 0) }).unwrap();
+            for fd in 0..=1 {
+                unistd::dup2(slave.as_raw_fd(), fd).unwrap();
+            }
+            assert!(File::open("/dev/tty").is_ok());
+
+            // Closing the master hangs up the controlling terminal once the test returns
+            unsafe { signal::signal(Signal::SIGHUP, SigHandler::SigIgn) }.unwrap();
+
+            // A session leader can't create a new session, so the grandchild detaches instead
+            test_util::run_in_child(|| {
+                detach_terminal().unwrap();
+                let error = File::open("/dev/tty").unwrap_err();
+                assert_eq!(error.raw_os_error(), Some(Errno::ENXIO as i32));
+                for fd in 0..=1 {
+                    assert!(!unistd::isatty(fd).unwrap());
+                }
+            });
+        });
+    }
+
+    #[test]
+    fn within_cgroups_in_every_hierarchy() {
+        let cgroup_path_list = [
+            PathBuf::from("/sys/fs/cgroup/memory/reno/test"),
+            PathBuf::from("/sys/fs/cgroup/systemd/user.slice"),
+        ];
+        let process_cgroup_path_list = [
+            PathBuf::from("/sys/fs/cgroup/memory/reno/test/child"),
+            PathBuf::from("/sys/fs/cgroup/systemd/user.slice"),
+        ];
+        assert!(within_cgroups(&process_cgroup_path_list, &cgroup_path_list));
+
+        // A process in the same cgroup of a hierarchy isn't in the container if it's outside
+        // the container cgroup in another hierarchy
+        let process_cgroup_path_list = [
+            PathBuf::from("/sys/fs/cgroup/memory/reno/test-other"),
+            PathBuf::from("/sys/fs/cgroup/systemd/user.slice"),
+        ];
+        assert!(!within_cgroups(
+            &process_cgroup_path_list,
+            &cgroup_path_list
+        ));
+        assert!(!within_cgroups(&process_cgroup_path_list, &[]));
+    }
+
+    #[test]
+    fn list_container_processes_without_pid_namespace() {
+        let pid = unistd::getpid().as_raw();
+        assert!(list_container_processes(pid, false).is_err());
+
+        // The test shares the cgroups with its process, which is in the list
+        let process_list = list_container_processes(pid, true).unwrap();
+        let process = process_list
+            .iter()
+            .find(|process| process.pid == pid)
+            .unwrap();
+        assert_eq!(process.namespace_pid, pid);
+    }
+}
@@ -1,20 +1,92 @@
-use anyhow::{Context, Result};
-use nix::sys::resource::{setrlimit, Resource};
+use anyhow::{bail, Context, Result};
+use caps::{CapSet, Capability};
+use log::warn;
+use nix::sys::resource::{getrlimit, setrlimit, Resource};
 use oci_spec::runtime::{PosixRlimit, PosixRlimitType};
 
-/// `set_rlimit` sets a soft and hard limit for each resource.
+/// `set_rlimits` sets a soft and hard limit for each resource.
 /// The soft limit is the value that the kernel enforces for the resource.
 /// The hard limit is a maximum value for the soft limit.
 /// For example, `RLIMIT_CPU` limits the amount of CPU time the container process could consume.
+/// Each resource limit is validated and applied independently, and every failure
+/// is reported together rather than stopping at the first one.
+/// If `clamp` is `true`, a hard limit that exceeds the hard limit of the runtime
+/// is lowered to the hard limit of the runtime with a warning.
 /// For more information, see the [setrlimit(2)](https://man7.org/linux/man-pages/man2/setrlimit.2.html)
 /// man page.
-pub fn set_rlimit(rlimit: &PosixRlimit) -> Result<()> {
+pub fn set_rlimits(rlimits: &[PosixRlimit], clamp: bool) -> Result<()> {
+    let error_list: Vec<String> = rlimits
+        .iter()
+        .filter_map(|rlimit| set_rlimit(rlimit, clamp).err())
+        .map(|err| format!("{:#}", err))
+        .collect();
+
+    if !error_list.is_empty() {
+        bail!("failed to set resource limits:\n{}", error_list.join("\n"));
+    }
+    Ok(())
+}
+
+/// `set_rlimit` validates and sets the soft and hard limit of a single resource.
+fn set_rlimit(rlimit: &PosixRlimit, clamp: bool) -> Result<()> {
     let resource = posix_rlimit_type_to_resource(&rlimit.typ());
-    setrlimit(resource, rlimit.soft(), rlimit.hard())
+
+    // Raising the hard limit above the hard limit of the runtime requires `CAP_SYS_RESOURCE`,
+    // which is usually missing when the runtime is invoked by an unprivileged user
+    let (_, current_hard) = getrlimit(resource).context(format!(
+        "failed to read the resource limit for {}",
+        rlimit.typ()
+    ))?;
+    let has_sys_resource = caps::has_cap(None, CapSet::Effective, Capability::CAP_SYS_RESOURCE)
+        .context("failed to read the effective capabilities")?;
+    let (soft, hard) = resolve_rlimit(rlimit, current_hard, has_sys_resource, clamp)?;
+
+    setrlimit(resource, soft, hard)
         .context(format!("failed to set resource limit for {}", rlimit.typ()))?;
     Ok(())
 }
 
+/// `resolve_rlimit` returns the soft and hard limit that should be set for `rlimit`,
+/// given the hard limit `current_hard` of the runtime and whether it has `CAP_SYS_RESOURCE`.
+fn resolve_rlimit(
+    rlimit: &PosixRlimit,
+    current_hard: u64,
+    has_sys_resource: bool,
+    clamp: bool,
+) -> Result<(u64, u64)> {
+    let (soft, hard) = (rlimit.soft(), rlimit.hard());
+    if soft > hard {
+        bail!(
+            "the soft limit {} of {} exceeds its hard limit {}",
+            soft,
+            rlimit.typ(),
+            hard
+        );
+    }
+
+    if hard > current_hard && !has_sys_resource {
+        if !clamp {
+            bail!(
+                "the hard limit {} of {} exceeds the hard limit {} of the runtime without CAP_SYS_RESOURCE, \
+                lower the hard limit to at most {} or enable clamping with --clamp-rlimits",
+                hard,
+                rlimit.typ(),
+                current_hard,
+                current_hard
+            );
+        }
+
+        warn!(
+            "clamping the hard limit {} of {} to the hard limit {} of the runtime",
+            hard,
+            rlimit.typ(),
+            current_hard
+        );
+        return Ok((soft.min(current_hard), current_hard));
+    }
+    Ok((soft, hard))
+}
+
 /// `posix_rlimit_type_to_resource` converts [PosixRlimitType] to [Resource].
 fn posix_rlimit_type_to_resource(rlimit: &PosixRlimitType) -> Resource {
     match rlimit {
@@ -36,3 +108,60 @@ fn posix_rlimit_type_to_resource(rlimit: &PosixRlimitType) -> Resource {
         PosixRlimitType::RlimitRttime => Resource::RLIMIT_RTTIME,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use oci_spec::runtime::PosixRlimitBuilder;
+
+    use super::*;
+
+    fn test_rlimit(soft: u64, hard: u64) -> PosixRlimit {
+        PosixRlimitBuilder::default()
+            .typ(PosixRlimitType::RlimitNofile)
+            .soft(soft)
+            .hard(hard)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn resolve_rlimit_soft_exceeds_hard() {
+        let error = resolve_rlimit(&test_rlimit(2048, 1024), 4096, true, true).unwrap_err();
+        assert!(error.to_string().contains("exceeds its hard limit"));
+    }
+
+    #[test]
+    fn resolve_rlimit_within_runtime_limit() {
+        let rlimit = test_rlimit(1024, 2048);
+        assert_eq!(
+            resolve_rlimit(&rlimit, 4096, false, false).unwrap(),
+            (1024, 2048)
+        );
+        assert_eq!(
+            resolve_rlimit(&rlimit, 1024, true, false).unwrap(),
+            (1024, 2048)
+        );
+    }
+
+    #[test]
+    fn resolve_rlimit_clamp() {
+        let rlimit = test_rlimit(2048, 4096);
+        assert!(resolve_rlimit(&rlimit, 1024, false, false).is_err());
+        assert_eq!(
+            resolve_rlimit(&rlimit, 1024, false, true).unwrap(),
+            (1024, 1024)
+        );
+        assert_eq!(
+            resolve_rlimit(&test_rlimit(512, 4096), 1024, false, true).unwrap(),
+            (512, 1024)
+        );
+    }
+
+    #[test]
+    fn set_rlimits_reports_every_error() {
+        let error = set_rlimits(&[test_rlimit(2, 1), test_rlimit(4, 3)], false).unwrap_err();
+        let message = error.to_string();
+        assert!(message.contains("soft limit 2"));
+        assert!(message.contains("soft limit 4"));
+    }
+}
@@ -0,0 +1,269 @@
+#[cfg(feature = "seccomp")]
+use std::os::fd::{FromRawFd, RawFd};
+use std::{
+    io::IoSlice,
+    os::{
+        fd::{AsRawFd, BorrowedFd, OwnedFd},
+        unix::net::UnixStream,
+    },
+    path::Path,
+};
+
+use anyhow::{bail, Context, Result};
+use nix::sys::socket::{self, ControlMessage, MsgFlags};
+use oci_spec::runtime::Spec;
+use serde::Serialize;
+
+use crate::state::{State, OCI_VERSION};
+
+#[cfg(feature = "seccomp")]
+use libseccomp::{
+    ScmpAction, ScmpArch, ScmpArgCompare, ScmpCompareOp, ScmpFilterContext, ScmpSyscall,
+};
+#[cfg(feature = "seccomp")]
+use oci_spec::runtime::{
+    LinuxSeccomp, LinuxSeccompAction, LinuxSeccompArg, LinuxSeccompFilterFlag, LinuxSeccompOperator,
+};
+
+/// The errno that is returned by `SCMP_ACT_ERRNO` if `errnoRet` is not specified.
+#[cfg(feature = "seccomp")]
+const DEFAULT_ERRNO_RET: u32 = libc::EPERM as u32;
+
+/// `apply_seccomp` loads the seccomp filter defined in `linux.seccomp`, which restricts
+/// the system calls that the container process could invoke. The filter is loaded
+/// right before the container process is started, since the runtime invokes system calls
+/// that the filter might deny. If a rule has the `SCMP_ACT_NOTIFY` action, the file descriptor
+/// of the notification listener is returned, which should be sent to `linux.seccomp.listenerPath`.
+/// For more information, see the [seccomp(2)](https://man7.org/linux/man-pages/man2/seccomp.2.html)
+/// man page.
+#[cfg(feature = "seccomp")]
+pub fn apply_seccomp(spec: &Spec) -> Result<Option<OwnedFd>> {
+    let seccomp = match spec
+        .linux()
+        .as_ref()
+        .and_then(|linux| linux.seccomp().as_ref())
+    {
+        Some(seccomp) => seccomp,
+        None => return Ok(None),
+    };
+
+    let notify = seccomp
+        .syscalls()
+        .as_deref()
+        .unwrap_or_default()
+        .iter()
+        .any(|syscall| syscall.action() == LinuxSeccompAction::ScmpActNotify);
+    if notify {
+        let fd = apply_seccomp_notify(seccomp)?;
+        return Ok(Some(unsafe { OwnedFd::from_raw_fd(fd) }));
+    }
+
+    seccomp_filter(seccomp)?
+        .load()
+        .context("failed to load the seccomp filter")?;
+    Ok(None)
+}
+
+/// `apply_seccomp` rejects the bundle configuration that defines `linux.seccomp`,
+/// since `reno` is compiled without the `seccomp` feature.
+#[cfg(not(feature = "seccomp"))]
+pub fn apply_seccomp(spec: &Spec) -> Result<Option<OwnedFd>> {
+    if spec
+        .linux()
+        .as_ref()
+        .and_then(|linux| linux.seccomp().as_ref())
+        .is_some()
+    {
+        bail!("linux.seccomp is defined, but reno is compiled without the seccomp feature");
+    }
+    Ok(None)
+}
+
+/// `apply_seccomp_notify` loads the seccomp filter where the system calls with the
+/// `SCMP_ACT_NOTIFY` action are intercepted, and returns the file descriptor of the
+/// notification listener. A supervisor process that receives the file descriptor handles
+/// the intercepted system calls with the `SECCOMP_IOCTL_NOTIF_RECV` and `SECCOMP_IOCTL_NOTIF_SEND`
+/// `ioctl` requests. The system calls that the container process invokes before the supervisor
+/// receives the file descriptor, such as `sendmsg` and `execve`, should not be intercepted.
+/// For more information, see the [seccomp_unotify(2)](https://man7.org/linux/man-pages/man2/seccomp_unotify.2.html)
+/// man page.
+#[cfg(feature = "seccomp")]
+pub fn apply_seccomp_notify(seccomp: &LinuxSeccomp) -> Result<RawFd> {
+    if seccomp.listener_path().is_none() {
+        bail!("the SCMP_ACT_NOTIFY action requires linux.seccomp.listenerPath");
+    }
+
+    let filter = seccomp_filter(seccomp)?;
+    filter.load().context("failed to load the seccomp filter")?;
+    let fd = filter
+        .get_notify_fd()
+        .context("failed to get the file descriptor of the seccomp notification listener")?;
+    Ok(fd)
+}
+
+/// `ContainerProcessState` is sent with the file descriptor of the seccomp notification
+/// listener to `linux.seccomp.listenerPath`, which is defined in the
+/// [runtime specification](https://github.com/opencontainers/runtime-spec/blob/main/config-linux.md#containerprocessstate).
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ContainerProcessState<'a> {
+    oci_version: &'a str,
+    fds: Vec<&'a str>,
+    pid: i32,
+    metadata: &'a str,
+    state: &'a State,
+}
+
+/// `send_seccomp_listener` connects to `listener_path` and sends the file descriptor
+/// of the seccomp notification listener `fd` along with the [ContainerProcessState].
+/// For more information, see the [unix(7)](https://man7.org/linux/man-pages/man7/unix.7.html)
+/// man page.
+pub fn send_seccomp_listener(
+    listener_path: &Path,
+    metadata: Option<&str>,
+    fd: BorrowedFd,
+    state: &State,
+) -> Result<()> {
+    let container_process_state = ContainerProcessState {
+        oci_version: OCI_VERSION,
+        fds: vec!["seccompFd"],
+        pid: state.pid,
+        metadata: metadata.unwrap_or_default(),
+        state,
+    };
+    let container_process_state = serde_json::to_string(&container_process_state)
+        .context("failed to serialize the container process state")?;
+
+    let stream = UnixStream::connect(listener_path).context(format!(
+        "failed to connect to the seccomp listener {}",
+        listener_path.display()
+    ))?;
+    let fd_list = [fd.as_raw_fd()];
+    socket::sendmsg::<()>(
+        stream.as_raw_fd(),
+        &[IoSlice::new(container_process_state.as_bytes())],
+        &[ControlMessage::ScmRights(&fd_list)],
+        MsgFlags::empty(),
+        None,
+    )
+    .context(format!(
+        "failed to send the seccomp file descriptor to {}",
+        listener_path.display()
+    ))?;
+    Ok(())
+}
+
+/// `seccomp_filter` converts [LinuxSeccomp] to a [ScmpFilterContext],
+/// which contains the default action, the architectures, the filter flags, and the rules.
+/// The default action returns `defaultErrnoRet` if it's `SCMP_ACT_ERRNO`, or `EPERM` if it's not specified.
+#[cfg(feature = "seccomp")]
+fn seccomp_filter(seccomp: &LinuxSeccomp) -> Result<ScmpFilterContext> {
+    let default_action = seccomp_action(seccomp.default_action(), seccomp.default_errno_ret());
+    let mut filter = ScmpFilterContext::new_filter(default_action)
+        .context("failed to initialize the seccomp filter")?;
+
+    if let Some(architectures) = seccomp.architectures() {
+        for architecture in architectures {
+            let scmp_arch: ScmpArch = architecture.to_string().parse().context(format!(
+                "the architecture {} is not supported",
+                architecture
+            ))?;
+            filter
+                .add_arch(scmp_arch)
+                .context(format!("failed to add the architecture {}", architecture))?;
+        }
+    }
+
+    if let Some(flags) = seccomp.flags() {
+        set_seccomp_flags(&mut filter, flags)?;
+    }
+
+    if let Some(syscalls) = seccomp.syscalls() {
+        for syscall in syscalls {
+            let action = seccomp_action(syscall.action(), syscall.errno_ret());
+            // The rules with the default action are rejected by `libseccomp`
+            if action == default_action {
+                continue;
+            }
+
+            for name in syscall.names() {
+                let scmp_syscall = match ScmpSyscall::from_name(name) {
+                    Ok(scmp_syscall) => scmp_syscall,
+                    // The system calls that don't exist on the architecture are skipped
+                    Err(_) => continue,
+                };
+
+                let comparator_list: Vec<ScmpArgCompare> = syscall
+                    .args()
+                    .as_deref()
+                    .unwrap_or_default()
+                    .iter()
+                    .map(seccomp_comparator)
+                    .collect();
+                filter
+                    .add_rule_conditional(action, scmp_syscall, &comparator_list)
+                    .context(format!("failed to add the seccomp rule for {}", name))?;
+            }
+        }
+    }
+    Ok(filter)
+}
+
+/// `set_seccomp_flags` applies `linux.seccomp.flags` to `filter`, which are passed to
+/// the `seccomp` system call when the filter is loaded. `SECCOMP_FILTER_FLAG_TSYNC` synchronizes
+/// the filter to every thread of the process, and `SECCOMP_FILTER_FLAG_LOG` logs the actions
+/// except `SCMP_ACT_ALLOW`. For more information, see the
+/// [seccomp(2)](https://man7.org/linux/man-pages/man2/seccomp.2.html) man page.
+#[cfg(feature = "seccomp")]
+fn set_seccomp_flags(
+    filter: &mut ScmpFilterContext,
+    flags: &[LinuxSeccompFilterFlag],
+) -> Result<()> {
+    for flag in flags {
+        match flag {
+            LinuxSeccompFilterFlag::SeccompFilterFlagLog => filter.set_ctl_log(true),
+            LinuxSeccompFilterFlag::SeccompFilterFlagTsync => filter.set_ctl_tsync(true),
+            LinuxSeccompFilterFlag::SeccompFilterFlagSpecAllow => filter.set_ctl_ssb(true),
+        }
+        .context(format!("failed to set the seccomp filter flag {}", flag))?;
+    }
+    Ok(())
+}
+
+/// `seccomp_action` converts [LinuxSeccompAction] to [ScmpAction].
+#[cfg(feature = "seccomp")]
+fn seccomp_action(action: LinuxSeccompAction, errno_ret: Option<u32>) -> ScmpAction {
+    match action {
+        LinuxSeccompAction::ScmpActKill => ScmpAction::KillThread,
+        LinuxSeccompAction::ScmpActKillProcess => ScmpAction::KillProcess,
+        LinuxSeccompAction::ScmpActTrap => ScmpAction::Trap,
+        LinuxSeccompAction::ScmpActErrno => {
+            ScmpAction::Errno(errno_ret.unwrap_or(DEFAULT_ERRNO_RET) as i32)
+        }
+        LinuxSeccompAction::ScmpActNotify => ScmpAction::Notify,
+        LinuxSeccompAction::ScmpActTrace => {
+            ScmpAction::Trace(errno_ret.unwrap_or(DEFAULT_ERRNO_RET) as u16)
+        }
+        LinuxSeccompAction::ScmpActLog => ScmpAction::Log,
+        LinuxSeccompAction::ScmpActAllow => ScmpAction::Allow,
+    }
+}
+
+/// `seccomp_comparator` converts [LinuxSeccompArg] to [ScmpArgCompare].
+#[cfg(feature = "seccomp")]
+fn seccomp_comparator(arg: &LinuxSeccompArg) -> ScmpArgCompare {
+    let op = match arg.op() {
+        LinuxSeccompOperator::ScmpCmpNe => ScmpCompareOp::NotEqual,
+        LinuxSeccompOperator::ScmpCmpLt => ScmpCompareOp::Less,
+        LinuxSeccompOperator::ScmpCmpLe => ScmpCompareOp::LessOrEqual,
+        LinuxSeccompOperator::ScmpCmpEq => ScmpCompareOp::Equal,
+        LinuxSeccompOperator::ScmpCmpGe => ScmpCompareOp::GreaterEqual,
+        LinuxSeccompOperator::ScmpCmpGt => ScmpCompareOp::Greater,
+        LinuxSeccompOperator::ScmpCmpMaskedEq => ScmpCompareOp::MaskedEqual(arg.value()),
+    };
+    let datum = match arg.op() {
+        LinuxSeccompOperator::ScmpCmpMaskedEq => arg.value_two().unwrap_or_default(),
+        _ => arg.value(),
+    };
+    ScmpArgCompare::new(arg.index() as u32, op, datum)
+}
@@ -1,6 +1,75 @@
-use std::{collections::HashMap, fs, path::Path};
+use std::{collections::HashMap, fs, io::ErrorKind, path::Path};
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
+use log::warn;
+use oci_spec::runtime::{LinuxNamespace, LinuxNamespaceType};
+
+/// The kernel parameters that are isolated by the IPC namespace, besides `fs.mqueue.*`.
+const IPC_SYSCTL_LIST: [&str; 8] = [
+    "kernel.msgmax",
+    "kernel.msgmnb",
+    "kernel.msgmni",
+    "kernel.sem",
+    "kernel.shmall",
+    "kernel.shmmax",
+    "kernel.shmmni",
+    "kernel.shm_rmid_forced",
+];
+
+/// The kernel parameters that are isolated by the UTS namespace.
+const UTS_SYSCTL_LIST: [&str; 2] = ["kernel.hostname", "kernel.domainname"];
+
+/// `validate_sysctl` checks that every kernel parameter in `kernel_parameter_map` is isolated by
+/// a namespace in `namespace_list`, since the other parameters are shared with the host, such as
+/// `kernel.kptr_restrict`, which would change the host from the container.
+/// `net.*` requires a network namespace, `kernel.hostname` and `kernel.domainname` require
+/// a UTS namespace, and the System V IPC parameters and `fs.mqueue.*` require an IPC namespace.
+/// If `unsafe_sysctl` is `true`, the other parameters are allowed with a warning.
+pub fn validate_sysctl(
+    kernel_parameter_map: &HashMap<String, String>,
+    namespace_list: &[LinuxNamespace],
+    unsafe_sysctl: bool,
+) -> Result<()> {
+    let has_namespace = |typ: LinuxNamespaceType| {
+        namespace_list
+            .iter()
+            .any(|namespace| namespace.typ() == typ)
+    };
+
+    for parameter in kernel_parameter_map.keys() {
+        // The parameter might be separated by `/`, such as `net/ipv4/ip_forward`
+        let parameter = parameter.replace('/', ".");
+        let namespace = if parameter.starts_with("net.") {
+            Some((LinuxNamespaceType::Network, "network"))
+        } else if UTS_SYSCTL_LIST.contains(&parameter.as_str()) {
+            Some((LinuxNamespaceType::Uts, "UTS"))
+        } else if IPC_SYSCTL_LIST.contains(&parameter.as_str())
+            || parameter.starts_with("fs.mqueue.")
+        {
+            Some((LinuxNamespaceType::Ipc, "IPC"))
+        } else {
+            None
+        };
+
+        match namespace {
+            Some((namespace, _)) if has_namespace(namespace) => (),
+            _ if unsafe_sysctl => warn!(
+                "setting the kernel parameter {}, which is shared with the host",
+                parameter
+            ),
+            Some((_, name)) => bail!(
+                "the kernel parameter {} requires the container to have its own {} namespace, since it's shared with the host otherwise",
+                parameter,
+                name
+            ),
+            None => bail!(
+                "the kernel parameter {} is not isolated by any namespace, which requires --unsafe-sysctl",
+                parameter
+            ),
+        }
+    }
+    Ok(())
+}
 
 /// `set_sysctl` modifies kernel parameters for the container.
 /// The parameters are listed under `/proc/sys/`, such as
@@ -22,9 +91,27 @@ pub fn set_sysctl(kernel_parameter_map: &HashMap<String, String>) -> Result<()>
 /// `set_oom_score_adj` sets the `oom_score_adj` for the container process.
 /// The `oom_score_adj` is an integer between `-1000` to `1000`.
 /// The lower the value, the lower the chance that it's going to be killed by the Out of Memory killer.
+/// Without `CAP_SYS_RESOURCE`, the write might fail with `EACCES`, which is only a warning
+/// if `oom_score_adj` is not lower than the current value, since the process is not
+/// more protected from the Out of Memory killer than requested.
 pub fn set_oom_score_adj(oom_score_adj: i32) -> Result<()> {
     let sysctl_path = Path::new("/proc/self/oom_score_adj");
-    fs::write(sysctl_path, oom_score_adj.to_string())
-        .context(format!("failed to set oom_score_adj to {}", oom_score_adj))?;
-    Ok(())
+    match fs::write(sysctl_path, oom_score_adj.to_string()) {
+        Err(err) if err.kind() == ErrorKind::PermissionDenied => {
+            let current = fs::read_to_string(sysctl_path)
+                .ok()
+                .and_then(|current| current.trim().parse::<i32>().ok());
+            match current {
+                Some(current) if oom_score_adj >= current => {
+                    warn!(
+                        "failed to set oom_score_adj to {}, which is kept at {}: {}",
+                        oom_score_adj, current, err
+                    );
+                    Ok(())
+                }
+                _ => Err(err).context(format!("failed to set oom_score_adj to {}", oom_score_adj)),
+            }
+        }
+        result => result.context(format!("failed to set oom_score_adj to {}", oom_score_adj)),
+    }
 }
@@ -1,26 +1,108 @@
-use clap::Parser;
+use clap::{error::ErrorKind, CommandFactory, Parser};
 
 mod cli;
 mod container;
+mod error;
+mod events;
+mod features;
+mod health;
 mod hook;
 mod linux;
 mod socket;
 mod state;
+#[cfg(test)]
+mod test_util;
+mod validation;
+
+use std::{path::PathBuf, process::exit};
 
 use anyhow::Result;
+use env_logger::Env;
 
-use crate::cli::{Cli, CliSubcommand};
+use crate::{
+    cli::{Cli, CliSubcommand},
+    container::ContainerOptions,
+};
 
 fn main() -> Result<()> {
-    match Cli::parse().command {
+    env_logger::Builder::from_env(Env::default().default_filter_or("warn")).init();
+
+    let args = Cli::parse();
+    let json_errors = args.json_errors;
+    match run(args) {
+        Err(error) if json_errors => {
+            cli::print_json_error(&error);
+            exit(1);
+        }
+        result => result,
+    }
+}
+
+fn run(args: Cli) -> Result<()> {
+    if args.version {
+        println!("{}", cli::build_version(args.short));
+        return Ok(());
+    }
+
+    let Some(command) = args.command else {
+        Cli::command()
+            .error(ErrorKind::MissingSubcommand, "a subcommand is required")
+            .exit();
+    };
+    match command {
         CliSubcommand::State { id } => cli::state(id),
         CliSubcommand::Create {
             id,
             bundle,
             pid_file,
-        } => cli::create(id, bundle, pid_file),
-        CliSubcommand::Start { id } => cli::start(id),
-        CliSubcommand::Kill { id, signal } => cli::kill(id, signal),
-        CliSubcommand::Delete { id, force } => cli::delete(id, force),
+            clamp_rlimits,
+            log_fifo,
+            no_pivot,
+            preserve_fds,
+            hostname,
+            add_default_mounts,
+            console_socket,
+            unsafe_sysctl,
+        } => cli::create(
+            id,
+            bundle,
+            pid_file,
+            ContainerOptions {
+                clamp_rlimits,
+                log_fifo: log_fifo.map(PathBuf::from),
+                no_pivot,
+                preserve_fds,
+                hostname,
+                add_default_mounts,
+                console_socket: console_socket.map(PathBuf::from),
+            },
+            unsafe_sysctl,
+            args.systemd_cgroup,
+        ),
+        CliSubcommand::Start {
+            id,
+            detach,
+            sync,
+            health_check,
+        } => cli::start(id, detach, sync, health_check),
+        CliSubcommand::Kill { id, signal, group } => cli::kill(id, signal, group),
+        CliSubcommand::Delete {
+            id,
+            force,
+            keep_state,
+        } => cli::delete(id, force, keep_state),
+        CliSubcommand::Update {
+            id,
+            memory,
+            cpu_quota,
+            cpu_shares,
+            pids_limit,
+            resources,
+        } => cli::update(id, memory, cpu_quota, cpu_shares, pids_limit, resources),
+        CliSubcommand::Ps { id, format } => cli::ps(id, format),
+        CliSubcommand::Events { id, follow } => cli::events(id, follow),
+        CliSubcommand::Features => cli::features(),
+        CliSubcommand::Lint { bundle } => cli::lint(bundle),
+        CliSubcommand::Version => cli::version(),
     }
 }
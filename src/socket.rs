@@ -1,25 +1,67 @@
 use std::{
     fs,
-    io::{BufRead, BufReader, Write},
+    io::{BufRead, BufReader, ErrorKind, IoSlice, IoSliceMut, Read, Write},
     net::Shutdown,
-    os::unix::net::{UnixListener, UnixStream},
+    os::{
+        fd::{AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd},
+        unix::net::{UnixListener, UnixStream},
+    },
     path::{Path, PathBuf},
+    time::Duration,
 };
 
 use anyhow::{bail, Context, Result};
+use nix::sys::{
+    socket::{self, ControlMessage, ControlMessageOwned, MsgFlags},
+    stat::{self, Mode},
+};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
 use crate::state::Status;
 
+/// The version of the protocol between the container process and the `reno` CLI,
+/// which is increased once the format of [SocketMessage] changes.
+pub const CURRENT_PROTOCOL_VERSION: u8 = 1;
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct SocketMessage {
+    /// The messages without a version are sent by the runtime before the protocol is versioned
+    #[serde(default)]
+    pub version: u8,
     pub status: Status,
     pub error: Option<String>,
 }
 
 impl SocketMessage {
     pub fn new(status: Status, error: Option<String>) -> Self {
-        SocketMessage { status, error }
+        SocketMessage {
+            version: CURRENT_PROTOCOL_VERSION,
+            status,
+            error,
+        }
+    }
+
+    /// `parse` deserializes the message from JSON, which returns an error if the message
+    /// is sent with a newer protocol than [CURRENT_PROTOCOL_VERSION].
+    fn parse(message: &[u8]) -> Result<Self> {
+        let message: Value =
+            serde_json::from_slice(message).context("failed to parse the client message")?;
+        let version = message
+            .get("version")
+            .and_then(Value::as_u64)
+            .unwrap_or_default();
+        if version > CURRENT_PROTOCOL_VERSION as u64 {
+            bail!(
+                "the message is sent with protocol version {}, while the runtime supports up to version {}",
+                version,
+                CURRENT_PROTOCOL_VERSION
+            );
+        }
+
+        let message: SocketMessage =
+            serde_json::from_value(message).context("failed to parse the client message")?;
+        Ok(message)
     }
 }
 
@@ -30,9 +72,14 @@ pub struct SocketServer {
 }
 
 impl SocketServer {
+    /// `bind` creates the socket at `path` with mode `0600`, so that other users can't connect
+    /// to it and send messages to the `reno` CLI or the container process.
     pub fn bind(path: &Path) -> Result<Self> {
-        let listener =
-            UnixListener::bind(path).context(format!("failed to bind to {}", path.display()))?;
+        // The mode of the socket is determined by the umask, which is restored after `bind`
+        let umask = stat::umask(Mode::from_bits_truncate(0o177));
+        let listener = UnixListener::bind(path);
+        stat::umask(umask);
+        let listener = listener.context(format!("failed to bind to {}", path.display()))?;
         Ok(SocketServer {
             path: path.to_path_buf(),
             listener,
@@ -64,6 +111,45 @@ impl SocketServer {
             None => bail!("failed to connect to a client"),
         }
     }
+
+    /// `wait_for_close` waits for the client to close the connection.
+    pub fn wait_for_close(&mut self) -> Result<()> {
+        match &mut self.stream {
+            Some(stream) => {
+                stream
+                    .read_to_end(&mut Vec::new())
+                    .context("failed to wait for the client to close the connection")?;
+                Ok(())
+            }
+            None => bail!("failed to connect to a client"),
+        }
+    }
+
+    /// `write_with_fd` sends the message along with the file descriptor `fd`,
+    /// which is duplicated into the process of the client with `SCM_RIGHTS`.
+    /// For more information, see the [unix(7)](https://man7.org/linux/man-pages/man7/unix.7.html)
+    /// man page.
+    pub fn write_with_fd(&mut self, message: SocketMessage, fd: BorrowedFd) -> Result<()> {
+        let mut message =
+            serde_json::to_string(&message).context("failed to serialize the client message")?;
+        message.push('\n');
+
+        match &self.stream {
+            Some(stream) => {
+                let fd_list = [fd.as_raw_fd()];
+                socket::sendmsg::<()>(
+                    stream.as_raw_fd(),
+                    &[IoSlice::new(message.as_bytes())],
+                    &[ControlMessage::ScmRights(&fd_list)],
+                    MsgFlags::empty(),
+                    None,
+                )
+                .context("failed to send the message to the client")?;
+                Ok(())
+            }
+            None => bail!("failed to connect to a client"),
+        }
+    }
 }
 
 impl Drop for SocketServer {
@@ -76,12 +162,17 @@ impl Drop for SocketServer {
 
 pub struct SocketClient {
     stream: UnixStream,
+    // The bytes that are received after the message returned by `read_with_fd`
+    pending: Vec<u8>,
 }
 
 impl SocketClient {
     pub fn connect(path: &Path) -> Result<Self> {
         let stream = UnixStream::connect(path).context("failed to connect to the server")?;
-        Ok(SocketClient { stream })
+        Ok(SocketClient {
+            stream,
+            pending: Vec::new(),
+        })
     }
 
     pub fn read(&mut self) -> Result<SocketMessage> {
@@ -91,9 +182,69 @@ impl SocketClient {
             .read_line(&mut buffer)
             .context("failed to read the message from the server")?;
 
-        let message: SocketMessage =
-            serde_json::from_str(&buffer).context("failed to parse the client message")?;
-        Ok(message)
+        SocketMessage::parse(buffer.as_bytes())
+    }
+
+    /// `read_with_fd` reads a message that might be sent along with a file descriptor
+    /// by [SocketServer::write_with_fd], and returns the message and the file descriptor.
+    pub fn read_with_fd(&mut self) -> Result<(SocketMessage, Option<OwnedFd>)> {
+        let mut buffer = vec![0; 4096];
+        let mut cmsg_buffer = nix::cmsg_space!(RawFd);
+        let mut iov = [IoSliceMut::new(&mut buffer)];
+        let message = socket::recvmsg::<()>(
+            self.stream.as_raw_fd(),
+            &mut iov,
+            Some(&mut cmsg_buffer),
+            MsgFlags::MSG_CMSG_CLOEXEC,
+        )
+        .context("failed to read the message from the server")?;
+
+        let mut fd = None;
+        for cmsg in message
+            .cmsgs()
+            .context("failed to read the control message from the server")?
+        {
+            if let ControlMessageOwned::ScmRights(fd_list) = cmsg {
+                fd = fd_list
+                    .first()
+                    .map(|fd| unsafe { OwnedFd::from_raw_fd(*fd) });
+            }
+        }
+        let length = message.bytes;
+
+        // The server might send another message right after this one,
+        // which is kept for `read_until_close`
+        let (message, pending) = match buffer[..length].iter().position(|byte| *byte == b'\n') {
+            Some(position) => buffer[..length].split_at(position + 1),
+            None => (&buffer[..length], &[][..]),
+        };
+        self.pending = pending.to_vec();
+
+        let message = SocketMessage::parse(message)?;
+        Ok((message, fd))
+    }
+
+    /// `read_until_close` waits for the server to close the connection, and returns the message
+    /// that the server sent before it closes the connection, or `None` if there's no message.
+    /// If `timeout` is not `None`, it stops waiting after `timeout` and returns the message
+    /// received so far, if any.
+    pub fn read_until_close(&mut self, timeout: Option<Duration>) -> Result<Option<SocketMessage>> {
+        let mut buffer = std::mem::take(&mut self.pending);
+        self.stream
+            .set_read_timeout(timeout)
+            .context("failed to set the read timeout of the connection")?;
+        match self.stream.read_to_end(&mut buffer) {
+            Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => (),
+            result => {
+                result.context("failed to read the message from the server")?;
+            }
+        }
+
+        let message = String::from_utf8_lossy(&buffer);
+        match message.lines().find(|line| !line.trim().is_empty()) {
+            Some(line) => Ok(Some(SocketMessage::parse(line.as_bytes())?)),
+            None => Ok(None),
+        }
     }
 
     pub fn shutdown(&self) -> Result<()> {
@@ -103,3 +254,50 @@ impl SocketClient {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{env, os::unix::fs::PermissionsExt, thread};
+
+    use super::*;
+    use crate::test_util::UMASK_LOCK;
+
+    #[test]
+    fn bind_socket_with_owner_only_mode() {
+        let path = env::temp_dir().join(format!("reno-socket-{}.sock", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let _lock = UMASK_LOCK.lock().unwrap();
+        let umask = stat::umask(Mode::from_bits_truncate(0o022));
+        let server = SocketServer::bind(&path).unwrap();
+        assert_eq!(stat::umask(umask).bits(), 0o022);
+
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        drop(server);
+    }
+
+    #[test]
+    fn write_and_read_message() {
+        let path = env::temp_dir().join(format!("reno-socket-message-{}.sock", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut server = {
+            let _lock = UMASK_LOCK.lock().unwrap();
+            SocketServer::bind(&path).unwrap()
+        };
+
+        let client_path = path.clone();
+        let client = thread::spawn(move || {
+            let mut client = SocketClient::connect(&client_path).unwrap();
+            client.read().unwrap()
+        });
+        server.listen().unwrap();
+        server
+            .write(SocketMessage::new(Status::Created, None))
+            .unwrap();
+
+        let message = client.join().unwrap();
+        assert_eq!(message.status, Status::Created);
+        assert!(message.error.is_none());
+    }
+}
@@ -1,17 +1,18 @@
 use std::{
     collections::HashMap,
     fs::{self, File},
-    io::Write,
+    io::{ErrorKind, Write},
     path::{Path, PathBuf},
 };
 
 use anyhow::{Context, Result};
+use nix::unistd;
 use procfs::process::ProcState;
 use serde::{Deserialize, Serialize};
 
-use crate::linux::process::inspect_process;
+use crate::{error::ContainerError, events::EventLog, linux::process::inspect_process};
 
-const OCI_VERSION: &str = "1.0.2";
+pub const OCI_VERSION: &str = "1.0.2";
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -32,6 +33,14 @@ pub struct State {
     pub status: Status,
     pub pid: i32,
     pub annotations: Option<HashMap<String, String>>,
+    /// The user that created the container, which is `0` for the containers created
+    /// before the owner is recorded
+    #[serde(default)]
+    pub owner: u32,
+    /// Whether the container cgroup is managed by `systemd`, which is chosen by `create`
+    /// and used by the other commands that manage the cgroup
+    #[serde(default)]
+    pub systemd_cgroup: bool,
 }
 
 impl State {
@@ -43,24 +52,57 @@ impl State {
             status: Status::Creating,
             pid: -1,
             annotations: Some(HashMap::new()),
+            owner: unistd::getuid().as_raw(),
+            systemd_cgroup: false,
         }
     }
 
+    /// `check_owner` returns [ContainerError::PermissionDenied] if the caller is neither
+    /// the owner of the container nor root, which prevents users that share the runtime root
+    /// from operating on the containers of each other.
+    pub fn check_owner(&self) -> Result<()> {
+        self.check_caller(unistd::getuid().as_raw())
+    }
+
+    /// `check_caller` checks if the user `uid` is allowed to operate on the container.
+    fn check_caller(&self, uid: u32) -> Result<()> {
+        if uid != 0 && uid != self.owner {
+            return Err(ContainerError::PermissionDenied(self.id.clone(), self.owner).into());
+        }
+        Ok(())
+    }
+
     /// `load` reads the container state from `{container_path}/state.json`.
+    /// It returns [ContainerError::NotFound] if the file doesn't exist,
+    /// or [ContainerError::CorruptState] if the file can't be deserialized.
     pub fn load(container_path: &Path) -> Result<Self> {
+        let id = container_path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
         let state_file_path = &container_path.join("state.json");
-        let state_json = fs::read_to_string(state_file_path).context(format!(
-            "failed to read the container state from {}",
-            state_file_path.display()
-        ))?;
+        let state_json = match fs::read_to_string(state_file_path) {
+            Err(error) if error.kind() == ErrorKind::NotFound => {
+                return Err(ContainerError::NotFound(id).into())
+            }
+            result => result.context(format!(
+                "failed to read the container state from {}",
+                state_file_path.display()
+            ))?,
+        };
 
         let state: State = serde_json::from_str(&state_json)
-            .context("failed to deserialize the state from JSON".to_string())?;
+            .map_err(|error| ContainerError::CorruptState(id, error))?;
         Ok(state)
     }
 
     /// `persist` serializes the container state to JSON and writes it to `{container_path}/state.json`.
+    /// If the status is changed, an [Event](crate::events::Event) is appended to the [EventLog].
     pub fn persist(&self, container_path: &Path) -> Result<()> {
+        let status_changed = State::load(container_path)
+            .map(|state| state.status != self.status)
+            .unwrap_or(true);
         let state_json = serde_json::to_string(&self)
             .context("failed to serialize the state to JSON".to_string())?;
 
@@ -75,6 +117,10 @@ impl State {
                 "failed to write container state to {}",
                 state_file_path.display()
             ))?;
+
+        if status_changed {
+            EventLog::new(container_path).append(self)?;
+        }
         Ok(())
     }
 
@@ -114,3 +160,45 @@ impl State {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_state_without_systemd_cgroup() {
+        let state_json = r#"{"ociVersion":"1.0.2","id":"test","bundle":"/bundle","status":"running","pid":1,"annotations":{},"owner":1000}"#;
+        let state: State = serde_json::from_str(state_json).unwrap();
+        assert!(!state.systemd_cgroup);
+    }
+
+    #[test]
+    fn persist_systemd_cgroup() {
+        let mut state = State::new(String::from("test"), PathBuf::from("/bundle"));
+        state.systemd_cgroup = true;
+        let state_json = serde_json::to_string(&state).unwrap();
+        let state: State = serde_json::from_str(&state_json).unwrap();
+        assert!(state.systemd_cgroup);
+    }
+
+    #[test]
+    fn check_caller_owner_or_root() {
+        let mut state = State::new(String::from("test"), PathBuf::from("/bundle"));
+        state.owner = 1000;
+        assert!(state.check_caller(1000).is_ok());
+        assert!(state.check_caller(0).is_ok());
+        let error = state.check_caller(1001).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<ContainerError>(),
+            Some(ContainerError::PermissionDenied(_, 1000))
+        ));
+    }
+
+    #[test]
+    fn load_state_without_owner() {
+        let state_json = r#"{"ociVersion":"1.0.2","id":"test","bundle":"/bundle","status":"running","pid":1,"annotations":{}}"#;
+        let state: State = serde_json::from_str(state_json).unwrap();
+        assert_eq!(state.owner, 0);
+        assert!(state.check_caller(1000).is_err());
+    }
+}
@@ -0,0 +1,116 @@
+//! `test_util` contains the helpers shared by the tests of different modules.
+
+use std::{
+    any::Any,
+    env, fs,
+    ops::Deref,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use nix::{
+    mount::{self, MsFlags},
+    sched::{self, CloneFlags},
+    sys::wait::{self, WaitStatus},
+    unistd::{self, ForkResult},
+};
+
+/// `UMASK_LOCK` serializes the tests that change the umask, which is shared by every thread,
+/// including [crate::socket::SocketServer::bind], which changes it around `bind`.
+pub static UMASK_LOCK: Mutex<()> = Mutex::new(());
+
+/// `is_root` checks if the tests run as root. The tests that mount file systems or change
+/// the credentials of a process are skipped otherwise.
+pub fn is_root() -> bool {
+    unistd::geteuid().is_root()
+}
+
+/// `TestDirectory` is a temporary directory created by [test_directory],
+/// which is removed with its content once it's dropped.
+pub struct TestDirectory(PathBuf);
+
+impl Deref for TestDirectory {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TestDirectory {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TestDirectory {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// `test_directory` creates an empty directory `reno-<prefix>-<name>-<pid>` under the temporary
+/// directory. The pid is included since the tests of the library and the binary might run
+/// at the same time, and a directory left by a previous run is replaced.
+pub fn test_directory(prefix: &str, name: &str) -> TestDirectory {
+    let path = env::temp_dir().join(format!("reno-{}-{}-{}", prefix, name, std::process::id()));
+    let _ = fs::remove_dir_all(&path);
+    fs::create_dir_all(&path).unwrap();
+    TestDirectory(path)
+}
+
+/// `run_in_child` runs `f` in a forked child process, so that the changes to the process,
+/// such as its credentials or namespaces, don't affect the other tests. It panics if `f` panics
+/// in the child process, whose message is written to the stderr directly, since the output
+/// captured by the test harness in the child process is lost.
+pub fn run_in_child(f: impl FnOnce()) {
+    match unsafe { unistd::fork() }.unwrap() {
+        ForkResult::Parent { child } => {
+            let wait_status = wait::waitpid(child, None).unwrap();
+            assert_eq!(
+                wait_status,
+                WaitStatus::Exited(child, 0),
+                "the child process failed"
+            );
+        }
+        ForkResult::Child => {
+            let code = match panic::catch_unwind(AssertUnwindSafe(f)) {
+                Ok(()) => 0,
+                Err(payload) => {
+                    let message = format!("{}\n", panic_message(payload.as_ref()));
+                    let _ = unistd::write(std::io::stderr(), message.as_bytes());
+                    1
+                }
+            };
+            unsafe { libc::_exit(code) };
+        }
+    }
+}
+
+/// `run_in_mount_namespace` runs `f` in a child process with a new mount namespace,
+/// where every mount is private, so that the mounts of `f` are removed once it returns.
+pub fn run_in_mount_namespace(f: impl FnOnce()) {
+    run_in_child(|| {
+        sched::unshare(CloneFlags::CLONE_NEWNS).unwrap();
+        mount::mount(
+            None::<&str>,
+            "/",
+            None::<&str>,
+            MsFlags::MS_REC | MsFlags::MS_PRIVATE,
+            None::<&str>,
+        )
+        .unwrap();
+        f();
+    })
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "the child process panicked"
+    }
+}
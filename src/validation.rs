@@ -0,0 +1,153 @@
+use std::{fs, path::Path};
+
+use anyhow::{anyhow, bail, Context, Result};
+use jsonschema::{Draft, JSONSchema};
+use oci_spec::runtime::Spec;
+use serde_json::Value;
+
+use crate::{
+    container,
+    linux::{hostname, sysctl},
+};
+
+/// The JSON Schema of the bundle configuration defined in the
+/// [runtime specification](https://github.com/opencontainers/runtime-spec/tree/main/schema)
+const CONFIG_SCHEMA: &str = include_str!("../schema/config-schema.json");
+
+/// `validate_config_json` validates the bundle configuration at `path` against the JSON Schema
+/// of the runtime specification, and reports every field that doesn't match the schema with its path,
+/// such as `/process/user/uid: "0" is not of type "integer"`.
+pub fn validate_config_json(path: &Path) -> Result<()> {
+    let error_list = config_json_issues(path)?;
+    if !error_list.is_empty() {
+        bail!(
+            "the bundle configuration at {} is invalid:\n{}",
+            path.display(),
+            error_list.join("\n")
+        );
+    }
+    Ok(())
+}
+
+/// `config_json_issues` returns every field of the bundle configuration at `path` that doesn't
+/// match the JSON Schema of the runtime specification.
+fn config_json_issues(path: &Path) -> Result<Vec<String>> {
+    let config_json = fs::read_to_string(path).context(format!(
+        "failed to read the bundle configuration from {}",
+        path.display()
+    ))?;
+    let config: Value = serde_json::from_str(&config_json).context(format!(
+        "failed to parse the bundle configuration at {}",
+        path.display()
+    ))?;
+
+    let schema: Value =
+        serde_json::from_str(CONFIG_SCHEMA).context("failed to parse the configuration schema")?;
+    let schema = JSONSchema::options()
+        .with_draft(Draft::Draft7)
+        .compile(&schema)
+        .map_err(|err| anyhow!("failed to compile the configuration schema: {}", err))?;
+
+    let error_list = match schema.validate(&config) {
+        Ok(()) => Vec::new(),
+        Err(errors) => errors
+            .map(|error| format!("{}: {}", error.instance_path, error))
+            .collect(),
+    };
+    Ok(error_list)
+}
+
+/// `lint_bundle` validates the bundle at `bundle` without creating a container, and returns every
+/// issue that would fail `create`, which are the fields that don't match the JSON Schema,
+/// and the issues returned by [validate_spec].
+pub fn lint_bundle(bundle: &Path) -> Result<Vec<String>> {
+    let bundle_spec = bundle.join("config.json");
+    let issue_list = config_json_issues(&bundle_spec)?;
+    if !issue_list.is_empty() {
+        return Ok(issue_list);
+    }
+    let spec = Spec::load(&bundle_spec).context("failed to load the bundle configuration")?;
+    Ok(validate_spec(bundle, &spec, false))
+}
+
+/// `validate_spec` returns every issue of the bundle configuration `spec` that is checked before
+/// the container is created, which are the root file system that doesn't exist, the sources of
+/// the bind mounts that don't exist, the kernel parameters that are shared with the host
+/// unless `unsafe_sysctl` is `true`, and the invalid hostname.
+pub fn validate_spec(bundle: &Path, spec: &Spec, unsafe_sysctl: bool) -> Vec<String> {
+    let mut issue_list = Vec::new();
+    if let Err(error) = container::resolve_rootfs(bundle, spec) {
+        issue_list.push(format!("{:#}", error));
+    }
+
+    for mount in spec.mounts().as_deref().unwrap_or_default() {
+        let bind_mount = mount.typ().as_deref() == Some("bind")
+            || mount.options().as_ref().is_some_and(|options| {
+                options
+                    .iter()
+                    .any(|option| option == "bind" || option == "rbind")
+            });
+        if let (true, Some(source)) = (bind_mount, mount.source()) {
+            if !bundle.join(source).exists() {
+                issue_list.push(format!(
+                    "the source {} of the bind mount {} doesn't exist",
+                    source.display(),
+                    mount.destination().display()
+                ));
+            }
+        }
+    }
+
+    if let Some(linux) = spec.linux() {
+        if let Some(kernel_parameter_map) = linux.sysctl() {
+            let namespace_list = linux.namespaces().clone().unwrap_or_default();
+            if let Err(error) =
+                sysctl::validate_sysctl(kernel_parameter_map, &namespace_list, unsafe_sysctl)
+            {
+                issue_list.push(format!("{:#}", error));
+            }
+        }
+    }
+
+    if let Some(hostname) = spec.hostname() {
+        if let Err(error) = hostname::validate_hostname(hostname) {
+            issue_list.push(format!("{:#}", error));
+        }
+    }
+    issue_list
+}
+
+#[cfg(test)]
+mod tests {
+    use oci_spec::runtime::MountBuilder;
+
+    use super::*;
+    use crate::test_util;
+
+    #[test]
+    fn validate_spec_reports_every_issue() {
+        let bundle = test_util::test_directory("validation", "bundle");
+        fs::create_dir_all(bundle.join("rootfs")).unwrap();
+
+        let mut spec = Spec::default();
+        assert!(validate_spec(&bundle, &spec, false).is_empty());
+
+        let mut mount_list = spec.mounts().clone().unwrap_or_default();
+        mount_list.push(
+            MountBuilder::default()
+                .destination("/data")
+                .source("missing")
+                .options(vec![String::from("rbind")])
+                .build()
+                .unwrap(),
+        );
+        spec.set_mounts(Some(mount_list));
+        spec.set_hostname(Some(String::new()));
+        let issue_list = validate_spec(&bundle, &spec, false);
+        assert_eq!(issue_list.len(), 2);
+        assert!(issue_list[0].contains("missing"));
+
+        fs::remove_dir_all(&bundle).unwrap();
+        assert_eq!(validate_spec(&bundle, &spec, false).len(), 3);
+    }
+}